@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_commands_schedule_id;
+ALTER TABLE commands DROP COLUMN IF EXISTS schedule_id;
+ALTER TABLE schedules DROP COLUMN IF EXISTS last_fired_slot;
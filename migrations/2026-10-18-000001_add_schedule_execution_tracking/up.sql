@@ -0,0 +1,7 @@
+-- Últim slot executat de cada horari (evita disparar un slot dues vegades)
+ALTER TABLE schedules ADD COLUMN last_fired_slot INT;
+
+-- Horari que ha generat la comanda (NULL per comandes manuals)
+ALTER TABLE commands ADD COLUMN schedule_id UUID REFERENCES schedules(id) ON DELETE SET NULL;
+
+CREATE INDEX idx_commands_schedule_id ON commands(schedule_id);
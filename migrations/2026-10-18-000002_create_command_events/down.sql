@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_commands_updated_at;
+DROP TABLE IF EXISTS command_events;
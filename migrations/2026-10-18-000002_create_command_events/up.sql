@@ -0,0 +1,11 @@
+-- Historial de canvis d'estat de cada comanda
+CREATE TABLE command_events (
+    id UUID PRIMARY KEY,
+    command_id UUID NOT NULL REFERENCES commands(id) ON DELETE CASCADE,
+    status VARCHAR NOT NULL, -- 'queued', 'sent', 'acked', 'failed'
+    message TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_command_events_command_id ON command_events(command_id);
+CREATE INDEX idx_commands_updated_at ON commands(updated_at);
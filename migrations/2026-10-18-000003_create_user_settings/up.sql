@@ -0,0 +1,9 @@
+-- Preferències de cada usuari. Si no n'hi ha, s'apliquen els valors per defecte.
+CREATE TABLE user_settings (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    timezone VARCHAR NOT NULL DEFAULT 'Europe/Madrid', -- Nom IANA, p.ex. 'Atlantic/Canary'
+    language VARCHAR NOT NULL DEFAULT 'ca', -- 'ca', 'es', 'en'
+    currency_display VARCHAR NOT NULL DEFAULT 'euros', -- 'euros' (€/kWh) o 'cents' (c€/kWh)
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,6 @@
+UPDATE rules
+SET params_json = jsonb_set(params_json, '{min_run_block}', to_jsonb(CEIL((params_json->>'min_run_block')::numeric / 60)::int))
+WHERE params_json ? 'min_run_block' AND jsonb_typeof(params_json->'min_run_block') = 'number';
+
+ALTER TABLE day_prices DROP CONSTRAINT IF EXISTS day_prices_resolution_check;
+ALTER TABLE day_prices DROP COLUMN IF EXISTS resolution_minutes;
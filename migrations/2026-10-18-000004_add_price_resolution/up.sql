@@ -0,0 +1,8 @@
+-- Durada dels períodes de preu de cada dia: 60 (horaris) o 15 (quarts d'hora)
+ALTER TABLE day_prices ADD COLUMN resolution_minutes INTEGER NOT NULL DEFAULT 60;
+ALTER TABLE day_prices ADD CONSTRAINT day_prices_resolution_check CHECK (resolution_minutes IN (15, 60));
+
+-- El bloc mínim de les regles passa d'hores a minuts
+UPDATE rules
+SET params_json = jsonb_set(params_json, '{min_run_block}', to_jsonb((params_json->>'min_run_block')::int * 60))
+WHERE params_json ? 'min_run_block' AND jsonb_typeof(params_json->'min_run_block') = 'number';
@@ -0,0 +1,19 @@
+-- Subscripció de cada usuari als avisos d'hores barates/cares
+CREATE TABLE price_alerts (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    notify_cheap BOOLEAN NOT NULL DEFAULT TRUE,
+    notify_expensive BOOLEAN NOT NULL DEFAULT FALSE,
+    -- Classificació per percentils del dia, o per llindars absoluts (€/kWh) si s'indiquen
+    cheap_percentile INTEGER NOT NULL DEFAULT 25,
+    expensive_percentile INTEGER NOT NULL DEFAULT 75,
+    cheap_threshold NUMERIC(10, 6),
+    expensive_threshold NUMERIC(10, 6),
+    min_hours INTEGER NOT NULL DEFAULT 2, -- Durada mínima d'un tram per avisar
+    lead_minutes INTEGER NOT NULL DEFAULT 0, -- Antelació de l'avís respecte l'inici del tram
+    -- Inici de l'últim tram avisat, perquè cada tram s'avisi una sola vegada
+    last_cheap_alert TIMESTAMPTZ,
+    last_expensive_alert TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,6 @@
+DROP TABLE user_tariffs;
+
+DELETE FROM day_prices WHERE tariff <> 'pvpc_2_0td';
+ALTER TABLE day_prices DROP CONSTRAINT day_prices_date_timezone_tariff_key;
+ALTER TABLE day_prices ADD CONSTRAINT day_prices_date_timezone_key UNIQUE (date, timezone);
+ALTER TABLE day_prices DROP COLUMN tariff;
@@ -0,0 +1,14 @@
+-- Cada dia pot tenir diverses corbes de preus publicades (PVPC, mercat spot...)
+ALTER TABLE day_prices ADD COLUMN tariff VARCHAR NOT NULL DEFAULT 'pvpc_2_0td';
+ALTER TABLE day_prices DROP CONSTRAINT day_prices_date_timezone_key;
+ALTER TABLE day_prices ADD CONSTRAINT day_prices_date_timezone_tariff_key UNIQUE (date, timezone, tariff);
+
+-- Tarifa contractada per cada usuari; sense fila s'aplica el PVPC 2.0TD
+CREATE TABLE user_tariffs (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    kind VARCHAR NOT NULL DEFAULT 'pvpc_2_0td'
+        CHECK (kind IN ('pvpc_2_0td', 'three_period', 'omie_spot', 'custom')),
+    params_json JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1 @@
+DROP TABLE one_shot_tasks;
@@ -0,0 +1,20 @@
+-- Tasques puntuals: engegar un aparell una sola vegada abans d'una hora límit
+CREATE TABLE one_shot_tasks (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_id UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    duration_minutes INTEGER NOT NULL,
+    not_before TIMESTAMPTZ NOT NULL, -- Inici més d'hora permès
+    deadline TIMESTAMPTZ NOT NULL,   -- El cicle ha d'haver acabat abans d'aquest instant
+    status VARCHAR NOT NULL DEFAULT 'waiting'
+        CHECK (status IN ('waiting', 'scheduled', 'started', 'completed', 'expired', 'cancelled')),
+    -- Bloc triat amb els preus disponibles (es pot replanificar fins que comença)
+    planned_start TIMESTAMPTZ,
+    planned_cost NUMERIC(10, 6),
+    command_id UUID REFERENCES commands(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_one_shot_tasks_user ON one_shot_tasks(user_id);
+CREATE INDEX idx_one_shot_tasks_status ON one_shot_tasks(status);
@@ -0,0 +1,2 @@
+-- Dies en què s'aplica cada regla: dies de la setmana, vigència i excepcions. Buit = cada dia.
+ALTER TABLE rules ADD COLUMN applicability_json JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,90 @@
+use crate::{
+    middleware::auth::AuthUser,
+    models::price::{PriceAlert, UpdatePriceAlertRequest},
+    schema::price_alerts,
+    services::price_alerts as alerts,
+    AppState,
+};
+use actix_web::{web, HttpResponse};
+use diesel::prelude::*;
+use serde_json::json;
+
+// Obtenir la subscripció als avisos de preu. Sense subscripció es retorna la
+// configuració per defecte, desactivada.
+pub async fn get_alerts(
+    AuthUser(user_id): AuthUser,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pool = &data.db_pool;
+    let conn = pool.get().await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
+
+    let subscription = conn
+        .interact(move |conn| alerts::load_subscription(conn, user_id))
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database query failed"))?
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get price alerts"))?;
+
+    let subscription = subscription.unwrap_or_else(|| PriceAlert {
+        enabled: false,
+        ..PriceAlert::defaults(user_id)
+    });
+
+    Ok(HttpResponse::Ok().json(subscription))
+}
+
+// Crear o modificar la subscripció als avisos de preu
+pub async fn update_alerts(
+    AuthUser(user_id): AuthUser,
+    payload: web::Json<UpdatePriceAlertRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pool = &data.db_pool;
+    let conn = pool.get().await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
+
+    let current = conn
+        .interact(move |conn| alerts::load_subscription(conn, user_id))
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database query failed"))?
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get price alerts"))?
+        .unwrap_or_else(|| PriceAlert::defaults(user_id));
+
+    let updated = current.apply(payload.into_inner())?;
+
+    let saved = conn
+        .interact(move |conn| alerts::save_subscription(conn, &updated))
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database update failed"))?
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to save price alerts"))?;
+
+    log::info!("User {} updated price alerts (enabled: {})", user_id, saved.enabled);
+
+    Ok(HttpResponse::Ok().json(saved))
+}
+
+// Cancel·lar la subscripció als avisos de preu
+pub async fn delete_alerts(
+    AuthUser(user_id): AuthUser,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pool = &data.db_pool;
+    let conn = pool.get().await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
+
+    let deleted = conn
+        .interact(move |conn| diesel::delete(price_alerts::table.find(user_id)).execute(conn))
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database operation failed"))?
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to delete price alerts"))?;
+
+    if deleted == 0 {
+        return Err(actix_web::error::ErrorNotFound("Price alerts not found"));
+    }
+
+    log::info!("User {} deleted price alerts", user_id);
+
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Price alerts deleted successfully"
+    })))
+}
@@ -372,7 +372,6 @@ pub fn create_jwt(user: &User, secret: &str) -> Result<String, actix_web::Error>
 }
 
 // Verificar JWT token
-#[allow(dead_code)] // S'utilitzarà en el middleware d'autenticació
 pub fn verify_jwt(token: &str, secret: &str) -> Result<Claims, actix_web::Error> {
     decode::<Claims>(
         token,
@@ -0,0 +1,32 @@
+use crate::{
+    middleware::auth::AuthUser,
+    services::command_processor::{self, CommandError},
+    AppState,
+};
+use actix_web::{web, HttpResponse};
+use serde_json::json;
+use uuid::Uuid;
+
+// Obtenir una comanda amb el seu historial d'estats
+pub async fn get_command(
+    command_id: web::Path<Uuid>,
+    AuthUser(user_id): AuthUser,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let command_id = command_id.into_inner();
+
+    let (command, history) = command_processor::get_command_history(&data.db_pool, user_id, command_id)
+        .await
+        .map_err(|e| match e {
+            CommandError::NotFound => actix_web::error::ErrorNotFound("Command not found"),
+            e => {
+                log::error!("Failed to get command {}: {}", command_id, e);
+                actix_web::error::ErrorInternalServerError("Failed to get command")
+            }
+        })?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "command": command,
+        "history": history
+    })))
+}
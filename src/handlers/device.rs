@@ -1,21 +1,20 @@
 use crate::{
+    middleware::auth::AuthUser,
     models::{command::*, device::*},
-    schema::{commands, device_states, devices},
+    schema::{device_states, devices},
+    services::command_processor,
     AppState, DbPool,
 };
 use actix_web::{web, HttpResponse};
-use chrono::Utc;
 use diesel::prelude::*;
 use uuid::Uuid;
 
 // Llistar tots els dispositius de l'usuari
 pub async fn list_devices(
-    // TODO: Add authentication
+    AuthUser(user_id): AuthUser,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let pool = &data.db_pool;
-    // TODO: Get user_id from authentication
-    let user_id = Uuid::new_v4(); // Temporary for testing
     
     let conn = pool.get().await.map_err(|e| {
         log::error!("Failed to get DB connection: {:?}", e);
@@ -45,12 +44,10 @@ pub async fn list_devices(
 // Obtenir informació d'un dispositiu específic
 pub async fn get_device(
     device_id: web::Path<Uuid>,
-    // TODO: Add authentication
+    AuthUser(user_id): AuthUser,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let pool = &data.db_pool;
-    // TODO: Get user_id from authentication
-    let user_id = Uuid::new_v4(); // Temporary for testing
     let device_id = device_id.into_inner();
     
     let conn = pool.get().await.map_err(|e| {
@@ -78,12 +75,10 @@ pub async fn get_device(
 // Obtenir l'estat actual d'un dispositiu
 pub async fn get_device_state(
     device_id: web::Path<Uuid>,
-    // TODO: Add authentication
+    AuthUser(user_id): AuthUser,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let pool = &data.db_pool;
-    // TODO: Get user_id from authentication
-    let user_id = Uuid::new_v4(); // Temporary for testing
     let device_id = device_id.into_inner();
     
     let conn = pool.get().await.map_err(|e| {
@@ -120,16 +115,24 @@ pub async fn get_device_state(
 pub async fn send_command(
     device_id: web::Path<Uuid>,
     web::Json(req): web::Json<CreateCommandRequest>,
-    // TODO: Add authentication
+    AuthUser(user_id): AuthUser,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let pool = &data.db_pool;
-    // TODO: Get user_id from authentication
-    let user_id = Uuid::new_v4(); // Temporary for testing
     let device_id = device_id.into_inner();
     
+    if req.device_id != device_id {
+        return Err(actix_web::error::ErrorBadRequest("device_id does not match the URL"));
+    }
+    
     // Verificar que el dispositiu pertany a l'usuari
-    verify_device_ownership(pool, device_id, user_id).await?;
+    let device = get_owned_device(pool, device_id, user_id).await?;
+    
+    // Verificar que el dispositiu pot executar la comanda
+    let payload = validate_command(&req.command_type, &req.payload, &device).map_err(|e| {
+        log::warn!("Rejected {} command for device {}: {}", req.command_type, device_id, e);
+        e
+    })?;
     
     // Crear la comanda
     let conn = pool.get().await.map_err(|e| {
@@ -142,17 +145,14 @@ pub async fn send_command(
         user_id,
         device_id,
         command_type: req.command_type.clone(),
-        payload_json: req.payload.clone(),
+        payload_json: payload,
         status: CommandStatus::Queued.to_string(),
         retry_count: 0,
+        schedule_id: None,
     };
     
     let command = conn
-        .interact(move |conn| {
-            diesel::insert_into(commands::table)
-                .values(&new_command)
-                .get_result::<Command>(conn)
-        })
+        .interact(move |conn| command_processor::insert_command(conn, &new_command, "Sent by user"))
         .await
         .map_err(|e| {
             log::error!("Database interaction error: {:?}", e);
@@ -163,10 +163,17 @@ pub async fn send_command(
             actix_web::error::ErrorInternalServerError("Failed to create command")
         })?;
     
-    // TODO: Enviar notificació FCM a l'app mòbil
-    
     log::info!("Command {} created for device {}", command.id, device_id);
     
+    // Fer arribar la comanda a l'app mòbil sense fer esperar la resposta
+    let pool = pool.clone();
+    let notifier = data.notifier.clone();
+    let hub = data.hub.clone();
+    let queued = command.clone();
+    tokio::spawn(async move {
+        command_processor::dispatch_command(&pool, notifier.as_ref(), hub.as_ref(), &queued).await;
+    });
+    
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "command_id": command.id,
         "status": command.status,
@@ -174,12 +181,12 @@ pub async fn send_command(
     })))
 }
 
-// Funció auxiliar per verificar propietat d'un dispositiu
-async fn verify_device_ownership(
+// Funció auxiliar per obtenir un dispositiu verificant-ne la propietat
+async fn get_owned_device(
     pool: &DbPool,
     device_id: Uuid,
     user_id: Uuid,
-) -> Result<(), actix_web::Error> {
+) -> Result<Device, actix_web::Error> {
     let conn = pool.get().await.map_err(|e| {
         log::error!("Failed to get DB connection: {:?}", e);
         actix_web::error::ErrorInternalServerError("Database connection error")
@@ -189,15 +196,12 @@ async fn verify_device_ownership(
         devices::table
             .find(device_id)
             .filter(devices::user_id.eq(user_id))
-            .select(devices::id)
-            .first::<Uuid>(conn)
+            .first::<Device>(conn)
     })
     .await
     .map_err(|e| {
         log::error!("Database interaction error: {:?}", e);
         actix_web::error::ErrorInternalServerError("Database error")
     })?
-    .map_err(|_| actix_web::error::ErrorForbidden("Device not found or access denied"))?;
-    
-    Ok(())
+    .map_err(|_| actix_web::error::ErrorForbidden("Device not found or access denied"))
 }
@@ -1,6 +1,10 @@
 use crate::{
+    middleware::auth::AuthUser,
     models::{command::*, device::*, user::*},
-    schema::{commands, device_states, devices, mobile_sessions, structures},
+    services::{
+        command_processor::{self, CommandError},
+        ws_hub::WsEvent,
+    },
     AppState, DbPool,
 };
 use actix_web::{web, HttpResponse};
@@ -24,21 +28,10 @@ pub struct HeartbeatResponse {
 
 // Handler per sincronitzar dispositius des de l'app
 pub async fn sync_devices(
-    req: actix_web::HttpRequest,
+    AuthUser(user_id): AuthUser,
     web::Json(sync_req): web::Json<DeviceSyncRequest>,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    // Obtenir user_id del JWT token
-    let auth_header = req.headers()
-        .get("Authorization")
-        .and_then(|h| h.to_str().ok())
-        .and_then(|h| h.strip_prefix("Bearer "))
-        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Missing or invalid token"))?;
-    
-    let claims = crate::handlers::auth::verify_jwt(auth_header, &data.jwt_secret)?;
-    let user_id = Uuid::parse_str(&claims.sub)
-        .map_err(|_| actix_web::error::ErrorBadRequest("Invalid user ID in token"))?;
-    
     let pool = &data.db_pool;
     
     // Processar structures
@@ -49,7 +42,9 @@ pub async fn sync_devices(
     // Processar dispositius
     let mut synced_devices = Vec::new();
     for device in sync_req.devices {
+        let state = device.state.clone();
         let synced = upsert_device(pool, user_id, device).await?;
+        data.hub.send_to_user(user_id, &WsEvent::DeviceState { device_id: synced.id, state });
         synced_devices.push(synced);
     }
     
@@ -64,21 +59,10 @@ pub async fn sync_devices(
 
 // Handler per al heartbeat de l'app
 pub async fn heartbeat(
-    http_req: actix_web::HttpRequest,
+    AuthUser(user_id): AuthUser,
     web::Json(req): web::Json<HeartbeatRequest>,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    // Obtenir user_id del JWT token
-    let auth_header = http_req.headers()
-        .get("Authorization")
-        .and_then(|h| h.to_str().ok())
-        .and_then(|h| h.strip_prefix("Bearer "))
-        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Missing or invalid token"))?;
-    
-    let claims = crate::handlers::auth::verify_jwt(auth_header, &data.jwt_secret)?;
-    let user_id = Uuid::parse_str(&claims.sub)
-        .map_err(|_| actix_web::error::ErrorBadRequest("Invalid user ID in token"))?;
-    
     let pool = &data.db_pool;
     
     // Actualitzar o crear sessió mòbil
@@ -86,6 +70,9 @@ pub async fn heartbeat(
     
     // Obtenir comandes pendents
     let pending_commands = get_pending_commands(pool, user_id).await?;
+    for command in &pending_commands {
+        data.hub.send_to_user(user_id, &WsEvent::command(command));
+    }
     
     Ok(HttpResponse::Ok().json(HeartbeatResponse {
         pending_commands,
@@ -95,48 +82,33 @@ pub async fn heartbeat(
 
 // Handler per reportar resultats de comandes
 pub async fn command_result(
+    AuthUser(user_id): AuthUser,
     web::Json(result): web::Json<CommandResult>,
-    // TODO: Add authentication
     data: web::Data<AppState>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let pool = &data.db_pool;
-    let conn = pool.get().await.map_err(|e| {
-        log::error!("Failed to get DB connection: {:?}", e);
-        actix_web::error::ErrorInternalServerError("Database connection error")
-    })?;
-    
     let command_id = result.command_id;
-    let status = if result.success { "acked" } else { "failed" };
+    let new_state = result.new_state.clone();
     
-    // Actualitzar estat de la comanda
-    conn.interact(move |conn| {
-        diesel::update(commands::table.find(command_id))
-            .set((
-                commands::status.eq(status),
-                commands::error_message.eq(&result.error_message),
-                commands::executed_at.eq(Some(Utc::now())),
-                commands::updated_at.eq(Utc::now()),
-            ))
-            .execute(conn)
-    })
-    .await
-    .map_err(|e| {
-        log::error!("Failed to update command: {:?}", e);
-        actix_web::error::ErrorInternalServerError("Database error")
-    })?
-    .map_err(|e| {
-        log::error!("Failed to update command: {:?}", e);
-        actix_web::error::ErrorInternalServerError("Failed to update command")
-    })?;
+    let command = command_processor::record_result(&data.db_pool, user_id, result)
+        .await
+        .map_err(|e| match e {
+            CommandError::NotFound => actix_web::error::ErrorNotFound("Command not found"),
+            CommandError::InvalidTransition { .. } => {
+                log::warn!("Rejected result for command {}: {}", command_id, e);
+                actix_web::error::ErrorConflict(e.to_string())
+            }
+            CommandError::Database(_) => {
+                log::error!("Failed to record result for command {}: {}", command_id, e);
+                actix_web::error::ErrorInternalServerError("Failed to update command")
+            }
+        })?;
     
-    // Si hi ha nou estat, actualitzar-lo
-    if let Some(new_state) = result.new_state {
-        update_device_state(pool, result.command_id, new_state).await?;
-    }
+    command_processor::publish_result(&data.hub, &command, new_state);
     
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "message": "Command result recorded",
-        "command_id": result.command_id
+        "command_id": command.id,
+        "status": command.status
     })))
 }
 
@@ -305,48 +277,8 @@ fn update_device_state_sync(
     device_id_param: Uuid,
     new_state: serde_json::Value,
 ) -> Result<(), diesel::result::Error> {
-    use crate::schema::device_states::dsl::*;
-    
-    diesel::update(device_states.filter(device_id.eq(&device_id_param)))
-        .set((
-            state_json.eq(&new_state),
-            updated_at.eq(Utc::now()),
-        ))
-        .execute(conn)?;
-    
-    Ok(())
-}
-
-async fn update_device_state(
-    pool: &DbPool,
-    command_id: Uuid,
-    new_state: serde_json::Value,
-) -> Result<(), actix_web::Error> {
-    let conn = pool.get().await.map_err(|e| {
-        log::error!("Failed to get DB connection: {:?}", e);
-        actix_web::error::ErrorInternalServerError("Database connection error")
-    })?;
-    
-    conn.interact(move |conn| {
-        // Obtenir device_id de la comanda
-        let device_id = commands::table
-            .find(command_id)
-            .select(commands::device_id)
-            .first::<Uuid>(conn)?;
-        
-        update_device_state_sync(conn, device_id, new_state)
-    })
-    .await
-    .map_err(|e| {
-        log::error!("Database interaction error: {:?}", e);
-        actix_web::error::ErrorInternalServerError("Database error")
-    })?
-    .map_err(|e| {
-        log::error!("Failed to update device state: {:?}", e);
-        actix_web::error::ErrorInternalServerError("Failed to update device state")
-    })?;
-    
-    Ok(())
+    // Els dispositius antics poden no tenir fila d'estat
+    command_processor::save_device_state(conn, device_id_param, &new_state)
 }
 
 async fn update_mobile_session(
@@ -410,29 +342,11 @@ async fn update_mobile_session(
 }
 
 async fn get_pending_commands(pool: &DbPool, user_id: Uuid) -> Result<Vec<Command>, actix_web::Error> {
-    let conn = pool.get().await.map_err(|e| {
-        log::error!("Failed to get DB connection: {:?}", e);
-        actix_web::error::ErrorInternalServerError("Database connection error")
-    })?;
-    
-    let commands = conn
-        .interact(move |conn| {
-            commands::table
-                .filter(commands::user_id.eq(user_id))
-                .filter(commands::status.eq("queued"))
-                .order(commands::created_at.asc())
-                .limit(10)
-                .load::<Command>(conn)
-        })
+    // Les comandes lliurades passen a sent i no es tornen a lliurar
+    command_processor::deliver_pending(pool, user_id, 10, command_processor::VIA_HEARTBEAT)
         .await
         .map_err(|e| {
-            log::error!("Database interaction error: {:?}", e);
-            actix_web::error::ErrorInternalServerError("Database error")
-        })?
-        .map_err(|e| {
-            log::error!("Failed to get pending commands: {:?}", e);
+            log::error!("Failed to get pending commands: {}", e);
             actix_web::error::ErrorInternalServerError("Failed to get pending commands")
-        })?;
-    
-    Ok(commands)
+        })
 }
@@ -1,7 +1,13 @@
+pub mod alert;
 pub mod auth;
+pub mod command;
 pub mod device;
 pub mod health;
 pub mod mobile;
+pub mod price;
 pub mod rule;
 pub mod schedule;
+pub mod settings;
+pub mod tariff;
+pub mod task;
 pub mod websocket;
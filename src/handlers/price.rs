@@ -0,0 +1,166 @@
+use crate::{
+    middleware::auth::AuthUser,
+    models::price::{DayPricesResponse, TierConfig},
+    services::{price_alerts, settings, tariffs},
+    AppState,
+};
+use actix_web::{web, HttpResponse};
+use chrono::{Duration, NaiveDate, Utc};
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+const DEFAULT_CHEAPEST_HOURS: u8 = 3;
+const MAX_RANGE_DAYS: i64 = 31;
+
+#[derive(Debug, Deserialize)]
+pub struct PriceQuery {
+    pub cheapest: Option<u8>, // Nombre d'hores més barates a les estadístiques
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PriceRangeQuery {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub cheapest: Option<u8>,
+}
+
+// Preus d'avui en la zona horària de l'usuari
+pub async fn get_today_prices(
+    AuthUser(user_id): AuthUser,
+    query: web::Query<PriceQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    get_day_prices(&data, user_id, 0, query.cheapest).await
+}
+
+// Preus de demà; 404 si REE encara no els ha publicat
+pub async fn get_tomorrow_prices(
+    AuthUser(user_id): AuthUser,
+    query: web::Query<PriceQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    get_day_prices(&data, user_id, 1, query.cheapest).await
+}
+
+// Preus d'un interval de dates (per defecte, avui). Els dies sense preus es
+// retornen a `missing`.
+pub async fn list_prices(
+    AuthUser(user_id): AuthUser,
+    query: web::Query<PriceRangeQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let query = query.into_inner();
+    let cheapest = validate_cheapest(query.cheapest)?;
+
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from > to {
+            return Err(actix_web::error::ErrorBadRequest("'from' must not be after 'to'"));
+        }
+        if (to - from).num_days() >= MAX_RANGE_DAYS {
+            return Err(actix_web::error::ErrorBadRequest(format!(
+                "The range must not exceed {} days",
+                MAX_RANGE_DAYS
+            )));
+        }
+    }
+
+    let (days, missing) = load_prices(&data, user_id, cheapest, move |today| {
+        let from = query.from.or(query.to).unwrap_or(today);
+        let to = query.to.unwrap_or(from);
+        from.iter_days().take_while(|d| *d <= to).take(MAX_RANGE_DAYS as usize).collect()
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "days": days,
+        "missing": missing
+    })))
+}
+
+async fn get_day_prices(
+    data: &AppState,
+    user_id: Uuid,
+    offset_days: i64,
+    cheapest: Option<u8>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let cheapest = validate_cheapest(cheapest)?;
+
+    let (days, missing) = load_prices(data, user_id, cheapest, move |today| {
+        vec![today + Duration::days(offset_days)]
+    })
+    .await?;
+
+    match (days.into_iter().next(), missing.first()) {
+        (Some(day), _) => Ok(HttpResponse::Ok().json(day)),
+        (None, Some(date)) => Ok(HttpResponse::NotFound().json(json!({
+            "error": "Prices not published yet",
+            "message": format!(
+                "Prices for {} are not published yet. Next-day market prices are usually available after 20:30 (peninsular time)",
+                date
+            )
+        }))),
+        (None, None) => Err(actix_web::error::ErrorInternalServerError("No date requested")),
+    }
+}
+
+fn validate_cheapest(cheapest: Option<u8>) -> Result<u8, actix_web::Error> {
+    match cheapest.unwrap_or(DEFAULT_CHEAPEST_HOURS) {
+        hours @ 1..=24 => Ok(hours),
+        _ => Err(actix_web::error::ErrorBadRequest("'cheapest' must be between 1 and 24")),
+    }
+}
+
+// Carrega els preus de les dates que retorna `dates` (a partir de l'avui de l'usuari)
+// en la seva zona horària i unitat preferides
+async fn load_prices(
+    data: &AppState,
+    user_id: Uuid,
+    cheapest: u8,
+    dates: impl FnOnce(NaiveDate) -> Vec<NaiveDate> + Send + 'static,
+) -> Result<(Vec<DayPricesResponse>, Vec<NaiveDate>), actix_web::Error> {
+    let pool = &data.db_pool;
+    let conn = pool.get().await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
+
+    let cache = data.prices.clone();
+    conn.interact(move |conn| {
+        let user_settings = settings::load_settings(conn, user_id)?;
+        let tz = user_settings.tz();
+        // Les franges segueixen els llindars dels avisos de l'usuari, si n'ha configurat
+        let tiers = price_alerts::load_subscription(conn, user_id)?
+            .map_or_else(TierConfig::default, |alert| alert.tier_config());
+        let tariff = tariffs::user_tariff(conn, user_id)?;
+        let today = Utc::now().with_timezone(&tz).date_naive();
+
+        let mut days = Vec::new();
+        let mut missing = Vec::new();
+        for date in dates(today) {
+            let prices = tariffs::tariff_prices(&tariff, date, tz, |series, date| {
+                cache
+                    .get_or_load(conn, series, date)
+                    .map(|prices| prices.map(|p| p.as_ref().clone()))
+            })?;
+
+            match prices.and_then(|prices| {
+                DayPricesResponse::new(
+                    date,
+                    tz.name(),
+                    tariff.kind(),
+                    prices,
+                    cheapest,
+                    &tiers,
+                    &user_settings.currency_display,
+                )
+            }) {
+                Some(day) => days.push(day),
+                None => missing.push(date),
+            }
+        }
+
+        Ok::<_, diesel::result::Error>((days, missing))
+    })
+    .await
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Database query failed"))?
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get prices"))
+}
@@ -1,10 +1,18 @@
 use crate::{
-    models::rule::{Rule, NewRule},
+    middleware::auth::AuthUser,
+    models::{
+        rule::{self, DeadlineEnergyParams, Rule, RuleApplicability, NewRule, RuleType},
+        schedule::{PreviewScheduleRequest, ScheduleResponse},
+        user::parse_timezone,
+    },
+    services::{optimizer::{self, OptimizerError}, schedule_builder, settings, tariffs, ws_hub::WsEvent},
+    utils::errors::ValidationErrors,
     AppState,
 };
-use actix_web::{web, HttpRequest, HttpResponse};
-use chrono::Utc;
+use actix_web::{web, HttpResponse};
+use chrono::{Duration, Utc};
 use diesel::prelude::*;
+use std::convert::Infallible;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use uuid::Uuid;
@@ -15,6 +23,11 @@ pub struct CreateRuleRequest {
     pub rule_type: String,
     pub params: serde_json::Value,
     pub active: Option<bool>,
+    // Per defecte, la zona horària de les preferències de l'usuari
+    pub timezone: Option<String>,
+    pub priority: Option<i32>, // 1-100, per defecte 1
+    // Dies de la setmana, vigència i excepcions; per defecte cada dia
+    pub applicability: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -22,6 +35,9 @@ pub struct UpdateRuleRequest {
     pub rule_type: Option<String>,
     pub params: Option<serde_json::Value>,
     pub active: Option<bool>,
+    pub timezone: Option<String>,
+    pub priority: Option<i32>,
+    pub applicability: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Serialize)]
@@ -31,26 +47,46 @@ pub struct RuleResponse {
     pub rule_type: String,
     pub params: serde_json::Value,
     pub active: bool,
+    pub timezone: String,
+    pub priority: i32,
+    pub applicability: serde_json::Value,
     pub created_at: chrono::DateTime<Utc>,
     pub updated_at: chrono::DateTime<Utc>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<RuleWarning>,
+}
+
+// Avís d'una altra regla activa del mateix dispositiu que es trepitja amb aquesta
+#[derive(Debug, Serialize)]
+pub struct RuleWarning {
+    pub rule_id: Uuid,
+    pub priority: i32,
+    pub message: String,
+}
+
+impl RuleResponse {
+    fn new(rule: Rule, warnings: Vec<RuleWarning>) -> Self {
+        Self {
+            id: rule.id,
+            device_id: rule.device_id,
+            rule_type: rule.rule_type,
+            params: rule.params_json,
+            active: rule.enabled,
+            timezone: rule.timezone,
+            priority: rule.priority,
+            applicability: rule.applicability_json,
+            created_at: rule.created_at,
+            updated_at: rule.updated_at,
+            warnings,
+        }
+    }
 }
 
 // Llistar totes les regles de l'usuari
 pub async fn list_rules(
-    req: HttpRequest,
+    AuthUser(user_id): AuthUser,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    // Obtenir user_id del JWT token
-    let auth_header = req.headers()
-        .get("Authorization")
-        .and_then(|h| h.to_str().ok())
-        .and_then(|h| h.strip_prefix("Bearer "))
-        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Missing or invalid token"))?;
-    
-    let claims = crate::handlers::auth::verify_jwt(auth_header, &data.jwt_secret)?;
-    let user_id = Uuid::parse_str(&claims.sub)
-        .map_err(|_| actix_web::error::ErrorBadRequest("Invalid user ID in token"))?;
-    
     let pool = &data.db_pool;
     let conn = pool.get().await
         .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
@@ -69,42 +105,133 @@ pub async fn list_rules(
     .map_err(|_| actix_web::error::ErrorInternalServerError("Database query failed"))?
     .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get rules"))?;
     
+    let rules: Vec<RuleResponse> = rules
+        .into_iter()
+        .map(|rule| RuleResponse::new(rule, Vec::new()))
+        .collect();
+
     Ok(HttpResponse::Ok().json(json!({
         "rules": rules
     })))
 }
 
+// Valida el tipus i els paràmetres d'una regla i retorna els paràmetres normalitzats
+fn validate_rule(
+    rule_type: &str,
+    params: &serde_json::Value,
+) -> Result<(RuleType, serde_json::Value), ValidationErrors> {
+    let rule_type: RuleType = rule_type
+        .parse()
+        .map_err(|e: String| ValidationErrors::single("rule_type", e))?;
+    let params = rule_type.validate_params(params)?;
+    Ok((rule_type, params))
+}
+
+// Valida una zona horària opcional i la retorna amb el nom canònic
+fn validate_timezone(timezone: Option<&str>) -> Result<Option<String>, ValidationErrors> {
+    timezone
+        .map(|tz| {
+            parse_timezone(tz)
+                .map(|tz| tz.name().to_string())
+                .map_err(|e| ValidationErrors::single("timezone", e))
+        })
+        .transpose()
+}
+
+// Regles actives del mateix dispositiu que poden encendre'l a les mateixes hores.
+// Els horaris es combinen igualment; l'avís ajuda a detectar regles duplicades.
+fn overlap_warnings(conn: &mut PgConnection, rule: &Rule) -> Result<Vec<RuleWarning>, diesel::result::Error> {
+    use crate::schema::rules;
+
+    if !rule.enabled {
+        return Ok(Vec::new());
+    }
+
+    let ranges_of = |rule: &Rule| match rule.get_rule_type() {
+        Ok(rule_type) => rule_type.day_ranges(&rule.params_json),
+        Err(_) => vec![(0, 24 * 60)],
+    };
+    let own_ranges = ranges_of(rule);
+    let own_days = rule.get_applicability().unwrap_or_default();
+
+    let others = rules::table
+        .filter(rules::device_id.eq(rule.device_id))
+        .filter(rules::enabled.eq(true))
+        .filter(rules::id.ne(rule.id))
+        .order((rules::priority.desc(), rules::created_at.asc()))
+        .load::<Rule>(conn)?;
+
+    Ok(others
+        .into_iter()
+        .filter(|other| own_days.overlaps(&other.get_applicability().unwrap_or_default()))
+        .filter(|other| rule::ranges_overlap(&own_ranges, &ranges_of(other)))
+        .map(|other| {
+            let message = match other.priority.cmp(&rule.priority) {
+                std::cmp::Ordering::Greater => format!(
+                    "Overlaps rule {} (priority {}), which takes precedence: shared slots are attributed to it",
+                    other.id, other.priority
+                ),
+                std::cmp::Ordering::Less => format!(
+                    "Overlaps rule {} (priority {}): both schedules are merged and shared slots are attributed to this rule",
+                    other.id, other.priority
+                ),
+                std::cmp::Ordering::Equal => format!(
+                    "Overlaps rule {} with the same priority: both schedules are merged and shared slots are attributed to the older rule",
+                    other.id
+                ),
+            };
+            RuleWarning {
+                rule_id: other.id,
+                priority: other.priority,
+                message,
+            }
+        })
+        .collect())
+}
+
+// Replanifica els horaris d'avui i demà després de canviar una regla, perquè una regla
+// desactivada, editada o eliminada no continuï engegant el dispositiu. La regla ja està
+// desada: si la replanificació falla es tornarà a fer amb els preus de demà.
+async fn rebuild_after_change(data: &AppState, user_id: Uuid) {
+    match schedule_builder::rebuild_user_schedules(&data.db_pool, user_id).await {
+        Ok(result) => data.hub.send_to_user(user_id, &WsEvent::schedules_rebuilt(&result)),
+        Err(e) => log::error!("Failed to rebuild schedules for user {} after rule change: {}", user_id, e),
+    }
+}
+
 // Crear una nova regla
 pub async fn create_rule(
-    req: HttpRequest,
+    AuthUser(user_id): AuthUser,
     payload: web::Json<CreateRuleRequest>,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    // Obtenir user_id del JWT token
-    let auth_header = req.headers()
-        .get("Authorization")
-        .and_then(|h| h.to_str().ok())
-        .and_then(|h| h.strip_prefix("Bearer "))
-        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Missing or invalid token"))?;
-    
-    let claims = crate::handlers::auth::verify_jwt(auth_header, &data.jwt_secret)?;
-    let user_id = Uuid::parse_str(&claims.sub)
-        .map_err(|_| actix_web::error::ErrorBadRequest("Invalid user ID in token"))?;
+    let (rule_type, params) = validate_rule(&payload.rule_type, &payload.params)?;
+    let timezone = validate_timezone(payload.timezone.as_deref())?;
+    let priority = rule::validate_priority(payload.priority.unwrap_or(rule::DEFAULT_PRIORITY))?;
+    let applicability = match &payload.applicability {
+        Some(applicability) => RuleApplicability::validate(applicability)?,
+        None => json!({}),
+    };
     
     let pool = &data.db_pool;
     let conn = pool.get().await
         .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
     
-    // Verificar que el dispositiu pertany a l'usuari
+    // Verificar que el dispositiu pertany a l'usuari i obtenir la zona horària per defecte
     let device_id = payload.device_id;
-    let device_exists = conn.interact(move |conn| {
+    let (device_exists, timezone) = conn.interact(move |conn| -> Result<(i64, String), diesel::result::Error> {
         use crate::schema::devices;
         
-        devices::table
+        let count = devices::table
             .filter(devices::id.eq(device_id))
             .filter(devices::user_id.eq(user_id))
             .count()
-            .get_result::<i64>(conn)
+            .get_result::<i64>(conn)?;
+        let timezone = match timezone {
+            Some(timezone) => timezone,
+            None => settings::load_settings(conn, user_id)?.timezone,
+        };
+        Ok((count, timezone))
     })
     .await
     .map_err(|_| actix_web::error::ErrorInternalServerError("Database query failed"))?
@@ -119,54 +246,39 @@ pub async fn create_rule(
         id: Uuid::new_v4(),
         user_id,
         device_id: payload.device_id,
-        rule_type: payload.rule_type.clone(),
-        params_json: payload.params.clone(),
-        timezone: "Europe/Madrid".to_string(), // TODO: Obtenir de l'usuari
-        priority: 1,
+        rule_type: rule_type.to_string(),
+        params_json: params,
+        timezone,
+        priority,
         enabled: payload.active.unwrap_or(true),
+        applicability_json: applicability,
     };
     
-    let rule = conn.interact(move |conn| {
+    let (rule, warnings) = conn.interact(move |conn| {
         use crate::schema::rules;
         
-        diesel::insert_into(rules::table)
+        let rule = diesel::insert_into(rules::table)
             .values(&new_rule)
-            .get_result::<Rule>(conn)
+            .get_result::<Rule>(conn)?;
+        let warnings = overlap_warnings(conn, &rule)?;
+        Ok::<_, diesel::result::Error>((rule, warnings))
     })
     .await
     .map_err(|_| actix_web::error::ErrorInternalServerError("Database insert failed"))?
     .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to create rule"))?;
     
     log::info!("User {} created rule {} for device {}", user_id, rule.id, rule.device_id);
+    rebuild_after_change(&data, user_id).await;
     
-    Ok(HttpResponse::Created().json(RuleResponse {
-        id: rule.id,
-        device_id: rule.device_id,
-        rule_type: rule.rule_type,
-        params: rule.params_json,
-        active: rule.enabled,
-        created_at: rule.created_at,
-        updated_at: rule.updated_at,
-    }))
+    Ok(HttpResponse::Created().json(RuleResponse::new(rule, warnings)))
 }
 
 // Obtenir una regla específica
 pub async fn get_rule(
-    req: HttpRequest,
+    AuthUser(user_id): AuthUser,
     path: web::Path<Uuid>,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    // Obtenir user_id del JWT token
-    let auth_header = req.headers()
-        .get("Authorization")
-        .and_then(|h| h.to_str().ok())
-        .and_then(|h| h.strip_prefix("Bearer "))
-        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Missing or invalid token"))?;
-    
-    let claims = crate::handlers::auth::verify_jwt(auth_header, &data.jwt_secret)?;
-    let user_id = Uuid::parse_str(&claims.sub)
-        .map_err(|_| actix_web::error::ErrorBadRequest("Invalid user ID in token"))?;
-    
     let pool = &data.db_pool;
     let conn = pool.get().await
         .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
@@ -187,35 +299,16 @@ pub async fn get_rule(
     .map_err(|_| actix_web::error::ErrorInternalServerError("Database query failed"))?
     .map_err(|_| actix_web::error::ErrorNotFound("Rule not found"))?;
     
-    Ok(HttpResponse::Ok().json(RuleResponse {
-        id: rule.id,
-        device_id: rule.device_id,
-        rule_type: rule.rule_type,
-        params: rule.params_json,
-        active: rule.enabled,
-        created_at: rule.created_at,
-        updated_at: rule.updated_at,
-    }))
+    Ok(HttpResponse::Ok().json(RuleResponse::new(rule, Vec::new())))
 }
 
 // Actualitzar una regla
 pub async fn update_rule(
-    req: HttpRequest,
+    AuthUser(user_id): AuthUser,
     path: web::Path<Uuid>,
     payload: web::Json<UpdateRuleRequest>,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    // Obtenir user_id del JWT token
-    let auth_header = req.headers()
-        .get("Authorization")
-        .and_then(|h| h.to_str().ok())
-        .and_then(|h| h.strip_prefix("Bearer "))
-        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Missing or invalid token"))?;
-    
-    let claims = crate::handlers::auth::verify_jwt(auth_header, &data.jwt_secret)?;
-    let user_id = Uuid::parse_str(&claims.sub)
-        .map_err(|_| actix_web::error::ErrorBadRequest("Invalid user ID in token"))?;
-    
     let pool = &data.db_pool;
     let conn = pool.get().await
         .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
@@ -223,172 +316,260 @@ pub async fn update_rule(
     let rule_id = path.into_inner();
     
     // Verificar que la regla pertany a un dispositiu de l'usuari
-    let rule_exists = conn.interact(move |conn| {
+    let existing = conn.interact(move |conn| {
         use crate::schema::{rules, devices};
         
         rules::table
             .inner_join(devices::table.on(devices::id.eq(rules::device_id)))
             .filter(rules::id.eq(rule_id))
             .filter(devices::user_id.eq(user_id))
-            .count()
-            .get_result::<i64>(conn)
+            .select(rules::all_columns)
+            .first::<Rule>(conn)
+            .optional()
     })
     .await
     .map_err(|_| actix_web::error::ErrorInternalServerError("Database query failed"))?
-    .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to verify rule"))?;
-    
-    if rule_exists == 0 {
-        return Err(actix_web::error::ErrorNotFound("Rule not found"));
-    }
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to verify rule"))?
+    .ok_or_else(|| actix_web::error::ErrorNotFound("Rule not found"))?;
+    
+    // Si canvia el tipus o els paràmetres, es valida la combinació resultant
+    let payload = payload.into_inner();
+    let (rule_type, params) = if payload.rule_type.is_some() || payload.params.is_some() {
+        let rule_type = payload.rule_type.unwrap_or_else(|| existing.rule_type.clone());
+        let params = payload.params.unwrap_or_else(|| existing.params_json.clone());
+        let (rule_type, params) = validate_rule(&rule_type, &params)?;
+        (rule_type.to_string(), params)
+    } else {
+        (existing.rule_type.clone(), existing.params_json.clone())
+    };
+    let enabled = payload.active.unwrap_or(existing.enabled);
+    let timezone = validate_timezone(payload.timezone.as_deref())?.unwrap_or(existing.timezone);
+    let priority = rule::validate_priority(payload.priority.unwrap_or(existing.priority))?;
+    let applicability = match &payload.applicability {
+        Some(applicability) => RuleApplicability::validate(applicability)?,
+        None => existing.applicability_json.clone(),
+    };
     
     // Actualitzar la regla
-    let rule = conn.interact(move |conn| {
+    let (rule, warnings) = conn.interact(move |conn| {
         use crate::schema::rules;
         
-        // Construir els camps a actualitzar dinàmicament
-        match (&payload.rule_type, &payload.params, payload.active) {
-            (Some(rt), Some(p), Some(a)) => {
-                diesel::update(rules::table.find(rule_id))
-                    .set((
-                        rules::rule_type.eq(rt),
-                        rules::params_json.eq(p),
-                        rules::enabled.eq(a),
-                        rules::updated_at.eq(Utc::now()),
-                    ))
-                    .get_result::<Rule>(conn)
-            },
-            (Some(rt), Some(p), None) => {
-                diesel::update(rules::table.find(rule_id))
-                    .set((
-                        rules::rule_type.eq(rt),
-                        rules::params_json.eq(p),
-                        rules::updated_at.eq(Utc::now()),
-                    ))
-                    .get_result::<Rule>(conn)
-            },
-            (Some(rt), None, Some(a)) => {
-                diesel::update(rules::table.find(rule_id))
-                    .set((
-                        rules::rule_type.eq(rt),
-                        rules::enabled.eq(a),
-                        rules::updated_at.eq(Utc::now()),
-                    ))
-                    .get_result::<Rule>(conn)
-            },
-            (None, Some(p), Some(a)) => {
-                diesel::update(rules::table.find(rule_id))
-                    .set((
-                        rules::params_json.eq(p),
-                        rules::enabled.eq(a),
-                        rules::updated_at.eq(Utc::now()),
-                    ))
-                    .get_result::<Rule>(conn)
-            },
-            (Some(rt), None, None) => {
-                diesel::update(rules::table.find(rule_id))
-                    .set((
-                        rules::rule_type.eq(rt),
-                        rules::updated_at.eq(Utc::now()),
-                    ))
-                    .get_result::<Rule>(conn)
-            },
-            (None, Some(p), None) => {
-                diesel::update(rules::table.find(rule_id))
-                    .set((
-                        rules::params_json.eq(p),
-                        rules::updated_at.eq(Utc::now()),
-                    ))
-                    .get_result::<Rule>(conn)
-            },
-            (None, None, Some(a)) => {
-                diesel::update(rules::table.find(rule_id))
-                    .set((
-                        rules::enabled.eq(a),
-                        rules::updated_at.eq(Utc::now()),
-                    ))
-                    .get_result::<Rule>(conn)
-            },
-            (None, None, None) => {
-                // Si no hi ha res a actualitzar, només actualitzem updated_at
-                diesel::update(rules::table.find(rule_id))
-                    .set(rules::updated_at.eq(Utc::now()))
-                    .get_result::<Rule>(conn)
-            },
-        }
+        let rule = diesel::update(rules::table.find(rule_id))
+            .set((
+                rules::rule_type.eq(rule_type),
+                rules::params_json.eq(params),
+                rules::enabled.eq(enabled),
+                rules::timezone.eq(timezone),
+                rules::priority.eq(priority),
+                rules::applicability_json.eq(applicability),
+                rules::updated_at.eq(Utc::now()),
+            ))
+            .get_result::<Rule>(conn)?;
+        let warnings = overlap_warnings(conn, &rule)?;
+        Ok::<_, diesel::result::Error>((rule, warnings))
     })
     .await
     .map_err(|_| actix_web::error::ErrorInternalServerError("Database update failed"))?
     .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to update rule"))?;
     
     log::info!("User {} updated rule {}", user_id, rule.id);
+    rebuild_after_change(&data, user_id).await;
     
-    Ok(HttpResponse::Ok().json(RuleResponse {
-        id: rule.id,
-        device_id: rule.device_id,
-        rule_type: rule.rule_type,
-        params: rule.params_json,
-        active: rule.enabled,
-        created_at: rule.created_at,
-        updated_at: rule.updated_at,
-    }))
+    Ok(HttpResponse::Ok().json(RuleResponse::new(rule, warnings)))
 }
 
 // Eliminar una regla
 pub async fn delete_rule(
-    req: HttpRequest,
+    AuthUser(user_id): AuthUser,
     path: web::Path<Uuid>,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    // Obtenir user_id del JWT token
-    let auth_header = req.headers()
-        .get("Authorization")
-        .and_then(|h| h.to_str().ok())
-        .and_then(|h| h.strip_prefix("Bearer "))
-        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Missing or invalid token"))?;
+    let pool = &data.db_pool;
+    let conn = pool.get().await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
     
-    let claims = crate::handlers::auth::verify_jwt(auth_header, &data.jwt_secret)?;
-    let user_id = Uuid::parse_str(&claims.sub)
-        .map_err(|_| actix_web::error::ErrorBadRequest("Invalid user ID in token"))?;
+    let rule_id = path.into_inner();
     
+    // Verificar i eliminar la regla, replanificant abans perquè l'esborrat no arrossegui
+    // els horaris de les altres regles del dispositiu
+    let deleted = conn.interact(move |conn| {
+        use crate::schema::{rules, devices};
+        
+        conn.transaction(|conn| {
+            // Primer verificar que pertany a l'usuari
+            let rule = rules::table
+                .inner_join(devices::table.on(devices::id.eq(rules::device_id)))
+                .filter(rules::id.eq(rule_id))
+                .filter(devices::user_id.eq(user_id))
+                .select(rules::all_columns)
+                .first::<Rule>(conn)
+                .optional()?;
+            
+            let Some(rule) = rule else {
+                return Ok(None);
+            };
+            
+            let result = schedule_builder::detach_rule_schedules(conn, user_id, &rule)?;
+            
+            // Eliminar la regla
+            diesel::delete(rules::table.find(rule_id))
+                .execute(conn)?;
+            
+            Ok::<_, diesel::result::Error>(Some(result))
+        })
+    })
+    .await
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Database operation failed"))?
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to delete rule"))?;
+    
+    let Some(result) = deleted else {
+        return Err(actix_web::error::ErrorNotFound("Rule not found"));
+    };
+    
+    log::info!("User {} deleted rule {}", user_id, rule_id);
+    data.hub.send_to_user(user_id, &WsEvent::schedules_rebuilt(&result));
+    
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Rule deleted successfully"
+    })))
+}
+
+// Previsualitzar l'horari que generaria una regla (existent o en edició) sense desar res
+pub async fn preview_schedule(
+    AuthUser(user_id): AuthUser,
+    payload: web::Json<PreviewScheduleRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
     let pool = &data.db_pool;
     let conn = pool.get().await
         .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
     
-    let rule_id = path.into_inner();
+    let preview = payload.into_inner();
+    let device_id = preview.device_id;
+    let rule_id = preview.rule_id;
+    let date = preview.date;
+    let preview_type = preview.rule_type;
     
-    // Verificar i eliminar la regla
-    let deleted = conn.interact(move |conn| -> Result<bool, diesel::result::Error> {
+    let (device_exists, rule, tz, prices, next_prices) = conn.interact(move |conn| {
         use crate::schema::{rules, devices};
         
-        // Primer verificar que pertany a l'usuari
-        let rule_exists = rules::table
-            .inner_join(devices::table.on(devices::id.eq(rules::device_id)))
-            .filter(rules::id.eq(rule_id))
+        let device_exists = devices::table
+            .filter(devices::id.eq(device_id))
             .filter(devices::user_id.eq(user_id))
             .count()
             .get_result::<i64>(conn)?;
         
-        if rule_exists == 0 {
-            return Ok(false);
-        }
+        let rule = match rule_id {
+            Some(rule_id) => rules::table
+                .inner_join(devices::table.on(devices::id.eq(rules::device_id)))
+                .filter(rules::id.eq(rule_id))
+                .filter(devices::user_id.eq(user_id))
+                .select(rules::all_columns)
+                .first::<Rule>(conn)
+                .optional()?,
+            None => None,
+        };
+        
+        // Els preus es mostren en la zona horària de la regla, o la de l'usuari si és nova
+        let tz = match &rule {
+            Some(rule) => schedule_builder::rule_timezone(rule),
+            None => settings::load_settings(conn, user_id)?.tz(),
+        };
+        let tariff = tariffs::user_tariff(conn, user_id)?;
+        let prices = tariffs::load_tariff_prices(conn, &tariff, date, tz)?;
         
-        // Eliminar la regla
-        diesel::delete(rules::table.find(rule_id))
-            .execute(conn)?;
+        // Les sessions de càrrega amb hora límit acaben l'endemà
+        let rule_type = preview_type.or_else(|| rule.as_ref().and_then(|r| r.get_rule_type().ok()));
+        let next_prices = match rule_type {
+            Some(RuleType::DeadlineEnergy) => {
+                tariffs::load_tariff_prices(conn, &tariff, date + Duration::days(1), tz)?
+            }
+            _ => None,
+        };
         
-        Ok(true)
+        Ok::<_, diesel::result::Error>((device_exists, rule, tz, prices, next_prices))
     })
     .await
-    .map_err(|_| actix_web::error::ErrorInternalServerError("Database operation failed"))?
-    .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to delete rule"))?;
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Database query failed"))?
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to load preview data"))?;
     
-    if !deleted {
-        return Err(actix_web::error::ErrorNotFound("Rule not found"));
+    if device_exists == 0 {
+        return Err(actix_web::error::ErrorNotFound("Device not found"));
     }
     
-    log::info!("User {} deleted rule {}", user_id, rule_id);
+    // Una regla desada no genera cap horari els dies que no s'aplica
+    if let Some(rule) = &rule {
+        let applicability = rule
+            .get_applicability()
+            .map_err(|e| ValidationErrors::single("applicability", e))?;
+        if !applicability.applies_on(date) {
+            return Err(actix_web::error::ErrorUnprocessableEntity(format!(
+                "Rule does not apply on {}",
+                date
+            )));
+        }
+    }
     
-    Ok(HttpResponse::Ok().json(json!({
-        "message": "Rule deleted successfully"
-    })))
+    // Els paràmetres enviats tenen preferència sobre els de la regla desada
+    let (rule_type, params) = match (rule, rule_id) {
+        (Some(rule), _) => (
+            match preview.rule_type {
+                Some(rule_type) => rule_type,
+                None => rule
+                    .get_rule_type()
+                    .map_err(|e| ValidationErrors::single("rule_type", e))?,
+            },
+            preview.rule_params.unwrap_or(rule.params_json),
+        ),
+        (None, Some(_)) => return Err(actix_web::error::ErrorNotFound("Rule not found")),
+        (None, None) => match (preview.rule_type, preview.rule_params) {
+            (Some(rule_type), Some(params)) => (rule_type, params),
+            _ => {
+                return Err(actix_web::error::ErrorBadRequest(
+                    "Either rule_id or rule_type and rule_params are required",
+                ))
+            }
+        },
+    };
+    
+    let params = rule_type.validate_params(&params)?;
+    
+    let prices = prices
+        .ok_or_else(|| {
+            actix_web::error::ErrorNotFound(format!("Prices for {} are not published yet", date))
+        })?;
+    
+    let optimized = match rule_type {
+        RuleType::DeadlineEnergy => {
+            let next_date = date + Duration::days(1);
+            let next_prices = next_prices.ok_or_else(|| {
+                actix_web::error::ErrorNotFound(format!("Prices for {} are not published yet", next_date))
+            })?;
+            let params: DeadlineEnergyParams = serde_json::from_value(params)
+                .map_err(|e| ValidationErrors::single("params", e.to_string()))?;
+            
+            // La previsualització mostra la sessió sencera, fins a l'hora límit de l'endemà
+            schedule_builder::plan_session(&params, date, tz, |day| {
+                Ok::<_, Infallible>(Some(if day == date { prices.clone() } else { next_prices.clone() }))
+            })
+            .unwrap_or_else(|e| match e {})
+        }
+        _ => optimizer::optimize_rule(rule_type, &params, &prices),
+    }
+    .map_err(|e| match e {
+        OptimizerError::NoPrices => actix_web::error::ErrorNotFound(e.to_string()),
+        OptimizerError::InvalidParams(_) => actix_web::error::ErrorBadRequest(e.to_string()),
+        OptimizerError::Infeasible(_) => actix_web::error::ErrorUnprocessableEntity(e.to_string()),
+    })?;
+    
+    Ok(HttpResponse::Ok().json(ScheduleResponse {
+        device_id,
+        date,
+        slots: optimized.slots,
+        total_cost: optimized.total_cost,
+        total_hours: optimized.total_hours,
+        average_cost: Some(optimized.average_cost),
+        savings: Some(optimized.savings),
+        savings_percentage: optimized.savings_percentage,
+    }))
 }
\ No newline at end of file
@@ -1,35 +1,181 @@
-use crate::AppState;
+use crate::{
+    middleware::auth::AuthUser,
+    models::{
+        rule::Rule,
+        schedule::{PricePeriod, Schedule, ScheduleResponse},
+    },
+    schema::{rules, schedules},
+    services::{price_fetcher::PRICE_TIMEZONE, schedule_builder, settings, tariffs, ws_hub::WsEvent},
+    AppState,
+};
 use actix_web::{web, HttpResponse};
+use chrono::{NaiveDate, Utc};
+use chrono_tz::Tz;
+use diesel::prelude::*;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
 use uuid::Uuid;
 
+#[derive(Debug, Deserialize)]
+pub struct ScheduleQuery {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub device_id: Option<Uuid>,
+}
+
+// Llistar horaris de l'usuari, filtrats opcionalment per dates i dispositiu
 pub async fn list_schedules(
-    // TODO: Add authentication
-    _data: web::Data<AppState>,
+    AuthUser(user_id): AuthUser,
+    query: web::Query<ScheduleQuery>,
+    data: web::Data<AppState>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    // TODO: Implementar llistat d'horaris
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "schedules": [],
-        "message": "List schedules endpoint to be implemented"
+    let query = query.into_inner();
+
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from > to {
+            return Err(actix_web::error::ErrorBadRequest("'from' must not be after 'to'"));
+        }
+    }
+
+    let pool = &data.db_pool;
+    let conn = pool.get().await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
+
+    let responses = conn.interact(move |conn| {
+        let mut db_query = schedules::table
+            .filter(schedules::user_id.eq(user_id))
+            .into_boxed();
+
+        if let Some(from) = query.from {
+            db_query = db_query.filter(schedules::date.ge(from));
+        }
+        if let Some(to) = query.to {
+            db_query = db_query.filter(schedules::date.le(to));
+        }
+        if let Some(device_id) = query.device_id {
+            db_query = db_query.filter(schedules::device_id.eq(device_id));
+        }
+
+        let user_schedules = db_query
+            .order((schedules::date.asc(), schedules::device_id.asc()))
+            .load::<Schedule>(conn)?;
+
+        to_responses(conn, &user_schedules)
+    })
+    .await
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Database query failed"))?
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get schedules"))?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "schedules": responses
     })))
 }
 
+// Obtenir els horaris d'avui de l'usuari ("avui" segons la seva zona horària)
 pub async fn get_today_schedules(
-    // TODO: Add authentication
-    _data: web::Data<AppState>,
+    AuthUser(user_id): AuthUser,
+    data: web::Data<AppState>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    // TODO: Implementar obtenció d'horaris d'avui
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "schedules": [],
-        "message": "Today schedules endpoint to be implemented"
+    let pool = &data.db_pool;
+    let conn = pool.get().await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
+
+    let (today, responses) = conn.interact(move |conn| {
+        let tz = settings::load_settings(conn, user_id)?.tz();
+        let today = Utc::now().with_timezone(&tz).date_naive();
+
+        let user_schedules = schedules::table
+            .filter(schedules::user_id.eq(user_id))
+            .filter(schedules::date.eq(today))
+            .order(schedules::device_id.asc())
+            .load::<Schedule>(conn)?;
+
+        Ok::<_, diesel::result::Error>((today, to_responses(conn, &user_schedules)?))
+    })
+    .await
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Database query failed"))?
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get schedules"))?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "date": today,
+        "schedules": responses
     })))
 }
 
+// Recalcular els horaris d'avui i demà a partir de les regles actives
 pub async fn rebuild_schedules(
-    // TODO: Add authentication
-    _data: web::Data<AppState>,
+    AuthUser(user_id): AuthUser,
+    data: web::Data<AppState>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    // TODO: Implementar reconstrucció d'horaris
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "message": "Rebuild schedules endpoint to be implemented"
+    let result = schedule_builder::rebuild_user_schedules(&data.db_pool, user_id)
+        .await
+        .map_err(|e| {
+            log::error!("Failed to rebuild schedules for user {}: {}", user_id, e);
+            actix_web::error::ErrorInternalServerError("Failed to rebuild schedules")
+        })?;
+
+    log::info!(
+        "User {} rebuilt {} schedules ({} failures)",
+        user_id,
+        result.schedules.len(),
+        result.failures.len()
+    );
+    data.hub.send_to_user(user_id, &WsEvent::schedules_rebuilt(&result));
+
+    let pool = &data.db_pool;
+    let conn = pool.get().await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
+
+    let rebuilt = result.schedules;
+    let responses = conn
+        .interact(move |conn| to_responses(conn, &rebuilt))
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database query failed"))?
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get schedules"))?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "schedules": responses,
+        "failures": result.failures
     })))
 }
+
+// Converteix horaris a respostes, calculant l'estalvi amb el preu mitjà de cada dia
+fn to_responses(
+    conn: &mut PgConnection,
+    user_schedules: &[Schedule],
+) -> Result<Vec<ScheduleResponse>, diesel::result::Error> {
+    // Preu mitjà del dia segons la tarifa de l'usuari i la zona horària de la regla,
+    // la mateixa amb què s'ha planificat l'horari
+    let Some(first) = user_schedules.first() else {
+        return Ok(Vec::new());
+    };
+    let tariff = tariffs::user_tariff(conn, first.user_id)?;
+
+    let rule_ids: Vec<Uuid> = user_schedules.iter().map(|s| s.rule_id).collect();
+    let timezones: HashMap<Uuid, Tz> = rules::table
+        .filter(rules::id.eq_any(rule_ids))
+        .load::<Rule>(conn)?
+        .iter()
+        .map(|rule| (rule.id, schedule_builder::rule_timezone(rule)))
+        .collect();
+
+    let mut average_prices: HashMap<(NaiveDate, Tz), Option<Decimal>> = HashMap::new();
+    let mut responses = Vec::with_capacity(user_schedules.len());
+    for schedule in user_schedules {
+        let tz = timezones.get(&schedule.rule_id).copied().unwrap_or(PRICE_TIMEZONE);
+        let average = match average_prices.get(&(schedule.date, tz)) {
+            Some(average) => *average,
+            None => {
+                let average = tariffs::load_tariff_prices(conn, &tariff, schedule.date, tz)?
+                    .and_then(|prices| PricePeriod::average(&prices));
+                average_prices.insert((schedule.date, tz), average);
+                average
+            }
+        };
+        responses.push(ScheduleResponse::from_schedule(schedule, average));
+    }
+
+    Ok(responses)
+}
@@ -0,0 +1,55 @@
+use crate::{
+    middleware::auth::AuthUser,
+    models::user::UpdateSettingsRequest,
+    services::settings,
+    AppState,
+};
+use actix_web::{web, HttpResponse};
+
+// Obtenir les preferències de l'usuari
+pub async fn get_settings(
+    AuthUser(user_id): AuthUser,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pool = &data.db_pool;
+    let conn = pool.get().await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
+
+    let user_settings = conn
+        .interact(move |conn| settings::load_settings(conn, user_id))
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database query failed"))?
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get settings"))?;
+
+    Ok(HttpResponse::Ok().json(user_settings))
+}
+
+// Actualitzar les preferències de l'usuari. Només afecta les regles noves;
+// les existents conserven la seva zona horària.
+pub async fn update_settings(
+    AuthUser(user_id): AuthUser,
+    payload: web::Json<UpdateSettingsRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pool = &data.db_pool;
+    let conn = pool.get().await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
+
+    let current = conn
+        .interact(move |conn| settings::load_settings(conn, user_id))
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database query failed"))?
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get settings"))?;
+
+    let updated = current.apply(payload.into_inner())?;
+
+    let saved = conn
+        .interact(move |conn| settings::save_settings(conn, &updated))
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database update failed"))?
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to save settings"))?;
+
+    log::info!("User {} updated settings (timezone {})", user_id, saved.timezone);
+
+    Ok(HttpResponse::Ok().json(saved))
+}
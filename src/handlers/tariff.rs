@@ -0,0 +1,73 @@
+use crate::{
+    middleware::auth::AuthUser,
+    models::tariff::UpdateTariffRequest,
+    services::{schedule_builder, tariffs, ws_hub::WsEvent},
+    AppState,
+};
+use actix_web::{web, HttpResponse};
+use serde_json::json;
+
+// Obtenir la tarifa contractada de l'usuari (PVPC 2.0TD per defecte)
+pub async fn get_tariff(
+    AuthUser(user_id): AuthUser,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pool = &data.db_pool;
+    let conn = pool.get().await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
+
+    let tariff = conn
+        .interact(move |conn| tariffs::load_tariff(conn, user_id))
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database query failed"))?
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get tariff"))?;
+
+    Ok(HttpResponse::Ok().json(tariff))
+}
+
+// Canviar la tarifa. Els horaris d'avui i demà es recalculen amb la nova corba de preus.
+pub async fn update_tariff(
+    AuthUser(user_id): AuthUser,
+    payload: web::Json<UpdateTariffRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pool = &data.db_pool;
+    let conn = pool.get().await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
+
+    let current = conn
+        .interact(move |conn| tariffs::load_tariff(conn, user_id))
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database query failed"))?
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get tariff"))?;
+
+    let updated = current.apply(payload.into_inner())?;
+
+    let saved = conn
+        .interact(move |conn| tariffs::save_tariff(conn, &updated))
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database update failed"))?
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to save tariff"))?;
+
+    log::info!("User {} switched to tariff {}", user_id, saved.kind);
+
+    // La tarifa ja està desada: si la replanificació falla es tornarà a fer amb els preus de demà
+    let rebuild = match schedule_builder::rebuild_user_schedules(&data.db_pool, user_id).await {
+        Ok(result) => {
+            data.hub.send_to_user(user_id, &WsEvent::schedules_rebuilt(&result));
+            Some(json!({
+                "schedules": result.schedules.len(),
+                "failures": result.failures
+            }))
+        }
+        Err(e) => {
+            log::error!("Failed to rebuild schedules for user {} after tariff change: {}", user_id, e);
+            None
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(json!({
+        "tariff": saved,
+        "rebuild": rebuild
+    })))
+}
@@ -0,0 +1,173 @@
+use crate::{
+    middleware::auth::AuthUser,
+    models::{
+        command::validate_command,
+        device::Device,
+        task::{CreateTaskRequest, OneShotTask, TaskStatus},
+    },
+    schema::{devices, one_shot_tasks},
+    services::tasks,
+    utils::errors::ValidationErrors,
+    AppState,
+};
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use diesel::prelude::*;
+use serde_json::json;
+use uuid::Uuid;
+
+// Llistar les tasques puntuals que encara no han acabat
+pub async fn list_tasks(
+    AuthUser(user_id): AuthUser,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pool = &data.db_pool;
+    let conn = pool.get().await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
+
+    let user_tasks = conn
+        .interact(move |conn| {
+            one_shot_tasks::table
+                .filter(one_shot_tasks::user_id.eq(user_id))
+                .filter(one_shot_tasks::status.eq_any([
+                    TaskStatus::Waiting.as_str(),
+                    TaskStatus::Scheduled.as_str(),
+                    TaskStatus::Started.as_str(),
+                ]))
+                .order(one_shot_tasks::deadline.asc())
+                .load::<OneShotTask>(conn)
+        })
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database query failed"))?
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get tasks"))?;
+
+    Ok(HttpResponse::Ok().json(user_tasks))
+}
+
+// Crear una tasca puntual. El bloc es tria de seguida amb els preus disponibles i
+// es replanifica si se'n publiquen de nous abans que comenci.
+pub async fn create_task(
+    AuthUser(user_id): AuthUser,
+    payload: web::Json<CreateTaskRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let now = Utc::now();
+    let new_task = payload.into_inner().into_task(user_id, now)?;
+
+    let pool = &data.db_pool;
+    let conn = pool.get().await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
+
+    let device_id = new_task.device_id;
+    let device = conn
+        .interact(move |conn| {
+            devices::table
+                .find(device_id)
+                .filter(devices::user_id.eq(user_id))
+                .first::<Device>(conn)
+                .optional()
+        })
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database query failed"))?
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to verify device"))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Device not found"))?;
+
+    // La tasca només engega el dispositiu
+    validate_command("on_off", &json!({ "on": true }), &device).map_err(|e| {
+        let reason: Vec<String> = e.fields.into_iter().map(|f| f.message).collect();
+        ValidationErrors::single("device_id", reason.join("; "))
+    })?;
+
+    let task = conn
+        .interact(move |conn| {
+            let task = diesel::insert_into(one_shot_tasks::table)
+                .values(&new_task)
+                .get_result::<OneShotTask>(conn)?;
+            tasks::plan_task(conn, &task, now)
+        })
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database insert failed"))?
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to create task"))?;
+
+    log::info!(
+        "User {} created task {} for device {} (start: {:?})",
+        user_id,
+        task.id,
+        task.device_id,
+        task.planned_start
+    );
+
+    Ok(HttpResponse::Created().json(task))
+}
+
+// Obtenir una tasca, amb l'hora d'inici triada
+pub async fn get_task(
+    AuthUser(user_id): AuthUser,
+    path: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pool = &data.db_pool;
+    let conn = pool.get().await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
+
+    let task_id = path.into_inner();
+    let task = conn
+        .interact(move |conn| {
+            one_shot_tasks::table
+                .find(task_id)
+                .filter(one_shot_tasks::user_id.eq(user_id))
+                .first::<OneShotTask>(conn)
+                .optional()
+        })
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database query failed"))?
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get task"))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Task not found"))?;
+
+    Ok(HttpResponse::Ok().json(task))
+}
+
+// Cancel·lar una tasca que encara no ha engegat el dispositiu
+pub async fn cancel_task(
+    AuthUser(user_id): AuthUser,
+    path: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pool = &data.db_pool;
+    let conn = pool.get().await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database connection failed"))?;
+
+    let task_id = path.into_inner();
+    let (cancelled, exists) = conn
+        .interact(move |conn| {
+            let owned = one_shot_tasks::table
+                .find(task_id)
+                .filter(one_shot_tasks::user_id.eq(user_id));
+            let cancelled = diesel::update(
+                owned.filter(one_shot_tasks::status.eq_any(TaskStatus::OPEN.map(|s| s.as_str()))),
+            )
+            .set((
+                one_shot_tasks::status.eq(TaskStatus::Cancelled.as_str()),
+                one_shot_tasks::updated_at.eq(Utc::now()),
+            ))
+            .execute(conn)?;
+            let exists = owned.count().get_result::<i64>(conn)?;
+            Ok::<_, diesel::result::Error>((cancelled, exists))
+        })
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database operation failed"))?
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to cancel task"))?;
+
+    if exists == 0 {
+        return Err(actix_web::error::ErrorNotFound("Task not found"));
+    }
+    if cancelled == 0 {
+        return Err(actix_web::error::ErrorConflict("Task has already started or finished"));
+    }
+
+    log::info!("User {} cancelled task {}", user_id, task_id);
+
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Task cancelled successfully"
+    })))
+}
@@ -1,34 +1,230 @@
+use crate::{
+    middleware::auth::authenticate_token,
+    models::command::CommandResult,
+    services::{
+        command_processor,
+        ws_hub::{ClientMessage, WsEvent},
+    },
+    AppState,
+};
 use actix_web::{web, HttpRequest, HttpResponse};
-use actix_ws::Message;
+use actix_ws::{CloseCode, CloseReason, Message, MessageStream, Session};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
 
+// Interval de ping del servidor i temps màxim sense notícies del client
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(45);
+// Temps per enviar el missatge d'autenticació si no hi ha token a la URL
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Deserialize)]
+pub struct WsQuery {
+    pub token: Option<String>,
+}
+
+// WebSocket d'esdeveniments en temps real. El token es pot passar a `?token=`,
+// al header `Authorization` o com a primer missatge `{"type":"auth","token":"..."}`.
+// L'app mòbil hi pot rebre les comandes enviant `{"type":"subscribe_commands"}` i
+// retornar-ne el resultat amb `{"type":"command_result", ...}`.
 pub async fn websocket_handler(
     req: HttpRequest,
     stream: web::Payload,
+    query: web::Query<WsQuery>,
+    data: web::Data<AppState>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let (res, mut session, mut msg_stream) = actix_ws::handle(&req, stream)?;
-    
-    // TODO: Implementar lògica del WebSocket
-    // - Autenticació via token
-    // - Subscripció a canvis d'estat de dispositius
-    // - Notificacions en temps real
-    
+    let token = query.into_inner().token.or_else(|| {
+        req.headers()
+            .get("Authorization")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .map(str::to_string)
+    });
+
+    // Un token invàlid es rebutja abans d'obrir el WebSocket
+    let user_id = match token {
+        Some(token) => Some(authenticate_token(&token, &data.jwt_secret)?),
+        None => None,
+    };
+
+    let (res, session, msg_stream) = actix_ws::handle(&req, stream)?;
+
     actix_web::rt::spawn(async move {
-        while let Some(Ok(msg)) = msg_stream.recv().await {
-            match msg {
-                Message::Text(text) => {
-                    // Handle text message
-                    let _ = session.text(text).await;
+        run_session(data, user_id, session, msg_stream).await;
+    });
+
+    Ok(res)
+}
+
+async fn run_session(
+    data: web::Data<AppState>,
+    user_id: Option<Uuid>,
+    mut session: Session,
+    mut msg_stream: MessageStream,
+) {
+    let user_id = match user_id {
+        Some(user_id) => user_id,
+        None => match wait_for_auth(&data.jwt_secret, &mut session, &mut msg_stream).await {
+            Some(user_id) => user_id,
+            None => {
+                let _ = session.close(Some(CloseCode::Policy.into())).await;
+                return;
+            }
+        },
+    };
+
+    let (connection_id, mut events) = data.hub.register(user_id);
+    log::info!("WebSocket {} opened for user {}", connection_id, user_id);
+
+    let close_reason = serve(&data, user_id, connection_id, &mut session, &mut msg_stream, &mut events).await;
+
+    data.hub.unregister(user_id, connection_id);
+    log::info!("WebSocket {} closed for user {}", connection_id, user_id);
+    let _ = session.close(close_reason).await;
+}
+
+async fn wait_for_auth(
+    jwt_secret: &str,
+    session: &mut Session,
+    msg_stream: &mut MessageStream,
+) -> Option<Uuid> {
+    let first = tokio::time::timeout(AUTH_TIMEOUT, msg_stream.recv()).await;
+
+    let error = match first {
+        Ok(Some(Ok(Message::Text(text)))) => match serde_json::from_str::<ClientMessage>(&text) {
+            Ok(ClientMessage::Auth { token }) => match authenticate_token(&token, jwt_secret) {
+                Ok(user_id) => return Some(user_id),
+                Err(_) => "Invalid token",
+            },
+            _ => "Expected an auth message",
+        },
+        Err(_) => "Authentication timeout",
+        _ => "Expected an auth message",
+    };
+
+    send_event(session, &WsEvent::Error { message: error.to_string() }).await;
+    None
+}
+
+// Bucle principal: reenvia els esdeveniments del hub, respon al client i tanca
+// la connexió si deixa de respondre
+async fn serve(
+    data: &AppState,
+    user_id: Uuid,
+    connection_id: Uuid,
+    session: &mut Session,
+    msg_stream: &mut MessageStream,
+    events: &mut tokio::sync::mpsc::UnboundedReceiver<String>,
+) -> Option<CloseReason> {
+    if !send_event(session, &WsEvent::Connected { user_id }).await {
+        return None;
+    }
+
+    let mut last_seen = Instant::now();
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+    loop {
+        tokio::select! {
+            msg = msg_stream.recv() => {
+                let Some(Ok(msg)) = msg else {
+                    return None;
+                };
+                last_seen = Instant::now();
+
+                match msg {
+                    Message::Text(text) => {
+                        let reply = match serde_json::from_str::<ClientMessage>(&text) {
+                            Ok(ClientMessage::Ping) => Some(WsEvent::Pong),
+                            Ok(ClientMessage::Auth { .. }) => Some(WsEvent::Error {
+                                message: "Already authenticated".to_string(),
+                            }),
+                            Ok(ClientMessage::SubscribeCommands) => {
+                                subscribe_commands(data, user_id, connection_id).await
+                            }
+                            Ok(ClientMessage::CommandResult(result)) => {
+                                handle_command_result(data, user_id, result).await
+                            }
+                            Err(e) => Some(WsEvent::Error {
+                                message: format!("Invalid message: {}", e),
+                            }),
+                        };
+                        if let Some(reply) = reply {
+                            if !send_event(session, &reply).await {
+                                return None;
+                            }
+                        }
+                    }
+                    Message::Ping(bytes) if session.pong(&bytes).await.is_err() => return None,
+                    Message::Close(reason) => return reason,
+                    _ => {}
                 }
-                Message::Binary(_) => {
-                    // Handle binary message
+            }
+            event = events.recv() => {
+                let text = event?;
+                if session.text(text).await.is_err() {
+                    return None;
                 }
-                Message::Ping(bytes) => {
-                    let _ = session.pong(&bytes).await;
+            }
+            _ = heartbeat.tick() => {
+                if last_seen.elapsed() > CLIENT_TIMEOUT {
+                    log::info!("Closing idle WebSocket for user {}", user_id);
+                    return Some(CloseReason {
+                        code: CloseCode::Away,
+                        description: Some("Idle timeout".to_string()),
+                    });
+                }
+                if session.ping(b"").await.is_err() {
+                    return None;
                 }
-                _ => {}
             }
         }
-    });
-    
-    Ok(res)
+    }
+}
+
+// A partir d'ara les comandes d'aquest usuari es lliuren per aquesta connexió;
+// les que ja esperaven a la cua s'envien immediatament
+async fn subscribe_commands(data: &AppState, user_id: Uuid, connection_id: Uuid) -> Option<WsEvent> {
+    data.hub.subscribe_commands(user_id, connection_id);
+
+    match command_processor::flush_to_websocket(&data.db_pool, &data.hub, user_id).await {
+        Ok(sent) => {
+            log::info!("WebSocket {} subscribed to commands ({} pending sent)", connection_id, sent);
+            None
+        }
+        Err(e) => {
+            log::error!("Failed to send pending commands to user {}: {}", user_id, e);
+            Some(WsEvent::Error {
+                message: "Failed to send pending commands".to_string(),
+            })
+        }
+    }
+}
+
+// Resultat d'una comanda rebut pel WebSocket, amb les mateixes regles que l'endpoint HTTP
+async fn handle_command_result(data: &AppState, user_id: Uuid, result: CommandResult) -> Option<WsEvent> {
+    let command_id = result.command_id;
+    let new_state = result.new_state.clone();
+
+    match command_processor::record_result(&data.db_pool, user_id, result).await {
+        Ok(command) => {
+            // L'estat nou arriba a totes les connexions de l'usuari, inclosa aquesta
+            command_processor::publish_result(&data.hub, &command, new_state);
+            None
+        }
+        Err(e) => {
+            log::warn!("Rejected WebSocket result for command {}: {}", command_id, e);
+            Some(WsEvent::CommandRejected {
+                command_id,
+                message: e.to_string(),
+            })
+        }
+    }
+}
+
+async fn send_event(session: &mut Session, event: &WsEvent) -> bool {
+    match serde_json::to_string(event) {
+        Ok(text) => session.text(text).await.is_ok(),
+        Err(_) => false,
+    }
 }
@@ -7,11 +7,16 @@ mod utils;
 
 use actix_cors::Cors;
 use actix_session::{storage::CookieSessionStore, SessionMiddleware};
-use actix_web::{cookie::Key, middleware::Logger, web, App, HttpServer};
+use actix_web::{
+    cookie::Key,
+    middleware::{from_fn, Logger},
+    web, App, HttpServer,
+};
 use deadpool_diesel::postgres::{Manager, Pool};
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use dotenv::dotenv;
-use std::env;
+use services::{price_cache::PriceCache, push_notifier::PushNotifier, ws_hub::WsHub};
+use std::{env, sync::Arc};
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
 
@@ -25,6 +30,9 @@ pub struct AppState {
     pub google_client_secret: String,
     pub fcm_server_key: String,
     pub encryption_key: String,
+    pub notifier: Arc<dyn PushNotifier>,
+    pub hub: Arc<WsHub>,
+    pub prices: Arc<PriceCache>,
 }
 
 #[actix_web::main]
@@ -51,6 +59,57 @@ async fn main() -> std::io::Result<()> {
 
     log::info!("Database migrations completed successfully");
 
+    // Connexions WebSocket per als esdeveniments en temps real
+    let hub = Arc::new(WsHub::new());
+
+    // Preus ja publicats, en memòria per a l'API de preus
+    let prices = Arc::new(PriceCache::new());
+
+    // Descàrrega periòdica de preus en segon pla
+    match services::price_fetcher::source_from_env() {
+        Ok(source) => {
+            tokio::spawn(services::price_fetcher::run_price_scheduler(
+                db_pool.clone(),
+                source,
+                hub.clone(),
+                prices.clone(),
+            ));
+        }
+        Err(e) => log::error!("Price fetching disabled: {}", e),
+    }
+
+    // Notificacions push per despertar l'app quan hi ha comandes
+    let fcm_server_key = env::var("FCM_SERVER_KEY").unwrap_or_default();
+    let notifier = services::push_notifier::notifier_from_key(&fcm_server_key);
+
+    // Execució dels horaris: encua comandes a cada canvi de slot
+    tokio::spawn(services::schedule_executor::run_schedule_executor(
+        db_pool.clone(),
+        notifier.clone(),
+        hub.clone(),
+    ));
+
+    // Cicle de vida de les comandes: caducitat i reintents
+    tokio::spawn(services::command_processor::run_command_processor(
+        db_pool.clone(),
+        notifier.clone(),
+        hub.clone(),
+    ));
+
+    // Tasques puntuals: engegada al bloc triat i caducitat
+    tokio::spawn(services::tasks::run_task_executor(
+        db_pool.clone(),
+        notifier.clone(),
+        hub.clone(),
+    ));
+
+    // Avisos de trams de preus barats o cars
+    tokio::spawn(services::price_alerts::run_price_alerts(
+        db_pool.clone(),
+        notifier.clone(),
+        hub.clone(),
+    ));
+
     // Configuració de l'aplicació
     let app_state = AppState {
         db_pool: db_pool.clone(),
@@ -59,8 +118,11 @@ async fn main() -> std::io::Result<()> {
             .expect("GOOGLE_CLIENT_ID must be set"),
         google_client_secret: env::var("GOOGLE_CLIENT_SECRET")
             .expect("GOOGLE_CLIENT_SECRET must be set"),
-        fcm_server_key: env::var("FCM_SERVER_KEY").unwrap_or_default(),
+        fcm_server_key,
         encryption_key: env::var("ENCRYPTION_KEY").expect("ENCRYPTION_KEY must be set"),
+        notifier,
+        hub,
+        prices,
     };
 
     // Configuració del servidor
@@ -99,6 +161,7 @@ async fn main() -> std::io::Result<()> {
 
         App::new()
             .app_data(web::Data::new(app_state.clone()))
+            .app_data(web::JsonConfig::default().error_handler(utils::errors::json_error_handler))
             .wrap(cors)
             .wrap(Logger::default())
             .wrap(SessionMiddleware::new(
@@ -115,37 +178,75 @@ async fn main() -> std::io::Result<()> {
                 )
                 // Mobile sync routes
                 .service(web::scope("/mobile")
-                    // TODO: Add authentication middleware
+                    .wrap(from_fn(middleware::auth::require_auth))
                     .route("/sync", web::post().to(handlers::mobile::sync_devices))
                     .route("/heartbeat", web::post().to(handlers::mobile::heartbeat))
                     .route("/command_result", web::post().to(handlers::mobile::command_result))
                 )
                 // Device routes
                 .service(web::scope("/devices")
-                    // TODO: Add authentication middleware
+                    .wrap(from_fn(middleware::auth::require_auth))
                     .route("", web::get().to(handlers::device::list_devices))
                     .route("/{device_id}", web::get().to(handlers::device::get_device))
                     .route("/{device_id}/state", web::get().to(handlers::device::get_device_state))
                     .route("/{device_id}/command", web::post().to(handlers::device::send_command))
                 )
+                // Command routes
+                .service(web::scope("/commands")
+                    .wrap(from_fn(middleware::auth::require_auth))
+                    .route("/{command_id}", web::get().to(handlers::command::get_command))
+                )
                 // Rules routes
                 .service(web::scope("/rules")
-                    // TODO: Add authentication middleware
+                    .wrap(from_fn(middleware::auth::require_auth))
                     .route("", web::get().to(handlers::rule::list_rules))
                     .route("", web::post().to(handlers::rule::create_rule))
+                    .route("/preview", web::post().to(handlers::rule::preview_schedule))
                     .route("/{rule_id}", web::get().to(handlers::rule::get_rule))
                     .route("/{rule_id}", web::put().to(handlers::rule::update_rule))
                     .route("/{rule_id}", web::delete().to(handlers::rule::delete_rule))
-                    // TODO: Implementar preview de schedule
-                    // .route("/preview", web::post().to(handlers::rule::preview_schedule))
                 )
                 // Schedules routes
                 .service(web::scope("/schedules")
-                    // TODO: Add authentication middleware
+                    .wrap(from_fn(middleware::auth::require_auth))
                     .route("", web::get().to(handlers::schedule::list_schedules))
                     .route("/today", web::get().to(handlers::schedule::get_today_schedules))
                     .route("/rebuild", web::post().to(handlers::schedule::rebuild_schedules))
                 )
+                // Prices routes
+                .service(web::scope("/prices")
+                    .wrap(from_fn(middleware::auth::require_auth))
+                    .route("", web::get().to(handlers::price::list_prices))
+                    .route("/today", web::get().to(handlers::price::get_today_prices))
+                    .route("/tomorrow", web::get().to(handlers::price::get_tomorrow_prices))
+                )
+                // Price alert subscription routes
+                .service(web::scope("/alerts")
+                    .wrap(from_fn(middleware::auth::require_auth))
+                    .route("", web::get().to(handlers::alert::get_alerts))
+                    .route("", web::put().to(handlers::alert::update_alerts))
+                    .route("", web::delete().to(handlers::alert::delete_alerts))
+                )
+                // User settings routes
+                .service(web::scope("/settings")
+                    .wrap(from_fn(middleware::auth::require_auth))
+                    .route("", web::get().to(handlers::settings::get_settings))
+                    .route("", web::put().to(handlers::settings::update_settings))
+                )
+                // Contracted tariff routes
+                .service(web::scope("/tariff")
+                    .wrap(from_fn(middleware::auth::require_auth))
+                    .route("", web::get().to(handlers::tariff::get_tariff))
+                    .route("", web::put().to(handlers::tariff::update_tariff))
+                )
+                // One-shot task routes
+                .service(web::scope("/tasks")
+                    .wrap(from_fn(middleware::auth::require_auth))
+                    .route("", web::get().to(handlers::task::list_tasks))
+                    .route("", web::post().to(handlers::task::create_task))
+                    .route("/{task_id}", web::get().to(handlers::task::get_task))
+                    .route("/{task_id}", web::delete().to(handlers::task::cancel_task))
+                )
                 // WebSocket for real-time updates
                 .route("/ws", web::get().to(handlers::websocket::websocket_handler))
             )
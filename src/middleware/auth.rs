@@ -1,27 +1,41 @@
 use crate::{handlers::auth::verify_jwt, AppState};
 use actix_web::{
-    dev::Payload,
-    error::ErrorUnauthorized,
+    body::MessageBody,
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    error::{ErrorInternalServerError, ErrorUnauthorized},
+    middleware::Next,
     web, Error, FromRequest, HttpMessage, HttpRequest,
 };
 use futures_util::future::{ready, Ready};
 use uuid::Uuid;
 
-pub struct RequireAuth;
+// Valida un JWT i retorna l'usuari que conté
+pub fn authenticate_token(token: &str, secret: &str) -> Result<Uuid, Error> {
+    let claims = verify_jwt(token, secret)?;
+    Uuid::parse_str(&claims.sub).map_err(|_| ErrorUnauthorized("Invalid user ID in token"))
+}
 
-impl FromRequest for RequireAuth {
-    type Error = Error;
-    type Future = Ready<Result<Self, Self::Error>>;
+// Middleware que exigeix un header `Authorization: Bearer <JWT>` vàlid
+// i desa el user_id a les extensions de la petició
+pub async fn require_auth(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let data = req
+        .app_data::<web::Data<AppState>>()
+        .ok_or_else(|| ErrorInternalServerError("Application state not configured"))?;
 
-    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
-        // TODO: Implementar autenticació completa
-        // Per ara només comprovem que hi ha un user_id a les extensions
-        if req.extensions().get::<Uuid>().is_some() {
-            ready(Ok(RequireAuth))
-        } else {
-            ready(Err(ErrorUnauthorized("Not authenticated")))
-        }
-    }
+    let token = req
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .ok_or_else(|| ErrorUnauthorized("Missing or invalid token"))?;
+
+    let user_id = authenticate_token(token, &data.jwt_secret)?;
+    req.extensions_mut().insert(user_id);
+
+    next.call(req).await
 }
 
 // Extractor per obtenir el user_id de les extensions
@@ -38,4 +52,4 @@ impl FromRequest for AuthUser {
             ready(Err(ErrorUnauthorized("Not authenticated")))
         }
     }
-}
\ No newline at end of file
+}
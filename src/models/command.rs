@@ -1,27 +1,56 @@
-use crate::schema::{commands, automation_logs};
+use crate::models::device::Device;
+use crate::schema::{command_events, commands, automation_logs};
+use crate::utils::errors::ValidationErrors;
 use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum CommandStatus {
     Queued,
     Sent,
     Acked,
     Failed,
+    Expired,
+    Superseded,
 }
 
-impl ToString for CommandStatus {
-    fn to_string(&self) -> String {
-        match self {
-            CommandStatus::Queued => "queued".to_string(),
-            CommandStatus::Sent => "sent".to_string(),
-            CommandStatus::Acked => "acked".to_string(),
-            CommandStatus::Failed => "failed".to_string(),
-        }
+impl std::fmt::Display for CommandStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            CommandStatus::Queued => "queued",
+            CommandStatus::Sent => "sent",
+            CommandStatus::Acked => "acked",
+            CommandStatus::Failed => "failed",
+            CommandStatus::Expired => "expired",
+            CommandStatus::Superseded => "superseded",
+        };
+        f.write_str(status)
+    }
+}
+
+impl CommandStatus {
+    // Transicions permeses: queued -> sent -> acked/failed.
+    // Una comanda acked és final; una de failed només es pot tornar a encuar.
+    // Una comanda sent sense resultat passa a expired i també és final: pot haver-se
+    // executat, així que tornar-la a enviar trencaria el lliurament com a molt una vegada.
+    // Una comanda failed que una de més nova deixa obsoleta passa a superseded, també final.
+    // Una comanda failed d'un horari el slot del qual ja s'ha acabat passa a expired.
+    pub fn can_transition_to(&self, next: &CommandStatus) -> bool {
+        matches!(
+            (self, next),
+            (CommandStatus::Queued, CommandStatus::Sent)
+                | (CommandStatus::Queued, CommandStatus::Failed)
+                | (CommandStatus::Sent, CommandStatus::Acked)
+                | (CommandStatus::Sent, CommandStatus::Failed)
+                | (CommandStatus::Sent, CommandStatus::Expired)
+                | (CommandStatus::Failed, CommandStatus::Queued)
+                | (CommandStatus::Failed, CommandStatus::Superseded)
+                | (CommandStatus::Failed, CommandStatus::Expired)
+        )
     }
 }
 
@@ -32,6 +61,8 @@ impl From<String> for CommandStatus {
             "sent" => CommandStatus::Sent,
             "acked" => CommandStatus::Acked,
             "failed" => CommandStatus::Failed,
+            "expired" => CommandStatus::Expired,
+            "superseded" => CommandStatus::Superseded,
             _ => CommandStatus::Queued,
         }
     }
@@ -51,6 +82,7 @@ pub struct Command {
     pub created_at: DateTime<Utc>,
     pub executed_at: Option<DateTime<Utc>>,
     pub updated_at: DateTime<Utc>,
+    pub schedule_id: Option<Uuid>,
 }
 
 #[derive(Debug, Clone, Deserialize, Insertable)]
@@ -63,6 +95,26 @@ pub struct NewCommand {
     pub payload_json: JsonValue,
     pub status: String,
     pub retry_count: i32,
+    pub schedule_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable, Identifiable)]
+#[diesel(table_name = command_events)]
+pub struct CommandEvent {
+    pub id: Uuid,
+    pub command_id: Uuid,
+    pub status: String,
+    pub message: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize, Insertable)]
+#[diesel(table_name = command_events)]
+pub struct NewCommandEvent {
+    pub id: Uuid,
+    pub command_id: Uuid,
+    pub status: String,
+    pub message: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable, Identifiable)]
@@ -122,6 +174,69 @@ pub struct TemperaturePayload {
     pub unit: String, // "celsius" o "fahrenheit"
 }
 
+// Valida el payload segons el tipus de comanda i les capacitats del dispositiu.
+// Retorna el payload normalitzat o el camp que el dispositiu no pot executar.
+pub fn validate_command(
+    command_type: &str,
+    payload: &JsonValue,
+    device: &Device,
+) -> Result<JsonValue, ValidationErrors> {
+    let (required_trait, normalized) = match command_type {
+        "on_off" => {
+            let payload: OnOffPayload = serde_json::from_value(payload.clone())
+                .map_err(|e| ValidationErrors::single("payload", format!("Invalid on_off payload: {}", e)))?;
+            ("OnOff", serde_json::to_value(payload))
+        }
+        "brightness" => {
+            let payload: BrightnessPayload = serde_json::from_value(payload.clone())
+                .map_err(|e| ValidationErrors::single("payload", format!("Invalid brightness payload: {}", e)))?;
+            if payload.brightness > 100 {
+                return Err(ValidationErrors::single("payload.brightness", "must be between 0 and 100"));
+            }
+            ("Brightness", serde_json::to_value(payload))
+        }
+        "temperature" => {
+            let payload: TemperaturePayload = serde_json::from_value(payload.clone())
+                .map_err(|e| ValidationErrors::single("payload", format!("Invalid temperature payload: {}", e)))?;
+            let range = match payload.unit.as_str() {
+                "celsius" => 5.0..=35.0,
+                "fahrenheit" => 41.0..=95.0,
+                other => {
+                    return Err(ValidationErrors::single(
+                        "payload.unit",
+                        format!("Unknown temperature unit '{}'", other),
+                    ))
+                }
+            };
+            if !range.contains(&payload.temperature) {
+                return Err(ValidationErrors::single(
+                    "payload.temperature",
+                    format!("must be between {} and {} {}", range.start(), range.end(), payload.unit),
+                ));
+            }
+            ("TemperatureSetting", serde_json::to_value(payload))
+        }
+        other => {
+            return Err(ValidationErrors::single(
+                "command_type",
+                format!("Unknown command type '{}'", other),
+            ))
+        }
+    };
+
+    if !device.has_trait(required_trait) {
+        return Err(ValidationErrors::single(
+            "command_type",
+            format!(
+                "Device '{}' does not support {} commands (missing {} capability)",
+                device.name, command_type, required_trait
+            ),
+        ));
+    }
+
+    normalized.map_err(|e| ValidationErrors::single("payload", e.to_string()))
+}
+
 impl Command {
     pub fn get_status(&self) -> CommandStatus {
         CommandStatus::from(self.status.clone())
@@ -132,12 +247,69 @@ impl Command {
     }
     
     pub fn should_expire(&self) -> bool {
-        // Expira després de 5 minuts sense ser executat
-        if let CommandStatus::Queued = self.get_status() {
-            let elapsed = Utc::now().signed_duration_since(self.created_at);
-            elapsed.num_minutes() > 5
-        } else {
-            false
+        // Expira després de 5 minuts sense ser lliurada (queued) o sense resultat (sent).
+        // Es compta des de l'últim canvi d'estat perquè els reintents tornin a començar.
+        match self.get_status() {
+            CommandStatus::Queued | CommandStatus::Sent => {
+                let elapsed = Utc::now().signed_duration_since(self.updated_at);
+                elapsed.num_minutes() >= 5
+            }
+            _ => false,
+        }
+    }
+    
+    // Moment a partir del qual una comanda fallida es pot tornar a encuar (30s, 60s, 120s...)
+    pub fn retry_due_at(&self) -> DateTime<Utc> {
+        let backoff = 30i64 << self.retry_count.clamp(0, 10);
+        self.updated_at + chrono::Duration::seconds(backoff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_sent_command_never_goes_back_to_the_queue() {
+        assert!(!CommandStatus::Sent.can_transition_to(&CommandStatus::Queued));
+        assert!(CommandStatus::Sent.can_transition_to(&CommandStatus::Expired));
+        assert!(CommandStatus::Failed.can_transition_to(&CommandStatus::Queued));
+    }
+
+    #[test]
+    fn a_failed_command_can_expire_but_a_queued_one_cannot() {
+        assert!(CommandStatus::Failed.can_transition_to(&CommandStatus::Expired));
+        assert!(!CommandStatus::Queued.can_transition_to(&CommandStatus::Expired));
+    }
+
+    #[test]
+    fn expired_acked_and_superseded_commands_are_final() {
+        let all = [
+            CommandStatus::Queued,
+            CommandStatus::Sent,
+            CommandStatus::Acked,
+            CommandStatus::Failed,
+            CommandStatus::Expired,
+            CommandStatus::Superseded,
+        ];
+        for next in &all {
+            assert!(!CommandStatus::Expired.can_transition_to(next));
+            assert!(!CommandStatus::Acked.can_transition_to(next));
+            assert!(!CommandStatus::Superseded.can_transition_to(next));
+        }
+    }
+
+    #[test]
+    fn only_failed_commands_can_be_superseded() {
+        assert!(CommandStatus::Failed.can_transition_to(&CommandStatus::Superseded));
+        assert!(!CommandStatus::Queued.can_transition_to(&CommandStatus::Superseded));
+        assert!(!CommandStatus::Sent.can_transition_to(&CommandStatus::Superseded));
+    }
+
+    #[test]
+    fn status_round_trips_through_its_string() {
+        for status in [CommandStatus::Sent, CommandStatus::Expired, CommandStatus::Superseded] {
+            assert_eq!(CommandStatus::from(status.to_string()), status);
         }
     }
 }
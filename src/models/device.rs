@@ -96,3 +96,34 @@ pub struct StructureSync {
     pub google_structure_id: String,
     pub name: String,
 }
+
+impl Device {
+    // Comprova si el dispositiu té una capacitat (trait de Google Home).
+    // Accepta `["OnOff"]`, `{"traits": ["on_off"]}` o `"action.devices.traits.OnOff"`.
+    pub fn has_trait(&self, name: &str) -> bool {
+        let traits = match &self.capabilities_json {
+            JsonValue::Array(traits) => traits,
+            JsonValue::Object(map) => match map.get("traits") {
+                Some(JsonValue::Array(traits)) => traits,
+                _ => return false,
+            },
+            _ => return false,
+        };
+
+        let wanted = normalize_trait(name);
+        traits
+            .iter()
+            .filter_map(JsonValue::as_str)
+            .any(|t| normalize_trait(t) == wanted)
+    }
+}
+
+fn normalize_trait(name: &str) -> String {
+    name.rsplit('.')
+        .next()
+        .unwrap_or(name)
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
@@ -3,6 +3,9 @@ pub mod device;
 pub mod rule;
 pub mod schedule;
 pub mod command;
+pub mod price;
+pub mod tariff;
+pub mod task;
 
 pub use user::*;
 pub use device::*;
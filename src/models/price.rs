@@ -0,0 +1,441 @@
+use crate::models::{schedule::PricePeriod, tariff::TariffKind};
+use crate::schema::price_alerts;
+use crate::utils::errors::ValidationErrors;
+use chrono::{DateTime, NaiveDate, Utc};
+use diesel::prelude::*;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Franja de preu de cada període respecte la resta del dia
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceTier {
+    Cheap,
+    Normal,
+    Expensive,
+}
+
+impl PriceTier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PriceTier::Cheap => "cheap",
+            PriceTier::Normal => "normal",
+            PriceTier::Expensive => "expensive",
+        }
+    }
+}
+
+// Límits de les franges: `cheap` fins a `cheap_max`, `expensive` a partir de `expensive_min`
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct TierThresholds {
+    pub cheap_max: Decimal,
+    pub expensive_min: Decimal,
+}
+
+impl TierThresholds {
+    pub fn classify(&self, price: Decimal) -> PriceTier {
+        if price <= self.cheap_max {
+            PriceTier::Cheap
+        } else if price >= self.expensive_min {
+            PriceTier::Expensive
+        } else {
+            PriceTier::Normal
+        }
+    }
+}
+
+// Com es classifiquen els preus: per percentils del dia o per llindars absoluts (€/kWh)
+#[derive(Debug, Clone, Copy)]
+pub struct TierConfig {
+    pub cheap_percentile: u8,
+    pub expensive_percentile: u8,
+    pub cheap_threshold: Option<Decimal>,
+    pub expensive_threshold: Option<Decimal>,
+}
+
+impl Default for TierConfig {
+    // Terços del dia
+    fn default() -> Self {
+        Self {
+            cheap_percentile: 33,
+            expensive_percentile: 67,
+            cheap_threshold: None,
+            expensive_threshold: None,
+        }
+    }
+}
+
+impl TierConfig {
+    pub fn thresholds(&self, prices: &[PricePeriod]) -> Option<TierThresholds> {
+        Some(TierThresholds {
+            cheap_max: self
+                .cheap_threshold
+                .or_else(|| percentile(prices, self.cheap_percentile))?,
+            expensive_min: self
+                .expensive_threshold
+                .or_else(|| percentile(prices, self.expensive_percentile))?,
+        })
+    }
+}
+
+// Percentil `p` dels preus pel mètode del rang més proper. None si no hi ha preus.
+pub fn percentile(prices: &[PricePeriod], p: u8) -> Option<Decimal> {
+    let mut sorted: Vec<Decimal> = prices.iter().map(|p| p.price).collect();
+    sorted.sort();
+
+    let rank = (p as usize * sorted.len()).div_ceil(100).max(1);
+    sorted.get(rank.min(sorted.len()).checked_sub(1)?).copied()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PricePoint {
+    #[serde(flatten)]
+    pub period: PricePeriod,
+    pub tier: PriceTier,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PriceStats {
+    pub min: PricePeriod,
+    pub max: PricePeriod,
+    pub mean: Decimal, // Ponderada per la durada dels períodes
+    pub cheapest_hours: Vec<PricePeriod>, // En ordre cronològic
+    pub tiers: TierThresholds,
+}
+
+// DTO amb els preus d'un dia en la zona horària i la unitat de l'usuari
+#[derive(Debug, Clone, Serialize)]
+pub struct DayPricesResponse {
+    pub date: NaiveDate,
+    pub timezone: String,
+    pub tariff: TariffKind,
+    pub unit: String, // "EUR/kWh" o "cEUR/kWh"
+    pub resolution_minutes: u32,
+    pub prices: Vec<PricePoint>,
+    pub stats: PriceStats,
+}
+
+impl DayPricesResponse {
+    // `currency_display` és la preferència de l'usuari ("euros" o "cents").
+    // Retorna None si no hi ha cap preu.
+    pub fn new(
+        date: NaiveDate,
+        timezone: &str,
+        tariff: TariffKind,
+        prices: Vec<PricePeriod>,
+        cheapest_hours: u8,
+        tiers: &TierConfig,
+        currency_display: &str,
+    ) -> Option<Self> {
+        // Les franges es calculen en €/kWh, que és la unitat dels llindars
+        let thresholds = tiers.thresholds(&prices)?;
+        let (unit, factor) = if currency_display == "cents" {
+            ("cEUR/kWh", Decimal::from(100))
+        } else {
+            ("EUR/kWh", Decimal::ONE)
+        };
+        let display = |price: Decimal| (price * factor).normalize();
+
+        let prices: Vec<PricePoint> = prices
+            .into_iter()
+            .map(|period| PricePoint {
+                tier: thresholds.classify(period.price),
+                period: PricePeriod {
+                    price: display(period.price),
+                    ..period
+                },
+            })
+            .collect();
+        let periods: Vec<PricePeriod> = prices.iter().map(|p| p.period.clone()).collect();
+
+        let resolution_minutes = periods.first()?.minutes;
+        let min = periods.iter().min_by_key(|p| (p.price, p.start))?.clone();
+        let max = periods.iter().max_by_key(|p| (p.price, std::cmp::Reverse(p.start)))?.clone();
+
+        let total_minutes: u32 = periods.iter().map(|p| p.minutes).sum();
+        let weighted: Decimal = periods.iter().map(|p| p.price * Decimal::from(p.minutes)).sum();
+        let mean = (weighted / Decimal::from(total_minutes.max(1))).round_dp(6);
+
+        // Les N hores més barates, comptades en períodes de la resolució del dia
+        let count = cheapest_hours as usize * 60 / resolution_minutes.max(1) as usize;
+        let mut cheapest = periods;
+        cheapest.sort_by_key(|p| (p.price, p.start));
+        cheapest.truncate(count);
+        cheapest.sort_by_key(|p| p.start);
+
+        Some(Self {
+            date,
+            timezone: timezone.to_string(),
+            tariff,
+            unit: unit.to_string(),
+            resolution_minutes,
+            prices,
+            stats: PriceStats {
+                min,
+                max,
+                mean,
+                cheapest_hours: cheapest,
+                tiers: TierThresholds {
+                    cheap_max: display(thresholds.cheap_max),
+                    expensive_min: display(thresholds.expensive_min),
+                },
+            },
+        })
+    }
+}
+
+// Subscripció als avisos de preu d'un usuari
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable, Identifiable, Insertable)]
+#[diesel(table_name = price_alerts, primary_key(user_id))]
+pub struct PriceAlert {
+    pub user_id: Uuid,
+    pub enabled: bool,
+    pub notify_cheap: bool,
+    pub notify_expensive: bool,
+    pub cheap_percentile: i32,
+    pub expensive_percentile: i32,
+    pub cheap_threshold: Option<Decimal>,    // €/kWh; té preferència sobre el percentil
+    pub expensive_threshold: Option<Decimal>,
+    pub min_hours: i32,    // Durada mínima del tram per avisar
+    pub lead_minutes: i32, // Antelació de l'avís
+    #[serde(skip_serializing)]
+    pub last_cheap_alert: Option<DateTime<Utc>>,
+    #[serde(skip_serializing)]
+    pub last_expensive_alert: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// DTO per crear o modificar la subscripció; els camps absents no canvien.
+// Per treure un llindar absolut s'envia `null`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdatePriceAlertRequest {
+    pub enabled: Option<bool>,
+    pub notify_cheap: Option<bool>,
+    pub notify_expensive: Option<bool>,
+    pub cheap_percentile: Option<i32>,
+    pub expensive_percentile: Option<i32>,
+    #[serde(default, deserialize_with = "present")]
+    pub cheap_threshold: Option<Option<Decimal>>,
+    #[serde(default, deserialize_with = "present")]
+    pub expensive_threshold: Option<Option<Decimal>>,
+    pub min_hours: Option<i32>,
+    pub lead_minutes: Option<i32>,
+}
+
+// Distingeix un camp absent (None) d'un `null` explícit (Some(None))
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+impl PriceAlert {
+    pub fn defaults(user_id: Uuid) -> Self {
+        let now = Utc::now();
+        Self {
+            user_id,
+            enabled: true,
+            notify_cheap: true,
+            notify_expensive: false,
+            cheap_percentile: 25,
+            expensive_percentile: 75,
+            cheap_threshold: None,
+            expensive_threshold: None,
+            min_hours: 2,
+            lead_minutes: 0,
+            last_cheap_alert: None,
+            last_expensive_alert: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn tier_config(&self) -> TierConfig {
+        TierConfig {
+            cheap_percentile: self.cheap_percentile.clamp(1, 99) as u8,
+            expensive_percentile: self.expensive_percentile.clamp(1, 99) as u8,
+            cheap_threshold: self.cheap_threshold,
+            expensive_threshold: self.expensive_threshold,
+        }
+    }
+
+    // Aplica els canvis i valida la configuració resultant
+    pub fn apply(mut self, update: UpdatePriceAlertRequest) -> Result<Self, ValidationErrors> {
+        self.enabled = update.enabled.unwrap_or(self.enabled);
+        self.notify_cheap = update.notify_cheap.unwrap_or(self.notify_cheap);
+        self.notify_expensive = update.notify_expensive.unwrap_or(self.notify_expensive);
+        self.cheap_percentile = update.cheap_percentile.unwrap_or(self.cheap_percentile);
+        self.expensive_percentile = update.expensive_percentile.unwrap_or(self.expensive_percentile);
+        self.cheap_threshold = update.cheap_threshold.unwrap_or(self.cheap_threshold);
+        self.expensive_threshold = update.expensive_threshold.unwrap_or(self.expensive_threshold);
+        self.min_hours = update.min_hours.unwrap_or(self.min_hours);
+        self.lead_minutes = update.lead_minutes.unwrap_or(self.lead_minutes);
+
+        let mut errors = ValidationErrors::new();
+        for (field, value) in [
+            ("cheap_percentile", self.cheap_percentile),
+            ("expensive_percentile", self.expensive_percentile),
+        ] {
+            if !(1..=99).contains(&value) {
+                errors.add(field, "must be between 1 and 99");
+            }
+        }
+        if self.cheap_percentile >= self.expensive_percentile {
+            errors.add("cheap_percentile", "must be lower than expensive_percentile");
+        }
+        for (field, value) in [
+            ("cheap_threshold", self.cheap_threshold),
+            ("expensive_threshold", self.expensive_threshold),
+        ] {
+            if value.is_some_and(|v| v.is_sign_negative()) {
+                errors.add(field, "must not be negative");
+            }
+        }
+        if let (Some(cheap), Some(expensive)) = (self.cheap_threshold, self.expensive_threshold) {
+            if cheap >= expensive {
+                errors.add("cheap_threshold", "must be lower than expensive_threshold");
+            }
+        }
+        if !(1..=12).contains(&self.min_hours) {
+            errors.add("min_hours", "must be between 1 and 12");
+        }
+        if !(0..=240).contains(&self.lead_minutes) {
+            errors.add("lead_minutes", "must be between 0 and 240");
+        }
+
+        errors.into_result(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    // Períodes consecutius de `minutes` minuts des de mitjanit UTC, amb preus en mil·lèsimes d'euro
+    fn periods(minutes: u32, prices: &[i64]) -> Vec<PricePeriod> {
+        let start: DateTime<Utc> = "2026-10-19T00:00:00Z".parse().unwrap();
+        prices
+            .iter()
+            .enumerate()
+            .map(|(i, &price)| {
+                let offset = i as u32 * minutes;
+                PricePeriod {
+                    start: start + Duration::minutes(offset as i64),
+                    hour: (offset / 60) as u8,
+                    minute: (offset % 60) as u8,
+                    minutes,
+                    price: Decimal::new(price, 3),
+                }
+            })
+            .collect()
+    }
+
+    fn response(prices: Vec<PricePeriod>, cheapest_hours: u8, currency_display: &str) -> DayPricesResponse {
+        DayPricesResponse::new(
+            NaiveDate::from_ymd_opt(2026, 10, 19).unwrap(),
+            "UTC",
+            TariffKind::Pvpc20td,
+            prices,
+            cheapest_hours,
+            &TierConfig::default(),
+            currency_display,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn percentile_uses_the_nearest_rank() {
+        // 1..=24 en ordre invers perquè s'hagin d'ordenar
+        let prices = periods(60, &(1..=24).rev().collect::<Vec<_>>());
+
+        // ceil(33 * 24 / 100) = 8, ceil(67 * 24 / 100) = 17
+        assert_eq!(percentile(&prices, 33), Some(Decimal::new(8, 3)));
+        assert_eq!(percentile(&prices, 67), Some(Decimal::new(17, 3)));
+        assert_eq!(percentile(&prices, 50), Some(Decimal::new(12, 3)));
+    }
+
+    #[test]
+    fn percentile_edges_are_the_minimum_and_the_maximum() {
+        let prices = periods(60, &[40, 10, 30, 20]);
+
+        assert_eq!(percentile(&prices, 0), Some(Decimal::new(10, 3)));
+        assert_eq!(percentile(&prices, 1), Some(Decimal::new(10, 3)));
+        assert_eq!(percentile(&prices, 25), Some(Decimal::new(10, 3)));
+        assert_eq!(percentile(&prices, 26), Some(Decimal::new(20, 3)));
+        assert_eq!(percentile(&prices, 99), Some(Decimal::new(40, 3)));
+        assert_eq!(percentile(&prices, 100), Some(Decimal::new(40, 3)));
+        assert_eq!(percentile(&[], 50), None);
+    }
+
+    #[test]
+    fn band_edges_belong_to_the_cheap_and_expensive_tiers() {
+        let thresholds = TierThresholds {
+            cheap_max: Decimal::new(10, 2),
+            expensive_min: Decimal::new(20, 2),
+        };
+
+        assert_eq!(thresholds.classify(Decimal::new(10, 2)), PriceTier::Cheap);
+        assert_eq!(thresholds.classify(Decimal::new(101, 3)), PriceTier::Normal);
+        assert_eq!(thresholds.classify(Decimal::new(199, 3)), PriceTier::Normal);
+        assert_eq!(thresholds.classify(Decimal::new(20, 2)), PriceTier::Expensive);
+    }
+
+    #[test]
+    fn absolute_thresholds_take_precedence_over_percentiles() {
+        let prices = periods(60, &[10, 20, 30, 40]);
+        let config = TierConfig {
+            cheap_threshold: Some(Decimal::new(25, 3)),
+            ..TierConfig::default()
+        };
+
+        let thresholds = config.thresholds(&prices).unwrap();
+        assert_eq!(thresholds.cheap_max, Decimal::new(25, 3));
+        assert_eq!(thresholds.expensive_min, Decimal::new(30, 3));
+        assert!(TierConfig::default().thresholds(&[]).is_none());
+    }
+
+    #[test]
+    fn stats_report_min_max_and_the_duration_weighted_mean() {
+        let day = response(periods(60, &[50, 10, 90, 10, 90, 30]), 0, "euros");
+        let stats = &day.stats;
+
+        // Els empats es resolen pel primer període
+        assert_eq!((stats.min.hour, stats.min.price), (1, Decimal::new(10, 3)));
+        assert_eq!((stats.max.hour, stats.max.price), (2, Decimal::new(90, 3)));
+        assert_eq!(stats.mean, Decimal::new(46_667, 6));
+        assert_eq!(day.resolution_minutes, 60);
+        assert!(stats.cheapest_hours.is_empty());
+    }
+
+    #[test]
+    fn cheapest_hours_count_quarter_hour_periods() {
+        // Dues hores en quarts d'hora, amb els preus més baixos repartits
+        let prices = [
+            80, 70, 10, 60, 20, 90, 30, 90, //
+            40, 95, 95, 95, 95, 95, 95, 95,
+        ];
+        let day = response(periods(15, &prices), 1, "euros");
+
+        assert_eq!(day.resolution_minutes, 15);
+        let cheapest: Vec<(u8, u8)> = day.stats.cheapest_hours.iter().map(|p| (p.hour, p.minute)).collect();
+        assert_eq!(cheapest, vec![(0, 30), (1, 0), (1, 30), (2, 0)]);
+    }
+
+    #[test]
+    fn prices_and_stats_are_shown_in_cents_when_asked() {
+        let day = response(periods(60, &[10, 20, 30]), 1, "cents");
+
+        assert_eq!(day.unit, "cEUR/kWh");
+        assert_eq!(day.prices[0].period.price, Decimal::from(1));
+        assert_eq!(day.prices[0].tier, PriceTier::Cheap);
+        assert_eq!(day.stats.max.price, Decimal::from(3));
+        assert_eq!(day.stats.mean, Decimal::from(2));
+        assert_eq!(day.stats.tiers.cheap_max, Decimal::from(1));
+    }
+}
@@ -1,8 +1,12 @@
 use crate::schema::rules;
-use chrono::{DateTime, Utc};
+use crate::utils::errors::ValidationErrors;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
 use diesel::prelude::*;
-use serde::{Deserialize, Serialize};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value as JsonValue;
+use std::{fmt, str::FromStr};
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -10,23 +14,167 @@ use uuid::Uuid;
 pub enum RuleType {
     MinHoursCheapest,
     XHoursWithinWindows,
+    PriceThreshold,
+    DeadlineEnergy,
 }
 
-impl ToString for RuleType {
-    fn to_string(&self) -> String {
+impl RuleType {
+    pub const ALL: [RuleType; 4] = [
+        RuleType::MinHoursCheapest,
+        RuleType::XHoursWithinWindows,
+        RuleType::PriceThreshold,
+        RuleType::DeadlineEnergy,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RuleType::MinHoursCheapest => "MIN_HOURS_CHEAPEST",
+            RuleType::XHoursWithinWindows => "X_HOURS_WITHIN_WINDOWS",
+            RuleType::PriceThreshold => "PRICE_THRESHOLD",
+            RuleType::DeadlineEnergy => "DEADLINE_ENERGY",
+        }
+    }
+
+    // Valida els paràmetres segons el tipus de regla i els retorna normalitzats
+    pub fn validate_params(&self, params: &JsonValue) -> Result<JsonValue, ValidationErrors> {
         match self {
-            RuleType::MinHoursCheapest => "MIN_HOURS_CHEAPEST".to_string(),
-            RuleType::XHoursWithinWindows => "X_HOURS_WITHIN_WINDOWS".to_string(),
+            RuleType::MinHoursCheapest => validate_typed::<MinHoursCheapestParams>(params),
+            RuleType::XHoursWithinWindows => validate_typed::<XHoursWithinWindowsParams>(params),
+            RuleType::PriceThreshold => validate_typed::<PriceThresholdParams>(params),
+            RuleType::DeadlineEnergy => validate_typed::<DeadlineEnergyParams>(params),
         }
     }
+
+    // Trams del dia [inici, fi) en minuts des de mitjanit on la regla pot encendre
+    // el dispositiu. Serveix per avisar quan dues regles es trepitgen.
+    pub fn day_ranges(&self, params: &JsonValue) -> Vec<(u32, u32)> {
+        let whole_day = vec![(0, 24 * 60)];
+        match self {
+            RuleType::MinHoursCheapest | RuleType::PriceThreshold => whole_day,
+            RuleType::XHoursWithinWindows => {
+                serde_json::from_value::<XHoursWithinWindowsParams>(params.clone())
+                    .map(|p| {
+                        p.allowed_windows
+                            .iter()
+                            .filter_map(|w| w.minute_ranges().ok())
+                            .flatten()
+                            .collect()
+                    })
+                    .unwrap_or(whole_day)
+            }
+            RuleType::DeadlineEnergy => {
+                serde_json::from_value::<DeadlineEnergyParams>(params.clone())
+                    .ok()
+                    .and_then(|p| p.allowed_window)
+                    .and_then(|w| w.minute_ranges().ok())
+                    .unwrap_or(whole_day)
+            }
+        }
+    }
+}
+
+// Prioritat de les regles: quan diverses regles encenen el mateix dispositiu a la
+// mateixa hora, el slot s'atribueix a la de prioritat més alta
+pub const DEFAULT_PRIORITY: i32 = 1;
+pub const MAX_PRIORITY: i32 = 100;
+
+pub fn validate_priority(priority: i32) -> Result<i32, ValidationErrors> {
+    if (1..=MAX_PRIORITY).contains(&priority) {
+        Ok(priority)
+    } else {
+        Err(ValidationErrors::single(
+            "priority",
+            format!("must be between 1 and {}", MAX_PRIORITY),
+        ))
+    }
+}
+
+// Dos conjunts de trams [inici, fi) es trepitgen
+pub fn ranges_overlap(a: &[(u32, u32)], b: &[(u32, u32)]) -> bool {
+    a.iter()
+        .any(|(a_start, a_end)| b.iter().any(|(b_start, b_end)| a_start < b_end && b_start < a_end))
+}
+
+impl fmt::Display for RuleType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
-impl From<String> for RuleType {
-    fn from(s: String) -> Self {
-        match s.as_str() {
-            "MIN_HOURS_CHEAPEST" => RuleType::MinHoursCheapest,
-            "X_HOURS_WITHIN_WINDOWS" => RuleType::XHoursWithinWindows,
-            _ => RuleType::MinHoursCheapest,
+impl FromStr for RuleType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RuleType::ALL
+            .into_iter()
+            .find(|t| t.as_str() == s)
+            .ok_or_else(|| {
+                let expected: Vec<&str> = RuleType::ALL.iter().map(|t| t.as_str()).collect();
+                format!("Unknown rule type '{}', expected one of: {}", s, expected.join(", "))
+            })
+    }
+}
+
+// Paràmetres tipats d'una regla amb les seves comprovacions de rang
+pub trait RuleParams: Serialize + DeserializeOwned {
+    fn validate(&self, errors: &mut ValidationErrors);
+}
+
+fn validate_typed<P: RuleParams>(params: &JsonValue) -> Result<JsonValue, ValidationErrors> {
+    let typed: P = serde_json::from_value(params.clone())
+        .map_err(|e| ValidationErrors::single("params", e.to_string()))?;
+
+    let mut errors = ValidationErrors::new();
+    typed.validate(&mut errors);
+    errors.into_result(())?;
+
+    serde_json::to_value(&typed).map_err(|e| ValidationErrors::single("params", e.to_string()))
+}
+
+// Els blocs mínims van en múltiples del període de preus més curt (quarts d'hora)
+const MIN_BLOCK_STEP_MINUTES: u16 = 15;
+
+// Els límits de les finestres i l'hora límit també: un límit a mig quart obligaria
+// l'optimitzador a treballar minut a minut
+pub const WINDOW_STEP_MINUTES: u32 = 15;
+
+// Comprovacions comunes d'hores, bloc mínim i nombre d'encesos
+fn validate_hours(
+    errors: &mut ValidationErrors,
+    hours_field: &str,
+    hours: u8,
+    min_run_block: Option<u16>,
+    max_switches_per_day: Option<u8>,
+) {
+    if !(1..=24).contains(&hours) {
+        errors.add(format!("params.{}", hours_field), "must be between 1 and 24");
+    }
+    validate_min_run_block(errors, min_run_block, hours_field, hours as u16 * 60);
+    if let Some(switches) = max_switches_per_day {
+        if !(1..=24).contains(&switches) {
+            errors.add("params.max_switches_per_day", "must be between 1 and 24");
+        }
+    }
+}
+
+// El bloc mínim va en quarts d'hora i ha de cabre en `limit` (`limit_minutes`)
+fn validate_min_run_block(
+    errors: &mut ValidationErrors,
+    min_run_block: Option<u16>,
+    limit: &str,
+    limit_minutes: u16,
+) {
+    if let Some(block) = min_run_block {
+        if block == 0 || block % MIN_BLOCK_STEP_MINUTES != 0 {
+            errors.add(
+                "params.min_run_block",
+                format!("must be a positive multiple of {} minutes", MIN_BLOCK_STEP_MINUTES),
+            );
+        } else if block > limit_minutes {
+            errors.add(
+                "params.min_run_block",
+                format!("must not exceed {} ({} minutes)", limit, limit_minutes),
+            );
         }
     }
 }
@@ -44,6 +192,7 @@ pub struct Rule {
     pub enabled: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub applicability_json: JsonValue, // RuleApplicability
 }
 
 #[derive(Debug, Clone, Deserialize, Insertable)]
@@ -57,58 +206,604 @@ pub struct NewRule {
     pub timezone: String,
     pub priority: i32,
     pub enabled: bool,
+    pub applicability_json: JsonValue,
+}
+
+// Dies en què s'aplica una regla. Tots els camps són opcionals: sense cap
+// restricció la regla s'aplica cada dia.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleApplicability {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub weekdays: Vec<u8>, // 1 = dilluns ... 7 = diumenge; buit = tots els dies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_from: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<NaiveDate>, // Inclòs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exception_dates: Vec<NaiveDate>, // Dies en què no s'aplica
+}
+
+const MAX_EXCEPTION_DATES: usize = 366;
+
+impl RuleApplicability {
+    // Valida l'aplicabilitat i la retorna normalitzada (dies ordenats i sense repetits)
+    pub fn validate(value: &JsonValue) -> Result<JsonValue, ValidationErrors> {
+        let mut applicability: RuleApplicability = serde_json::from_value(value.clone())
+            .map_err(|e| ValidationErrors::single("applicability", e.to_string()))?;
+        applicability.weekdays.sort_unstable();
+        applicability.weekdays.dedup();
+        applicability.exception_dates.sort_unstable();
+        applicability.exception_dates.dedup();
+
+        let mut errors = ValidationErrors::new();
+        if applicability.weekdays.iter().any(|d| !(1..=7).contains(d)) {
+            errors.add(
+                "applicability.weekdays",
+                "must be between 1 (Monday) and 7 (Sunday)",
+            );
+        }
+        if let (Some(from), Some(until)) = (applicability.valid_from, applicability.valid_until) {
+            if from > until {
+                errors.add("applicability.valid_from", "must not be after valid_until");
+            }
+        }
+        if applicability.exception_dates.len() > MAX_EXCEPTION_DATES {
+            errors.add(
+                "applicability.exception_dates",
+                format!("must contain at most {} dates", MAX_EXCEPTION_DATES),
+            );
+        }
+        errors.into_result(())?;
+
+        serde_json::to_value(&applicability)
+            .map_err(|e| ValidationErrors::single("applicability", e.to_string()))
+    }
+
+    pub fn applies_on(&self, date: NaiveDate) -> bool {
+        let weekday = date.weekday().number_from_monday() as u8;
+        (self.weekdays.is_empty() || self.weekdays.contains(&weekday))
+            && self.valid_from.is_none_or(|from| from <= date)
+            && self.valid_until.is_none_or(|until| date <= until)
+            && !self.exception_dates.contains(&date)
+    }
+
+    // Dues regles poden aplicar-se algun mateix dia (comprovació per dies de la setmana i vigència)
+    pub fn overlaps(&self, other: &RuleApplicability) -> bool {
+        let weekdays = |a: &RuleApplicability| -> Vec<u8> {
+            if a.weekdays.is_empty() {
+                (1..=7).collect()
+            } else {
+                a.weekdays.clone()
+            }
+        };
+        let other_weekdays = weekdays(other);
+        let shared_weekday = weekdays(self).iter().any(|d| other_weekdays.contains(d));
+
+        let starts_before_end = |from: Option<NaiveDate>, until: Option<NaiveDate>| match (from, until) {
+            (Some(from), Some(until)) => from <= until,
+            _ => true,
+        };
+        shared_weekday
+            && starts_before_end(self.valid_from, other.valid_until)
+            && starts_before_end(other.valid_from, self.valid_until)
+    }
 }
 
 // Paràmetres específics per a cada tipus de regla
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct MinHoursCheapestParams {
     pub min_hours_per_day: u8,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub max_switches_per_day: Option<u8>,
-    #[serde(default)]
-    pub min_run_block: Option<u8>, // Mínim d'hores consecutives
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_run_block: Option<u16>, // Mínim de minuts consecutius (múltiple de 15)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct XHoursWithinWindowsParams {
     pub target_hours_per_day: u8,
     pub allowed_windows: Vec<TimeWindow>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub max_switches_per_day: Option<u8>,
-    #[serde(default)]
-    pub min_run_block: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_run_block: Option<u16>, // Minuts
+}
+
+// Encén el dispositiu sempre que el preu no superi un límit: absolut (€/kWh) o
+// un percentil dels preus del dia. Cal indicar exactament un dels dos.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PriceThresholdParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_price: Option<Decimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub percentile: Option<u8>, // 1-99
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_hours: Option<u8>, // S'afegeixen les hores més barates per sobre del límit si cal
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_hours: Option<u8>, // Com a molt, les hores més barates per sota del límit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_run_block: Option<u16>, // Minuts
 }
 
+// Càrrega d'energia amb hora límit (p.ex. un cotxe elèctric: 18 kWh abans de les
+// 07:30). Cada sessió de càrrega va de l'hora límit d'un dia a la del dia següent,
+// així que travessa la mitjanit i es planifica amb els preus de tots dos dies.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeadlineEnergyParams {
+    pub energy_kwh: Decimal,
+    pub charger_kw: Decimal,
+    pub deadline: String, // Format "HH:MM", hora local
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_window: Option<TimeWindow>, // Hores en què el vehicle està connectat
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct TimeWindow {
     pub start: String, // Format "HH:MM"
     pub end: String,   // Format "HH:MM"
 }
 
-// DTO per a crear/editar regles des de l'app
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct CreateRuleRequest {
-    pub device_id: Uuid,
-    pub rule_type: RuleType,
-    pub params: JsonValue,
-    pub timezone: String,
-    pub priority: Option<i32>,
-    pub enabled: Option<bool>,
+impl TimeWindow {
+    // Converteix "HH:MM" a minuts des de mitjanit ("24:00" és vàlid com a final de dia)
+    pub fn parse_time(value: &str) -> Result<u32, String> {
+        let (hours, minutes) = value
+            .split_once(':')
+            .filter(|(h, m)| h.len() == 2 && m.len() == 2)
+            .ok_or_else(|| format!("'{}' is not in HH:MM format", value))?;
+        let hours: u32 = hours
+            .parse()
+            .map_err(|_| format!("'{}' has an invalid hour", value))?;
+        let minutes: u32 = minutes
+            .parse()
+            .map_err(|_| format!("'{}' has invalid minutes", value))?;
+
+        if minutes >= 60 || hours > 24 || (hours == 24 && minutes > 0) {
+            return Err(format!("'{}' is not a valid time of day", value));
+        }
+
+        Ok(hours * 60 + minutes)
+    }
+
+    // Comprova que els límits de la finestra cauen en quarts d'hora
+    pub fn validate_grid(&self) -> Result<Vec<(u32, u32)>, String> {
+        let ranges = self.minute_ranges()?;
+        if ranges
+            .iter()
+            .any(|(start, end)| start % WINDOW_STEP_MINUTES != 0 || end % WINDOW_STEP_MINUTES != 0)
+        {
+            return Err(format!("times must be multiples of {} minutes", WINDOW_STEP_MINUTES));
+        }
+        Ok(ranges)
+    }
+
+    // Trams [inici, fi) en minuts des de mitjanit. Una finestra que passa de
+    // mitjanit (p.ex. 22:00-06:00) es divideix en dos trams del mateix dia.
+    pub fn minute_ranges(&self) -> Result<Vec<(u32, u32)>, String> {
+        let start = Self::parse_time(&self.start)?;
+        let end = Self::parse_time(&self.end)?;
+
+        if start == end || start == 24 * 60 {
+            return Err(format!("window {}-{} is empty", self.start, self.end));
+        }
+
+        if start < end {
+            Ok(vec![(start, end)])
+        } else {
+            let mut ranges = vec![(start, 24 * 60)];
+            if end > 0 {
+                ranges.push((0, end));
+            }
+            Ok(ranges)
+        }
+    }
+}
+
+impl RuleParams for MinHoursCheapestParams {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        validate_hours(
+            errors,
+            "min_hours_per_day",
+            self.min_hours_per_day,
+            self.min_run_block,
+            self.max_switches_per_day,
+        );
+    }
+}
+
+impl RuleParams for XHoursWithinWindowsParams {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        validate_hours(
+            errors,
+            "target_hours_per_day",
+            self.target_hours_per_day,
+            self.min_run_block,
+            self.max_switches_per_day,
+        );
+
+        if self.allowed_windows.is_empty() {
+            errors.add("params.allowed_windows", "must contain at least one window");
+            return;
+        }
+
+        let mut covered = [false; 24 * 60];
+        let mut valid = true;
+        for (i, window) in self.allowed_windows.iter().enumerate() {
+            match window.validate_grid() {
+                Ok(ranges) => {
+                    for (start, end) in ranges {
+                        covered[start as usize..end as usize].fill(true);
+                    }
+                }
+                Err(e) => {
+                    errors.add(format!("params.allowed_windows[{}]", i), e);
+                    valid = false;
+                }
+            }
+        }
+
+        // Les finestres han de deixar prou temps per a les hores demanades
+        let available = covered.iter().filter(|c| **c).count() as u32;
+        if valid && available < self.target_hours_per_day as u32 * 60 {
+            errors.add(
+                "params.target_hours_per_day",
+                format!(
+                    "allowed windows only cover {:.2} hours",
+                    available as f32 / 60.0
+                ),
+            );
+        }
+    }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct UpdateRuleRequest {
-    pub params: Option<JsonValue>,
-    pub priority: Option<i32>,
-    pub enabled: Option<bool>,
+impl RuleParams for PriceThresholdParams {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        match (self.max_price, self.percentile) {
+            (Some(_), Some(_)) => errors.add("params", "max_price and percentile are mutually exclusive"),
+            (None, None) => errors.add("params", "either max_price or percentile is required"),
+            _ => {}
+        }
+        if self.max_price.is_some_and(|p| p.is_sign_negative()) {
+            errors.add("params.max_price", "must not be negative");
+        }
+        if self.percentile.is_some_and(|p| !(1..=99).contains(&p)) {
+            errors.add("params.percentile", "must be between 1 and 99");
+        }
+
+        if self.min_hours.is_some_and(|h| h > 24) {
+            errors.add("params.min_hours", "must be between 0 and 24");
+        }
+        if self.max_hours.is_some_and(|h| !(1..=24).contains(&h)) {
+            errors.add("params.max_hours", "must be between 1 and 24");
+        }
+        if let (Some(min), Some(max)) = (self.min_hours, self.max_hours) {
+            if min > max {
+                errors.add("params.min_hours", "must not exceed max_hours");
+            }
+        }
+
+        // El bloc mínim ha de cabre en el màxim d'hores o, si no n'hi ha, en el dia
+        match self.max_hours {
+            Some(max_hours) => validate_hours(
+                errors,
+                "max_hours",
+                max_hours.clamp(1, 24),
+                self.min_run_block,
+                None,
+            ),
+            None => validate_min_run_block(errors, self.min_run_block, "one day", 24 * 60),
+        }
+    }
+}
+
+// Límits de l'energia a carregar i de la potència del carregador
+const MAX_ENERGY_KWH: u32 = 200;
+const MAX_CHARGER_KW: u32 = 50;
+
+impl DeadlineEnergyParams {
+    // Minuts de càrrega necessaris, arrodonits a l'alça. L'últim tram pot ser parcial.
+    pub fn charge_minutes(&self) -> Option<u32> {
+        if self.charger_kw <= Decimal::ZERO {
+            return None;
+        }
+        (self.energy_kwh * Decimal::from(60) / self.charger_kw)
+            .ceil()
+            .to_u32()
+    }
+
+    // Sessió de càrrega que acaba l'endemà de `date` a l'hora límit: [inici, fi) en UTC.
+    // Si l'hora límit cau en el salt horari de primavera s'avança una hora.
+    pub fn session(&self, date: NaiveDate, tz: Tz) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let deadline = TimeWindow::parse_time(&self.deadline).ok()?;
+        let instant = |date: NaiveDate| -> Option<DateTime<Utc>> {
+            let local = date.and_hms_opt(0, 0, 0)? + Duration::minutes(deadline as i64);
+            tz.from_local_datetime(&local)
+                .earliest()
+                .or_else(|| tz.from_local_datetime(&(local + Duration::hours(1))).earliest())
+                .map(|dt| dt.with_timezone(&Utc))
+        };
+        Some((instant(date)?, instant(date + Duration::days(1))?))
+    }
+}
+
+impl RuleParams for DeadlineEnergyParams {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        if self.energy_kwh <= Decimal::ZERO || self.energy_kwh > Decimal::from(MAX_ENERGY_KWH) {
+            errors.add(
+                "params.energy_kwh",
+                format!("must be greater than 0 and at most {}", MAX_ENERGY_KWH),
+            );
+        }
+        if self.charger_kw <= Decimal::ZERO || self.charger_kw > Decimal::from(MAX_CHARGER_KW) {
+            errors.add(
+                "params.charger_kw",
+                format!("must be greater than 0 and at most {}", MAX_CHARGER_KW),
+            );
+        }
+        match TimeWindow::parse_time(&self.deadline) {
+            Ok(minutes) if minutes >= 24 * 60 => {
+                errors.add("params.deadline", "must be between 00:00 and 23:59")
+            }
+            Ok(minutes) if minutes % WINDOW_STEP_MINUTES != 0 => errors.add(
+                "params.deadline",
+                format!("must be a multiple of {} minutes", WINDOW_STEP_MINUTES),
+            ),
+            Ok(_) => {}
+            Err(e) => errors.add("params.deadline", e),
+        }
+
+        // La càrrega ha de cabre en la sessió (o en la finestra permesa)
+        let available = match &self.allowed_window {
+            Some(window) => match window.validate_grid() {
+                Ok(ranges) => ranges.iter().map(|(start, end)| end - start).sum(),
+                Err(e) => {
+                    errors.add("params.allowed_window", e);
+                    return;
+                }
+            },
+            None => 24 * 60,
+        };
+        if let Some(minutes) = self.charge_minutes() {
+            if minutes > available {
+                errors.add(
+                    "params.energy_kwh",
+                    format!(
+                        "needs {:.2} hours of charging but only {:.2} are available",
+                        minutes as f32 / 60.0,
+                        available as f32 / 60.0
+                    ),
+                );
+            }
+        }
+    }
 }
 
 impl Rule {
-    pub fn get_rule_type(&self) -> RuleType {
-        RuleType::from(self.rule_type.clone())
+    pub fn get_rule_type(&self) -> Result<RuleType, String> {
+        self.rule_type.parse()
+    }
+
+    // Les regles sense aplicabilitat (`{}`) s'apliquen cada dia
+    pub fn get_applicability(&self) -> Result<RuleApplicability, String> {
+        serde_json::from_value(self.applicability_json.clone())
+            .map_err(|e| format!("Invalid applicability: {}", e))
     }
     
     pub fn is_active(&self) -> bool {
         self.enabled
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn field_errors(result: Result<JsonValue, ValidationErrors>) -> Vec<String> {
+        result.err().map_or_else(Vec::new, |e| e.fields.into_iter().map(|f| f.field).collect())
+    }
+
+    #[test]
+    fn windows_that_wrap_midnight_split_into_two_ranges() {
+        let window = TimeWindow {
+            start: "22:00".to_string(),
+            end: "06:00".to_string(),
+        };
+        assert_eq!(window.minute_ranges().unwrap(), vec![(1320, 1440), (0, 360)]);
+
+        let until_midnight = TimeWindow {
+            start: "22:00".to_string(),
+            end: "00:00".to_string(),
+        };
+        assert_eq!(until_midnight.minute_ranges().unwrap(), vec![(1320, 1440)]);
+    }
+
+    #[test]
+    fn accepts_windows_on_the_quarter_hour_grid() {
+        let params = json!({
+            "target_hours_per_day": 6,
+            "allowed_windows": [{ "start": "22:00", "end": "06:00" }, { "start": "13:15", "end": "14:45" }],
+            "min_run_block": 60
+        });
+        assert!(RuleType::XHoursWithinWindows.validate_params(&params).is_ok());
+    }
+
+    #[test]
+    fn rejects_window_bounds_off_the_quarter_hour_grid() {
+        let params = json!({
+            "target_hours_per_day": 8,
+            "allowed_windows": [{ "start": "00:01", "end": "23:59" }],
+            "min_run_block": 120,
+            "max_switches_per_day": 4
+        });
+        assert_eq!(
+            field_errors(RuleType::XHoursWithinWindows.validate_params(&params)),
+            vec!["params.allowed_windows[0]"]
+        );
+
+        let wrapping = json!({
+            "target_hours_per_day": 2,
+            "allowed_windows": [{ "start": "22:00", "end": "05:50" }]
+        });
+        assert_eq!(
+            field_errors(RuleType::XHoursWithinWindows.validate_params(&wrapping)),
+            vec!["params.allowed_windows[0]"]
+        );
+    }
+
+    #[test]
+    fn rejects_deadlines_off_the_quarter_hour_grid() {
+        let params = json!({
+            "energy_kwh": 10,
+            "charger_kw": 7.4,
+            "deadline": "07:10",
+            "allowed_window": { "start": "21:00", "end": "07:07" }
+        });
+        assert_eq!(
+            field_errors(RuleType::DeadlineEnergy.validate_params(&params)),
+            vec!["params.deadline", "params.allowed_window"]
+        );
+    }
+
+    #[test]
+    fn threshold_block_limit_names_the_field_the_client_sent() {
+        let without_max = json!({ "max_price": 0.1, "min_run_block": 1500 });
+        let errors = RuleType::PriceThreshold.validate_params(&without_max).unwrap_err();
+        assert_eq!(errors.fields.len(), 1);
+        assert_eq!(errors.fields[0].field, "params.min_run_block");
+        assert_eq!(errors.fields[0].message, "must not exceed one day (1440 minutes)");
+
+        let with_max = json!({ "max_price": 0.1, "max_hours": 2, "min_run_block": 180 });
+        let errors = RuleType::PriceThreshold.validate_params(&with_max).unwrap_err();
+        assert_eq!(errors.fields[0].message, "must not exceed max_hours (120 minutes)");
+
+        let fits = json!({ "max_price": 0.1, "min_run_block": 1440 });
+        assert!(RuleType::PriceThreshold.validate_params(&fits).is_ok());
+    }
+
+    fn deadline(deadline: &str) -> DeadlineEnergyParams {
+        DeadlineEnergyParams {
+            energy_kwh: Decimal::from(10),
+            charger_kw: Decimal::from(8),
+            deadline: deadline.to_string(),
+            allowed_window: None,
+        }
+    }
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn session_runs_from_the_deadline_to_the_next_day() {
+        let madrid = chrono_tz::Europe::Madrid;
+        let date = NaiveDate::from_ymd_opt(2026, 10, 19).unwrap();
+
+        assert_eq!(
+            deadline("07:30").session(date, madrid),
+            Some((utc("2026-10-19T05:30:00Z"), utc("2026-10-20T05:30:00Z")))
+        );
+        assert_eq!(deadline("7h").session(date, madrid), None);
+    }
+
+    #[test]
+    fn sessions_over_a_time_change_last_23_or_25_hours() {
+        let madrid = chrono_tz::Europe::Madrid;
+
+        // L'hora límit de les 02:30 no existeix el 29 de març: passa a les 03:30
+        let spring = deadline("02:30")
+            .session(NaiveDate::from_ymd_opt(2026, 3, 28).unwrap(), madrid)
+            .unwrap();
+        assert_eq!(spring, (utc("2026-03-28T01:30:00Z"), utc("2026-03-29T01:30:00Z")));
+
+        let (start, end) = deadline("07:00")
+            .session(NaiveDate::from_ymd_opt(2026, 10, 24).unwrap(), madrid)
+            .unwrap();
+        assert_eq!(end - start, Duration::hours(25));
+    }
+
+    fn day(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, month, day).unwrap()
+    }
+
+    #[test]
+    fn applies_every_day_without_restrictions() {
+        let always = RuleApplicability::default();
+        assert!((19..=25).all(|d| always.applies_on(day(10, d))));
+    }
+
+    #[test]
+    fn applies_only_on_the_listed_weekdays() {
+        // Dilluns 19 d'octubre de 2026 ... diumenge 25
+        let weekends = RuleApplicability {
+            weekdays: vec![6, 7],
+            ..RuleApplicability::default()
+        };
+        let applied: Vec<u32> = (19..=25).filter(|&d| weekends.applies_on(day(10, d))).collect();
+        assert_eq!(applied, vec![24, 25]);
+    }
+
+    #[test]
+    fn validity_bounds_are_inclusive() {
+        let october = RuleApplicability {
+            valid_from: Some(day(10, 1)),
+            valid_until: Some(day(10, 31)),
+            ..RuleApplicability::default()
+        };
+        assert!(!october.applies_on(day(9, 30)));
+        assert!(october.applies_on(day(10, 1)));
+        assert!(october.applies_on(day(10, 31)));
+        assert!(!october.applies_on(day(11, 1)));
+    }
+
+    #[test]
+    fn exception_dates_are_skipped() {
+        let rule = RuleApplicability {
+            weekdays: vec![1],
+            exception_dates: vec![day(10, 26)],
+            ..RuleApplicability::default()
+        };
+        assert!(rule.applies_on(day(10, 19)));
+        assert!(!rule.applies_on(day(10, 26)));
+        assert!(rule.applies_on(day(11, 2)));
+    }
+
+    #[test]
+    fn overlaps_needs_a_shared_weekday_and_validity() {
+        let weekdays = RuleApplicability {
+            weekdays: vec![1, 2, 3, 4, 5],
+            ..RuleApplicability::default()
+        };
+        let weekends = RuleApplicability {
+            weekdays: vec![6, 7],
+            ..RuleApplicability::default()
+        };
+        let every_day = RuleApplicability::default();
+        assert!(!weekdays.overlaps(&weekends));
+        assert!(weekdays.overlaps(&every_day));
+        assert!(every_day.overlaps(&weekends));
+
+        let until_october = RuleApplicability {
+            valid_until: Some(day(10, 31)),
+            ..RuleApplicability::default()
+        };
+        let from_october = RuleApplicability {
+            valid_from: Some(day(10, 31)),
+            ..RuleApplicability::default()
+        };
+        let from_november = RuleApplicability {
+            valid_from: Some(day(11, 1)),
+            ..RuleApplicability::default()
+        };
+        // Els límits són inclosos: coincidir l'últim dia ja és solapar-se
+        assert!(until_october.overlaps(&from_october));
+        assert!(from_october.overlaps(&until_october));
+        assert!(!until_october.overlaps(&from_november));
+        assert!(!from_november.overlaps(&until_october));
+    }
+}
@@ -1,5 +1,7 @@
+use crate::models::rule::{RuleType, TimeWindow};
 use crate::schema::{schedules, day_prices};
-use chrono::{DateTime, NaiveDate, Utc};
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
 use diesel::prelude::*;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
@@ -19,6 +21,7 @@ pub struct Schedule {
     pub status: String, // "pending", "active", "completed", "failed"
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub last_fired_slot: Option<i32>, // Índex de l'últim slot executat
 }
 
 #[derive(Debug, Clone, Deserialize, Insertable)]
@@ -40,9 +43,11 @@ pub struct DayPrice {
     pub id: Uuid,
     pub date: NaiveDate,
     pub timezone: String,
-    pub prices_json: JsonValue, // Array de 24 preus (o 23/25 en canvi horari)
+    pub prices_json: JsonValue, // Array de períodes (24 hores, o 23/25 en canvi horari; ×4 si són quarts)
     pub source: String,
     pub created_at: DateTime<Utc>,
+    pub resolution_minutes: i32, // 60 o 15
+    pub tariff: String,          // Corba publicada: "pvpc_2_0td" o "omie_spot"
 }
 
 #[derive(Debug, Clone, Deserialize, Insertable)]
@@ -53,22 +58,49 @@ pub struct NewDayPrice {
     pub timezone: String,
     pub prices_json: JsonValue,
     pub source: String,
+    pub resolution_minutes: i32,
+    pub tariff: String,
 }
 
 // Estructures per als slots de temps
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TimeSlot {
-    pub start: String,  // Format "HH:MM"
+    pub start: String,  // Format "HH:MM" (hora local, només per mostrar)
     pub end: String,    // Format "HH:MM"
     pub action: String, // "on" o "off"
+    // Límits reals del slot. Els dies de canvi horari l'hora local és ambigua
+    // (les 02:00 d'octubre passen dues vegades), així que l'executor usa aquests.
+    // Els horaris antics no en tenen i es resolen a partir de l'hora local.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_at: Option<DateTime<Utc>>,
+    // Regla que encén el dispositiu en aquest slot (la de més prioritat si n'hi ha diverses)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule_id: Option<Uuid>,
 }
 
+// Preu d'un període de liquidació (una hora o un quart d'hora)
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct HourlyPrice {
-    pub hour: u8,
+pub struct PricePeriod {
+    pub start: DateTime<Utc>, // Inici del període en UTC
+    pub hour: u8,             // Hora local de rellotge (es repeteix l'últim diumenge d'octubre)
+    pub minute: u8,           // 0, 15, 30 o 45
+    pub minutes: u32,         // Durada: 60 o 15
     pub price: Decimal,
 }
 
+// Format desat a `prices_json`. Els preus antics només tenen `hour` i `price`.
+#[derive(Debug, Deserialize)]
+struct StoredPricePeriod {
+    start: Option<DateTime<Utc>>,
+    hour: u8,
+    #[serde(default)]
+    minute: u8,
+    minutes: Option<u32>,
+    price: Decimal,
+}
+
 // DTO per a la resposta de l'horari calculat
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScheduleResponse {
@@ -77,6 +109,8 @@ pub struct ScheduleResponse {
     pub slots: Vec<TimeSlot>,
     pub total_cost: Decimal,
     pub total_hours: f32,
+    pub average_cost: Option<Decimal>, // Cost de les mateixes hores al preu mitjà del dia
+    pub savings: Option<Decimal>,
     pub savings_percentage: Option<f32>, // Comparació amb no optimitzar
 }
 
@@ -86,9 +120,108 @@ pub struct PreviewScheduleRequest {
     pub device_id: Uuid,
     pub rule_id: Option<Uuid>,
     pub date: NaiveDate,
+    pub rule_type: Option<RuleType>,    // Obligatori si no s'indica rule_id
     pub rule_params: Option<JsonValue>, // Per previsualitzar sense crear la regla
 }
 
+impl DayPrice {
+    // Els preus sense `start` són períodes consecutius des de la mitjanit local del dia
+    pub fn get_prices(&self) -> Result<Vec<PricePeriod>, serde_json::Error> {
+        let stored: Vec<StoredPricePeriod> = serde_json::from_value(self.prices_json.clone())?;
+
+        let tz: Tz = self.timezone.parse().unwrap_or(chrono_tz::Europe::Madrid);
+        let midnight = self.date.and_time(NaiveTime::MIN);
+        let day_start = tz
+            .from_local_datetime(&midnight)
+            .earliest()
+            .map(|dt| dt.with_timezone(&Utc))
+            .unwrap_or_else(|| Utc.from_utc_datetime(&midnight));
+
+        let resolution = self.resolution_minutes as u32;
+        Ok(stored
+            .into_iter()
+            .enumerate()
+            .map(|(i, p)| PricePeriod {
+                start: p
+                    .start
+                    .unwrap_or(day_start + Duration::minutes(i as i64 * resolution as i64)),
+                hour: p.hour,
+                minute: p.minute,
+                minutes: p.minutes.unwrap_or(resolution),
+                price: p.price,
+            })
+            .collect())
+    }
+
+    pub fn average_price(&self) -> Option<Decimal> {
+        PricePeriod::average(&self.get_prices().ok()?)
+    }
+}
+
+impl PricePeriod {
+    // Mitjana ponderada per la durada de cada període
+    pub fn average(prices: &[PricePeriod]) -> Option<Decimal> {
+        let minutes: u32 = prices.iter().map(|p| p.minutes).sum();
+        if minutes == 0 {
+            return None;
+        }
+        let weighted: Decimal = prices.iter().map(|p| p.price * Decimal::from(p.minutes)).sum();
+        Some(weighted / Decimal::from(minutes))
+    }
+}
+
+impl TimeSlot {
+    pub fn is_on(&self) -> bool {
+        self.action == "on"
+    }
+
+    pub fn duration_minutes(&self) -> u32 {
+        if let (Some(start), Some(end)) = (self.start_at, self.end_at) {
+            return (end - start).num_minutes().max(0) as u32;
+        }
+
+        match (TimeWindow::parse_time(&self.start), TimeWindow::parse_time(&self.end)) {
+            (Ok(start), Ok(end)) if end > start => end - start,
+            _ => 0,
+        }
+    }
+}
+
+impl ScheduleResponse {
+    // `average_price` és el preu mitjà del dia, per calcular l'estalvi respecte no optimitzar
+    pub fn from_schedule(schedule: &Schedule, average_price: Option<Decimal>) -> Self {
+        let slots = schedule.get_slots().unwrap_or_default();
+        let on_minutes: u32 = slots
+            .iter()
+            .filter(|s| s.is_on())
+            .map(TimeSlot::duration_minutes)
+            .sum();
+        let total_hours = on_minutes as f32 / 60.0;
+
+        let average_cost = average_price.map(|avg| avg * Decimal::from(on_minutes) / Decimal::from(60));
+        let savings_percentage = average_cost.and_then(|baseline| {
+            if baseline.is_zero() {
+                return None;
+            }
+            ((baseline - schedule.total_cost) / baseline * Decimal::from(100))
+                .round_dp(2)
+                .try_into()
+                .ok()
+        });
+
+        Self {
+            device_id: schedule.device_id,
+            date: schedule.date,
+            slots,
+            total_cost: schedule.total_cost,
+            total_hours,
+            average_cost: average_cost.map(|c| c.round_dp(4)),
+            savings: average_cost.map(|c| (c - schedule.total_cost).round_dp(4)),
+            savings_percentage,
+        }
+    }
+}
+
 impl Schedule {
     pub fn get_slots(&self) -> Result<Vec<TimeSlot>, serde_json::Error> {
         serde_json::from_value(self.slots_json.clone())
@@ -0,0 +1,517 @@
+use crate::models::rule::TimeWindow;
+use crate::schema::user_tariffs;
+use crate::utils::errors::ValidationErrors;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, Timelike, Utc, Weekday};
+use diesel::prelude::*;
+use rust_decimal::Decimal;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use std::{fmt, str::FromStr};
+use uuid::Uuid;
+
+// Corbes de preus publicades que es descarreguen cada dia
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PriceSeries {
+    Pvpc, // PVPC 2.0TD (indicador 1001)
+    Spot, // Mercat diari d'OMIE (indicador 600)
+}
+
+impl PriceSeries {
+    pub const ALL: [PriceSeries; 2] = [PriceSeries::Pvpc, PriceSeries::Spot];
+
+    // Valor de la columna `day_prices.tariff`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PriceSeries::Pvpc => "pvpc_2_0td",
+            PriceSeries::Spot => "omie_spot",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TariffKind {
+    #[serde(rename = "pvpc_2_0td")]
+    Pvpc20td,
+    #[serde(rename = "three_period")]
+    ThreePeriod,
+    #[serde(rename = "omie_spot")]
+    OmieSpot,
+    #[serde(rename = "custom")]
+    Custom,
+}
+
+impl TariffKind {
+    pub const ALL: [TariffKind; 4] = [
+        TariffKind::Pvpc20td,
+        TariffKind::ThreePeriod,
+        TariffKind::OmieSpot,
+        TariffKind::Custom,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TariffKind::Pvpc20td => "pvpc_2_0td",
+            TariffKind::ThreePeriod => "three_period",
+            TariffKind::OmieSpot => "omie_spot",
+            TariffKind::Custom => "custom",
+        }
+    }
+
+    // Valida els paràmetres segons el tipus de tarifa i els retorna normalitzats
+    pub fn validate_params(&self, params: &JsonValue) -> Result<JsonValue, ValidationErrors> {
+        match self {
+            TariffKind::Pvpc20td => validate_typed::<PvpcParams>(params),
+            TariffKind::ThreePeriod => validate_typed::<ThreePeriodParams>(params),
+            TariffKind::OmieSpot => validate_typed::<OmieSpotParams>(params),
+            TariffKind::Custom => validate_typed::<CustomParams>(params),
+        }
+    }
+}
+
+impl fmt::Display for TariffKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TariffKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TariffKind::ALL
+            .into_iter()
+            .find(|t| t.as_str() == s)
+            .ok_or_else(|| {
+                let expected: Vec<&str> = TariffKind::ALL.iter().map(|t| t.as_str()).collect();
+                format!("Unknown tariff kind '{}', expected one of: {}", s, expected.join(", "))
+            })
+    }
+}
+
+// Paràmetres tipats d'una tarifa amb les seves comprovacions
+pub trait TariffParams: Serialize + DeserializeOwned {
+    fn validate(&self, errors: &mut ValidationErrors);
+}
+
+fn validate_typed<P: TariffParams>(params: &JsonValue) -> Result<JsonValue, ValidationErrors> {
+    let typed: P = serde_json::from_value(params.clone())
+        .map_err(|e| ValidationErrors::single("params", e.to_string()))?;
+
+    let mut errors = ValidationErrors::new();
+    typed.validate(&mut errors);
+    errors.into_result(())?;
+
+    serde_json::to_value(&typed).map_err(|e| ValidationErrors::single("params", e.to_string()))
+}
+
+fn validate_price(errors: &mut ValidationErrors, field: String, price: Decimal) {
+    if price.is_sign_negative() {
+        errors.add(field, "must not be negative");
+    }
+}
+
+// Festius nacionals de data fixa, que en la 2.0TD són període vall tot el dia
+const NATIONAL_HOLIDAYS: [(u32, u32); 9] = [
+    (1, 1),
+    (1, 6),
+    (5, 1),
+    (8, 15),
+    (10, 12),
+    (11, 1),
+    (12, 6),
+    (12, 8),
+    (12, 25),
+];
+
+// Períodes horaris de la 2.0TD
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TouPeriod {
+    Punta,
+    Llano,
+    Valle,
+}
+
+impl TouPeriod {
+    // Període d'un instant en hora local: punta 10-14 i 18-22, pla 8-10, 14-18 i
+    // 22-24, vall 0-8 i tot el dia els caps de setmana i festius nacionals
+    pub fn at(local: NaiveDateTime) -> Self {
+        let date = local.date();
+        if is_weekend_or_holiday(date) {
+            return TouPeriod::Valle;
+        }
+
+        match local.hour() {
+            0..=7 => TouPeriod::Valle,
+            10..=13 | 18..=21 => TouPeriod::Punta,
+            _ => TouPeriod::Llano,
+        }
+    }
+}
+
+fn is_weekend_or_holiday(date: NaiveDate) -> bool {
+    matches!(date.weekday(), Weekday::Sat | Weekday::Sun)
+        || NATIONAL_HOLIDAYS.contains(&(date.month(), date.day()))
+}
+
+// El PVPC no té paràmetres
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PvpcParams {}
+
+// Preus fixos (€/kWh) per període de la 2.0TD
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ThreePeriodParams {
+    pub punta: Decimal,
+    pub llano: Decimal,
+    pub valle: Decimal,
+}
+
+impl ThreePeriodParams {
+    pub fn price(&self, period: TouPeriod) -> Decimal {
+        match period {
+            TouPeriod::Punta => self.punta,
+            TouPeriod::Llano => self.llano,
+            TouPeriod::Valle => self.valle,
+        }
+    }
+
+    fn validate_prices(&self, errors: &mut ValidationErrors, prefix: &str) {
+        for (field, price) in [("punta", self.punta), ("llano", self.llano), ("valle", self.valle)] {
+            validate_price(errors, format!("{}.{}", prefix, field), price);
+        }
+    }
+}
+
+// Indexada: preu spot d'OMIE més un marge i, opcionalment, peatges per període (€/kWh)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OmieSpotParams {
+    pub margin: Decimal,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tolls: Option<ThreePeriodParams>,
+}
+
+impl OmieSpotParams {
+    pub fn price(&self, spot: Decimal, local: NaiveDateTime) -> Decimal {
+        let tolls = self
+            .tolls
+            .as_ref()
+            .map_or(Decimal::ZERO, |tolls| tolls.price(TouPeriod::at(local)));
+        spot + self.margin + tolls
+    }
+}
+
+// Tarifa personalitzada: el primer tram que coincideix fixa el preu
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CustomParams {
+    pub periods: Vec<CustomPeriod>,
+    pub default_price: Decimal, // Preu fora de tots els trams
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CustomPeriod {
+    pub start: String, // Format "HH:MM"
+    pub end: String,   // Format "HH:MM"; pot passar de mitjanit
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub weekdays: Vec<u8>, // 1 = dilluns ... 7 = diumenge; buit = tots els dies
+    pub price: Decimal,
+}
+
+// Els trams personalitzats van en múltiples de quart d'hora
+const CUSTOM_STEP_MINUTES: u32 = 15;
+const MAX_CUSTOM_PERIODS: usize = 48;
+
+impl CustomPeriod {
+    fn window(&self) -> TimeWindow {
+        TimeWindow {
+            start: self.start.clone(),
+            end: self.end.clone(),
+        }
+    }
+
+    fn matches(&self, local: NaiveDateTime) -> bool {
+        let weekday = local.weekday().number_from_monday() as u8;
+        if !self.weekdays.is_empty() && !self.weekdays.contains(&weekday) {
+            return false;
+        }
+
+        let minute = local.hour() * 60 + local.minute();
+        self.window()
+            .minute_ranges()
+            .unwrap_or_default()
+            .into_iter()
+            .any(|(start, end)| (start..end).contains(&minute))
+    }
+}
+
+impl CustomParams {
+    pub fn price(&self, local: NaiveDateTime) -> Decimal {
+        self.periods
+            .iter()
+            .find(|p| p.matches(local))
+            .map_or(self.default_price, |p| p.price)
+    }
+
+    // Hores si tots els límits són en punt, quarts d'hora si no
+    pub fn resolution_minutes(&self) -> u32 {
+        let on_the_hour = self.periods.iter().all(|p| {
+            p.window()
+                .minute_ranges()
+                .unwrap_or_default()
+                .into_iter()
+                .all(|(start, end)| start % 60 == 0 && end % 60 == 0)
+        });
+        if on_the_hour {
+            60
+        } else {
+            CUSTOM_STEP_MINUTES
+        }
+    }
+}
+
+impl TariffParams for PvpcParams {
+    fn validate(&self, _errors: &mut ValidationErrors) {}
+}
+
+impl TariffParams for ThreePeriodParams {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        self.validate_prices(errors, "params");
+    }
+}
+
+impl TariffParams for OmieSpotParams {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        validate_price(errors, "params.margin".to_string(), self.margin);
+        if let Some(tolls) = &self.tolls {
+            tolls.validate_prices(errors, "params.tolls");
+        }
+    }
+}
+
+impl TariffParams for CustomParams {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        validate_price(errors, "params.default_price".to_string(), self.default_price);
+        if self.periods.len() > MAX_CUSTOM_PERIODS {
+            errors.add(
+                "params.periods",
+                format!("must not contain more than {} periods", MAX_CUSTOM_PERIODS),
+            );
+        }
+
+        for (i, period) in self.periods.iter().enumerate() {
+            let field = format!("params.periods[{}]", i);
+            match period.window().minute_ranges() {
+                Ok(ranges) => {
+                    if ranges
+                        .iter()
+                        .any(|(start, end)| start % CUSTOM_STEP_MINUTES != 0 || end % CUSTOM_STEP_MINUTES != 0)
+                    {
+                        errors.add(
+                            field.clone(),
+                            format!("times must be multiples of {} minutes", CUSTOM_STEP_MINUTES),
+                        );
+                    }
+                }
+                Err(e) => errors.add(field.clone(), e),
+            }
+            if period.weekdays.iter().any(|d| !(1..=7).contains(d)) {
+                errors.add(format!("{}.weekdays", field), "must be between 1 (Monday) and 7 (Sunday)");
+            }
+            validate_price(errors, format!("{}.price", field), period.price);
+        }
+    }
+}
+
+// Tarifa d'un usuari amb els paràmetres ja tipats
+#[derive(Debug, Clone)]
+pub enum Tariff {
+    Pvpc,
+    ThreePeriod(ThreePeriodParams),
+    OmieSpot(OmieSpotParams),
+    Custom(CustomParams),
+}
+
+impl Tariff {
+    pub fn kind(&self) -> TariffKind {
+        match self {
+            Tariff::Pvpc => TariffKind::Pvpc20td,
+            Tariff::ThreePeriod(_) => TariffKind::ThreePeriod,
+            Tariff::OmieSpot(_) => TariffKind::OmieSpot,
+            Tariff::Custom(_) => TariffKind::Custom,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable, Identifiable, Insertable)]
+#[diesel(table_name = user_tariffs, primary_key(user_id))]
+pub struct UserTariff {
+    pub user_id: Uuid,
+    pub kind: String,
+    #[serde(rename = "params")]
+    pub params_json: JsonValue,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// DTO per canviar la tarifa; els paràmetres depenen del tipus
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateTariffRequest {
+    pub kind: TariffKind,
+    #[serde(default)]
+    pub params: Option<JsonValue>,
+}
+
+impl UserTariff {
+    pub fn defaults(user_id: Uuid) -> Self {
+        let now = Utc::now();
+        Self {
+            user_id,
+            kind: TariffKind::Pvpc20td.as_str().to_string(),
+            params_json: json!({}),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn tariff(&self) -> Result<Tariff, String> {
+        let params = self.params_json.clone();
+        let parse_error = |e: serde_json::Error| format!("Invalid {} tariff params: {}", self.kind, e);
+
+        Ok(match self.kind.parse::<TariffKind>()? {
+            TariffKind::Pvpc20td => Tariff::Pvpc,
+            TariffKind::ThreePeriod => Tariff::ThreePeriod(serde_json::from_value(params).map_err(parse_error)?),
+            TariffKind::OmieSpot => Tariff::OmieSpot(serde_json::from_value(params).map_err(parse_error)?),
+            TariffKind::Custom => Tariff::Custom(serde_json::from_value(params).map_err(parse_error)?),
+        })
+    }
+
+    // Substitueix la tarifa per la indicada, amb els paràmetres validats
+    pub fn apply(mut self, update: UpdateTariffRequest) -> Result<Self, ValidationErrors> {
+        let params = update.params.unwrap_or_else(|| json!({}));
+        self.params_json = update.kind.validate_params(&params)?;
+        self.kind = update.kind.as_str().to_string();
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str, hour: u32, minute: u32) -> NaiveDateTime {
+        date.parse::<NaiveDate>().unwrap().and_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn tolls() -> ThreePeriodParams {
+        ThreePeriodParams {
+            punta: Decimal::new(3, 2),
+            llano: Decimal::new(2, 2),
+            valle: Decimal::new(1, 2),
+        }
+    }
+
+    fn period(start: &str, end: &str, weekdays: Vec<u8>, price: Decimal) -> CustomPeriod {
+        CustomPeriod {
+            start: start.to_string(),
+            end: end.to_string(),
+            weekdays,
+            price,
+        }
+    }
+
+    #[test]
+    fn weekday_periods_follow_the_2_0td_hours() {
+        // 2026-10-19 és dilluns
+        let expected = [
+            (0, TouPeriod::Valle),
+            (7, TouPeriod::Valle),
+            (8, TouPeriod::Llano),
+            (10, TouPeriod::Punta),
+            (13, TouPeriod::Punta),
+            (14, TouPeriod::Llano),
+            (18, TouPeriod::Punta),
+            (21, TouPeriod::Punta),
+            (22, TouPeriod::Llano),
+            (23, TouPeriod::Llano),
+        ];
+        for (hour, period) in expected {
+            assert_eq!(TouPeriod::at(at("2026-10-19", hour, 30)), period, "hour {}", hour);
+        }
+    }
+
+    #[test]
+    fn weekends_and_national_holidays_are_valle_all_day() {
+        // Dissabte, diumenge i el 12 d'octubre, que el 2026 cau en dilluns
+        for date in ["2026-10-17", "2026-10-18", "2026-10-12"] {
+            for hour in [0, 9, 12, 19, 23] {
+                assert_eq!(TouPeriod::at(at(date, hour, 0)), TouPeriod::Valle, "{} {}h", date, hour);
+            }
+        }
+    }
+
+    #[test]
+    fn omie_spot_adds_the_margin_and_the_tolls_of_the_period() {
+        let spot = Decimal::new(8, 2);
+        let mut params = OmieSpotParams {
+            margin: Decimal::new(5, 3),
+            tolls: None,
+        };
+        assert_eq!(params.price(spot, at("2026-10-19", 11, 0)), Decimal::new(85, 3));
+
+        params.tolls = Some(tolls());
+        assert_eq!(params.price(spot, at("2026-10-19", 11, 0)), Decimal::new(115, 3));
+        assert_eq!(params.price(spot, at("2026-10-19", 9, 0)), Decimal::new(105, 3));
+        assert_eq!(params.price(spot, at("2026-10-18", 11, 0)), Decimal::new(95, 3));
+    }
+
+    #[test]
+    fn custom_periods_can_cross_midnight() {
+        let params = CustomParams {
+            periods: vec![period("22:00", "06:00", Vec::new(), Decimal::new(5, 2))],
+            default_price: Decimal::new(20, 2),
+        };
+
+        assert_eq!(params.price(at("2026-10-19", 23, 45)), Decimal::new(5, 2));
+        assert_eq!(params.price(at("2026-10-19", 0, 0)), Decimal::new(5, 2));
+        assert_eq!(params.price(at("2026-10-19", 5, 59)), Decimal::new(5, 2));
+        assert_eq!(params.price(at("2026-10-19", 6, 0)), Decimal::new(20, 2));
+        assert_eq!(params.price(at("2026-10-19", 21, 59)), Decimal::new(20, 2));
+    }
+
+    #[test]
+    fn the_first_matching_custom_period_wins_and_weekdays_filter() {
+        let params = CustomParams {
+            periods: vec![
+                period("10:00", "14:00", vec![6, 7], Decimal::new(3, 2)),
+                period("08:00", "20:00", Vec::new(), Decimal::new(10, 2)),
+            ],
+            default_price: Decimal::new(20, 2),
+        };
+
+        // Dissabte
+        assert_eq!(params.price(at("2026-10-17", 11, 0)), Decimal::new(3, 2));
+        // Dilluns: el primer tram no s'aplica
+        assert_eq!(params.price(at("2026-10-19", 11, 0)), Decimal::new(10, 2));
+        assert_eq!(params.price(at("2026-10-19", 21, 0)), Decimal::new(20, 2));
+    }
+
+    #[test]
+    fn custom_resolution_is_hourly_only_when_every_bound_is_on_the_hour() {
+        let mut params = CustomParams {
+            periods: vec![period("22:00", "06:00", Vec::new(), Decimal::new(5, 2))],
+            default_price: Decimal::new(20, 2),
+        };
+        assert_eq!(params.resolution_minutes(), 60);
+
+        params.periods.push(period("13:15", "14:00", Vec::new(), Decimal::new(7, 2)));
+        assert_eq!(params.resolution_minutes(), 15);
+
+        params.periods.clear();
+        assert_eq!(params.resolution_minutes(), 60);
+    }
+}
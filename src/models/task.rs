@@ -0,0 +1,201 @@
+use crate::schema::one_shot_tasks;
+use crate::utils::errors::ValidationErrors;
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Waiting,   // Encara no hi ha preus per a cap bloc abans de l'hora límit
+    Scheduled, // Bloc triat; es pot replanificar si arriben preus nous
+    Started,   // Comanda d'engegada encuada
+    Completed,
+    Expired,
+    Cancelled,
+}
+
+impl TaskStatus {
+    // Estats en què la tasca encara no ha engegat el dispositiu
+    pub const OPEN: [TaskStatus; 2] = [TaskStatus::Waiting, TaskStatus::Scheduled];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskStatus::Waiting => "waiting",
+            TaskStatus::Scheduled => "scheduled",
+            TaskStatus::Started => "started",
+            TaskStatus::Completed => "completed",
+            TaskStatus::Expired => "expired",
+            TaskStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl std::fmt::Display for TaskStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Tasca puntual: engegar el dispositiu una sola vegada durant `duration_minutes`
+// en el bloc més barat entre `not_before` i `deadline`
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable, Identifiable)]
+#[diesel(table_name = one_shot_tasks)]
+pub struct OneShotTask {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub device_id: Uuid,
+    pub duration_minutes: i32,
+    pub not_before: DateTime<Utc>,
+    pub deadline: DateTime<Utc>,
+    pub status: String,
+    pub planned_start: Option<DateTime<Utc>>,
+    pub planned_cost: Option<Decimal>, // Cost per kW de càrrega del bloc triat
+    pub command_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize, Insertable)]
+#[diesel(table_name = one_shot_tasks)]
+pub struct NewOneShotTask {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub device_id: Uuid,
+    pub duration_minutes: i32,
+    pub not_before: DateTime<Utc>,
+    pub deadline: DateTime<Utc>,
+    pub status: String,
+}
+
+// DTO per crear una tasca des de l'app
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateTaskRequest {
+    pub device_id: Uuid,
+    pub duration_minutes: i32,
+    pub deadline: DateTime<Utc>,
+    pub not_before: Option<DateTime<Utc>>, // Per defecte, ara
+}
+
+// Els preus no arriben més enllà de demà, així que l'hora límit tampoc
+const MAX_DEADLINE_HOURS: i64 = 48;
+const DURATION_STEP_MINUTES: i32 = 15;
+
+impl CreateTaskRequest {
+    // Valida la petició i la converteix en una tasca pendent de planificar
+    pub fn into_task(self, user_id: Uuid, now: DateTime<Utc>) -> Result<NewOneShotTask, ValidationErrors> {
+        let not_before = self.not_before.unwrap_or(now).max(now);
+
+        let mut errors = ValidationErrors::new();
+        if self.duration_minutes <= 0
+            || self.duration_minutes > 24 * 60
+            || self.duration_minutes % DURATION_STEP_MINUTES != 0
+        {
+            errors.add(
+                "duration_minutes",
+                format!(
+                    "must be a positive multiple of {} minutes up to 24 hours",
+                    DURATION_STEP_MINUTES
+                ),
+            );
+        }
+        if self.deadline > now + Duration::hours(MAX_DEADLINE_HOURS) {
+            errors.add(
+                "deadline",
+                format!("must be within the next {} hours", MAX_DEADLINE_HOURS),
+            );
+        } else if self.deadline - Duration::minutes(self.duration_minutes as i64) < not_before {
+            errors.add("deadline", "leaves no time to run the task");
+        }
+
+        errors.into_result(NewOneShotTask {
+            id: Uuid::new_v4(),
+            user_id,
+            device_id: self.device_id,
+            duration_minutes: self.duration_minutes,
+            not_before,
+            deadline: self.deadline,
+            status: TaskStatus::Waiting.to_string(),
+        })
+    }
+}
+
+impl OneShotTask {
+    pub fn duration(&self) -> Duration {
+        Duration::minutes(self.duration_minutes as i64)
+    }
+
+    // Últim moment en què encara es pot engegar per acabar a temps
+    pub fn latest_start(&self) -> DateTime<Utc> {
+        self.deadline - self.duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        "2026-10-19T10:07:00Z".parse().unwrap()
+    }
+
+    fn request(duration_minutes: i32, deadline_hours: i64) -> CreateTaskRequest {
+        CreateTaskRequest {
+            device_id: Uuid::new_v4(),
+            duration_minutes,
+            deadline: now() + Duration::hours(deadline_hours),
+            not_before: None,
+        }
+    }
+
+    fn error_fields(result: Result<NewOneShotTask, ValidationErrors>) -> Vec<String> {
+        result.err().map_or_else(Vec::new, |e| e.fields.into_iter().map(|f| f.field).collect())
+    }
+
+    #[test]
+    fn duration_goes_in_quarter_hours_up_to_a_day() {
+        for minutes in [15, 45, 24 * 60] {
+            assert!(request(minutes, 30).into_task(Uuid::new_v4(), now()).is_ok());
+        }
+        for minutes in [0, -15, 50, 24 * 60 + 15] {
+            assert_eq!(
+                error_fields(request(minutes, 30).into_task(Uuid::new_v4(), now())),
+                vec!["duration_minutes"]
+            );
+        }
+    }
+
+    #[test]
+    fn deadline_is_limited_to_the_next_48_hours() {
+        assert!(request(60, 48).into_task(Uuid::new_v4(), now()).is_ok());
+        assert_eq!(
+            error_fields(request(60, 49).into_task(Uuid::new_v4(), now())),
+            vec!["deadline"]
+        );
+    }
+
+    #[test]
+    fn deadline_must_leave_time_to_run_the_task() {
+        let mut late = request(120, 1);
+        assert_eq!(error_fields(late.clone().into_task(Uuid::new_v4(), now())), vec!["deadline"]);
+
+        late.duration_minutes = 60;
+        assert!(late.into_task(Uuid::new_v4(), now()).is_ok());
+    }
+
+    #[test]
+    fn not_before_defaults_to_now_and_never_goes_back() {
+        let user_id = Uuid::new_v4();
+        let task = request(60, 10).into_task(user_id, now()).unwrap();
+        assert_eq!(task.not_before, now());
+        assert_eq!(task.user_id, user_id);
+        assert_eq!(task.status, "waiting");
+
+        let mut past = request(60, 10);
+        past.not_before = Some(now() - Duration::hours(2));
+        assert_eq!(past.into_task(user_id, now()).unwrap().not_before, now());
+    }
+}
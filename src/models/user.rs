@@ -1,5 +1,7 @@
-use crate::schema::{users, grants, mobile_sessions};
+use crate::schema::{users, grants, mobile_sessions, user_settings};
+use crate::utils::errors::ValidationErrors;
 use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -69,6 +71,86 @@ pub struct NewMobileSession {
     pub app_version: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable, Identifiable, Insertable)]
+#[diesel(table_name = user_settings, primary_key(user_id))]
+pub struct UserSettings {
+    pub user_id: Uuid,
+    pub timezone: String, // Nom IANA, p.ex. "Europe/Madrid" o "Atlantic/Canary"
+    pub language: String, // "ca", "es" o "en"
+    pub currency_display: String, // "euros" (€/kWh) o "cents" (c€/kWh)
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// DTO per actualitzar les preferències; els camps absents no canvien
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateSettingsRequest {
+    pub timezone: Option<String>,
+    pub language: Option<String>,
+    pub currency_display: Option<String>,
+}
+
+pub const DEFAULT_TIMEZONE: &str = "Europe/Madrid";
+pub const LANGUAGES: &[&str] = &["ca", "es", "en"];
+pub const CURRENCY_DISPLAYS: &[&str] = &["euros", "cents"];
+
+impl UserSettings {
+    // Valors per defecte per als usuaris que encara no han desat preferències
+    pub fn defaults(user_id: Uuid) -> Self {
+        let now = Utc::now();
+        Self {
+            user_id,
+            timezone: DEFAULT_TIMEZONE.to_string(),
+            language: LANGUAGES[0].to_string(),
+            currency_display: CURRENCY_DISPLAYS[0].to_string(),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn tz(&self) -> Tz {
+        self.timezone.parse().unwrap_or(chrono_tz::Europe::Madrid)
+    }
+
+    // Aplica els canvis validats; retorna tots els errors de camp alhora
+    pub fn apply(mut self, update: UpdateSettingsRequest) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        if let Some(timezone) = update.timezone {
+            match parse_timezone(&timezone) {
+                Ok(tz) => self.timezone = tz.name().to_string(),
+                Err(e) => errors.add("timezone", e),
+            }
+        }
+        if let Some(language) = update.language {
+            if LANGUAGES.contains(&language.as_str()) {
+                self.language = language;
+            } else {
+                errors.add("language", format!("expected one of: {}", LANGUAGES.join(", ")));
+            }
+        }
+        if let Some(currency_display) = update.currency_display {
+            if CURRENCY_DISPLAYS.contains(&currency_display.as_str()) {
+                self.currency_display = currency_display;
+            } else {
+                errors.add(
+                    "currency_display",
+                    format!("expected one of: {}", CURRENCY_DISPLAYS.join(", ")),
+                );
+            }
+        }
+
+        errors.into_result(self)
+    }
+}
+
+// Valida un nom de zona horària IANA
+pub fn parse_timezone(name: &str) -> Result<Tz, String> {
+    name.parse::<Tz>()
+        .map_err(|_| format!("unknown timezone '{}', expected an IANA name like Europe/Madrid", name))
+}
+
 impl User {
     pub fn new(google_sub: String, email: String, name: String, picture: Option<String>) -> NewUser {
         NewUser {
@@ -12,6 +12,16 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    command_events (id) {
+        id -> Uuid,
+        command_id -> Uuid,
+        status -> Varchar,
+        message -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     commands (id) {
         id -> Uuid,
@@ -25,6 +35,7 @@ diesel::table! {
         created_at -> Timestamptz,
         executed_at -> Nullable<Timestamptz>,
         updated_at -> Timestamptz,
+        schedule_id -> Nullable<Uuid>,
     }
 }
 
@@ -36,6 +47,8 @@ diesel::table! {
         prices_json -> Jsonb,
         source -> Varchar,
         created_at -> Timestamptz,
+        resolution_minutes -> Int4,
+        tariff -> Varchar,
     }
 }
 
@@ -88,6 +101,42 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    one_shot_tasks (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        device_id -> Uuid,
+        duration_minutes -> Int4,
+        not_before -> Timestamptz,
+        deadline -> Timestamptz,
+        status -> Varchar,
+        planned_start -> Nullable<Timestamptz>,
+        planned_cost -> Nullable<Numeric>,
+        command_id -> Nullable<Uuid>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    price_alerts (user_id) {
+        user_id -> Uuid,
+        enabled -> Bool,
+        notify_cheap -> Bool,
+        notify_expensive -> Bool,
+        cheap_percentile -> Int4,
+        expensive_percentile -> Int4,
+        cheap_threshold -> Nullable<Numeric>,
+        expensive_threshold -> Nullable<Numeric>,
+        min_hours -> Int4,
+        lead_minutes -> Int4,
+        last_cheap_alert -> Nullable<Timestamptz>,
+        last_expensive_alert -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     rules (id) {
         id -> Uuid,
@@ -100,6 +149,7 @@ diesel::table! {
         enabled -> Bool,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        applicability_json -> Jsonb,
     }
 }
 
@@ -115,6 +165,7 @@ diesel::table! {
         status -> Varchar,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        last_fired_slot -> Nullable<Int4>,
     }
 }
 
@@ -129,6 +180,27 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    user_settings (user_id) {
+        user_id -> Uuid,
+        timezone -> Varchar,
+        language -> Varchar,
+        currency_display -> Varchar,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    user_tariffs (user_id) {
+        user_id -> Uuid,
+        kind -> Varchar,
+        params_json -> Jsonb,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Uuid,
@@ -144,30 +216,43 @@ diesel::table! {
 diesel::joinable!(automation_logs -> devices (device_id));
 diesel::joinable!(automation_logs -> rules (rule_id));
 diesel::joinable!(automation_logs -> users (user_id));
+diesel::joinable!(command_events -> commands (command_id));
 diesel::joinable!(commands -> devices (device_id));
+diesel::joinable!(commands -> schedules (schedule_id));
 diesel::joinable!(commands -> users (user_id));
 diesel::joinable!(device_states -> devices (device_id));
 diesel::joinable!(devices -> structures (structure_id));
 diesel::joinable!(devices -> users (user_id));
 diesel::joinable!(grants -> users (user_id));
 diesel::joinable!(mobile_sessions -> users (user_id));
+diesel::joinable!(one_shot_tasks -> commands (command_id));
+diesel::joinable!(one_shot_tasks -> devices (device_id));
+diesel::joinable!(one_shot_tasks -> users (user_id));
+diesel::joinable!(price_alerts -> users (user_id));
 diesel::joinable!(rules -> devices (device_id));
 diesel::joinable!(rules -> users (user_id));
 diesel::joinable!(schedules -> devices (device_id));
 diesel::joinable!(schedules -> rules (rule_id));
 diesel::joinable!(schedules -> users (user_id));
 diesel::joinable!(structures -> users (user_id));
+diesel::joinable!(user_settings -> users (user_id));
+diesel::joinable!(user_tariffs -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     automation_logs,
+    command_events,
     commands,
     day_prices,
     device_states,
     devices,
     grants,
     mobile_sessions,
+    one_shot_tasks,
+    price_alerts,
     rules,
     schedules,
     structures,
+    user_settings,
+    user_tariffs,
     users,
 );
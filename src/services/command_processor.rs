@@ -0,0 +1,892 @@
+use crate::{
+    models::{
+        command::{
+            Command, CommandEvent, CommandResult, CommandStatus, NewAutomationLog, NewCommand,
+            NewCommandEvent,
+        },
+        device::NewDeviceState,
+    },
+    schema::{automation_logs, command_events, commands, device_states},
+    services::{
+        push_notifier::{self, PushNotifier},
+        schedule_executor,
+        ws_hub::{WsEvent, WsHub},
+    },
+    DbPool,
+};
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use serde_json::{json, Value as JsonValue};
+use std::sync::Arc;
+use uuid::Uuid;
+
+const TICK_SECONDS: u64 = 30;
+const EXPIRE_MINUTES: i64 = 5;
+const MAX_RETRIES: i32 = 3;
+
+pub const VIA_HEARTBEAT: &str = "Delivered in heartbeat";
+pub const VIA_WEBSOCKET: &str = "Delivered over WebSocket";
+const UNDELIVERED: &str = "WebSocket closed before delivery";
+
+#[derive(Debug, thiserror::Error)]
+pub enum CommandError {
+    #[error("Command not found")]
+    NotFound,
+    #[error("Command is {from} and cannot become {to}")]
+    InvalidTransition { from: String, to: String },
+    #[error("Database error: {0}")]
+    Database(String),
+}
+
+impl From<diesel::result::Error> for CommandError {
+    fn from(e: diesel::result::Error) -> Self {
+        match e {
+            diesel::result::Error::NotFound => CommandError::NotFound,
+            e => CommandError::Database(e.to_string()),
+        }
+    }
+}
+
+// Comprova que el canvi d'estat és permès abans de tocar la base de dades
+fn ensure_transition(from: &CommandStatus, to: &CommandStatus) -> Result<(), CommandError> {
+    if from.can_transition_to(to) {
+        Ok(())
+    } else {
+        Err(CommandError::InvalidTransition {
+            from: from.to_string(),
+            to: to.to_string(),
+        })
+    }
+}
+
+// Afegeix una entrada a l'historial de la comanda
+pub fn record_event(
+    conn: &mut PgConnection,
+    command_id: Uuid,
+    status: &CommandStatus,
+    message: Option<String>,
+) -> Result<(), diesel::result::Error> {
+    diesel::insert_into(command_events::table)
+        .values(&NewCommandEvent {
+            id: Uuid::new_v4(),
+            command_id,
+            status: status.to_string(),
+            message,
+        })
+        .execute(conn)?;
+    Ok(())
+}
+
+// Desa l'estat reportat d'un dispositiu, creant-ne la fila si encara no en té
+pub fn save_device_state(
+    conn: &mut PgConnection,
+    device_id: Uuid,
+    state: &JsonValue,
+) -> Result<(), diesel::result::Error> {
+    let now = Utc::now();
+    diesel::insert_into(device_states::table)
+        .values(&NewDeviceState {
+            id: Uuid::new_v4(),
+            device_id,
+            state_json: state.clone(),
+            updated_at: now,
+        })
+        .on_conflict(device_states::device_id)
+        .do_update()
+        .set((
+            device_states::state_json.eq(state),
+            device_states::updated_at.eq(now),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+// Crea una comanda encuada i n'inicia l'historial
+pub fn insert_command(
+    conn: &mut PgConnection,
+    new_command: &NewCommand,
+    message: &str,
+) -> Result<Command, diesel::result::Error> {
+    conn.transaction(|conn| {
+        let command = diesel::insert_into(commands::table)
+            .values(new_command)
+            .get_result::<Command>(conn)?;
+        record_event(conn, command.id, &CommandStatus::Queued, Some(message.to_string()))?;
+        Ok(command)
+    })
+}
+
+// Registra el resultat d'una comanda reportat per l'app mòbil de l'usuari.
+// Només s'accepten resultats de comandes pròpies que s'han enviat i encara no tenen resultat.
+pub async fn record_result(
+    pool: &DbPool,
+    user_id: Uuid,
+    result: CommandResult,
+) -> Result<Command, CommandError> {
+    let conn = pool
+        .get()
+        .await
+        .map_err(|e| CommandError::Database(e.to_string()))?;
+
+    conn.interact(move |conn| record_result_sync(conn, user_id, result))
+        .await
+        .map_err(|e| CommandError::Database(e.to_string()))?
+}
+
+fn record_result_sync(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    result: CommandResult,
+) -> Result<Command, CommandError> {
+    let next = if result.success {
+        CommandStatus::Acked
+    } else {
+        CommandStatus::Failed
+    };
+
+    conn.transaction(|conn| {
+        // Les comandes d'altres usuaris es tracten com inexistents
+        let command = commands::table
+            .find(result.command_id)
+            .filter(commands::user_id.eq(user_id))
+            .for_update()
+            .first::<Command>(conn)?;
+
+        // L'app només pot informar de comandes que ha rebut
+        let current = command.get_status();
+        if current == CommandStatus::Queued {
+            return Err(CommandError::InvalidTransition {
+                from: command.status.clone(),
+                to: next.to_string(),
+            });
+        }
+        ensure_transition(&current, &next)?;
+
+        // L'hora d'execució la dona l'app, però mai en el futur
+        let now = Utc::now();
+        let executed_at = result.executed_at.min(now);
+
+        let updated = diesel::update(commands::table.find(command.id))
+            .set((
+                commands::status.eq(next.to_string()),
+                commands::error_message.eq(&result.error_message),
+                commands::executed_at.eq(Some(executed_at)),
+                commands::updated_at.eq(now),
+            ))
+            .get_result::<Command>(conn)?;
+
+        // L'estat reportat és l'estat real del dispositiu, tant si la comanda ha funcionat com si no
+        if let Some(new_state) = &result.new_state {
+            save_device_state(conn, command.device_id, new_state)?;
+        }
+
+        record_event(conn, command.id, &next, result.error_message.clone())?;
+
+        diesel::insert_into(automation_logs::table)
+            .values(&NewAutomationLog {
+                id: Uuid::new_v4(),
+                user_id,
+                device_id: Some(command.device_id),
+                rule_id: None,
+                action: format!("command_{}", next),
+                details_json: Some(json!({
+                    "command_id": command.id,
+                    "command_type": command.command_type,
+                    "schedule_id": command.schedule_id,
+                    "executed_at": executed_at,
+                    "error_message": result.error_message,
+                    "new_state": result.new_state,
+                })),
+            })
+            .execute(conn)?;
+
+        log::info!(
+            "Command {} for device {} marked as {}",
+            command.id,
+            command.device_id,
+            next
+        );
+
+        Ok(updated)
+    })
+}
+
+// Publica pel WebSocket el nou estat de la comanda i, si n'hi ha, l'estat del dispositiu
+pub fn publish_result(hub: &WsHub, command: &Command, new_state: Option<JsonValue>) {
+    hub.send_to_user(command.user_id, &WsEvent::command(command));
+    if let Some(state) = new_state {
+        hub.send_to_user(
+            command.user_id,
+            &WsEvent::DeviceState {
+                device_id: command.device_id,
+                state,
+            },
+        );
+    }
+}
+
+// Marca com a enviades les comandes encuades de l'usuari que es lliuren a l'app.
+// Una comanda només passa a sent una vegada, així que mai es lliura per dues vies.
+pub async fn deliver_pending(
+    pool: &DbPool,
+    user_id: Uuid,
+    limit: i64,
+    via: &'static str,
+) -> Result<Vec<Command>, CommandError> {
+    let conn = pool
+        .get()
+        .await
+        .map_err(|e| CommandError::Database(e.to_string()))?;
+
+    ensure_transition(&CommandStatus::Queued, &CommandStatus::Sent)?;
+
+    conn.interact(move |conn| {
+        conn.transaction(|conn| {
+            let ids = commands::table
+                .filter(commands::user_id.eq(user_id))
+                .filter(commands::status.eq(CommandStatus::Queued.to_string()))
+                .order(commands::created_at.asc())
+                .limit(limit)
+                .select(commands::id)
+                .for_update()
+                .skip_locked()
+                .load::<Uuid>(conn)?;
+
+            if ids.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            let mut delivered = diesel::update(
+                commands::table
+                    .filter(commands::id.eq_any(ids))
+                    .filter(commands::status.eq(CommandStatus::Queued.to_string())),
+            )
+            .set((
+                commands::status.eq(CommandStatus::Sent.to_string()),
+                commands::updated_at.eq(Utc::now()),
+            ))
+            .get_results::<Command>(conn)?;
+
+            for command in &delivered {
+                record_event(conn, command.id, &CommandStatus::Sent, Some(via.to_string()))?;
+            }
+
+            delivered.sort_by_key(|c| c.created_at);
+            Ok(delivered)
+        })
+    })
+    .await
+    .map_err(|e| CommandError::Database(e.to_string()))?
+}
+
+// Canvia una comanda d'estat només si encara és a l'estat esperat i la transició és permesa
+async fn transition(
+    pool: &DbPool,
+    command_id: Uuid,
+    from: CommandStatus,
+    to: CommandStatus,
+    message: &'static str,
+) -> Result<Option<Command>, CommandError> {
+    ensure_transition(&from, &to)?;
+
+    let conn = pool
+        .get()
+        .await
+        .map_err(|e| CommandError::Database(e.to_string()))?;
+
+    conn.interact(move |conn| {
+        conn.transaction(|conn| {
+            let updated = diesel::update(
+                commands::table
+                    .find(command_id)
+                    .filter(commands::status.eq(from.to_string())),
+            )
+            .set((
+                commands::status.eq(to.to_string()),
+                commands::updated_at.eq(Utc::now()),
+            ))
+            .get_result::<Command>(conn)
+            .optional()?;
+
+            if updated.is_some() {
+                record_event(conn, command_id, &to, Some(message.to_string()))?;
+            }
+            Ok(updated)
+        })
+    })
+    .await
+    .map_err(|e| CommandError::Database(e.to_string()))?
+}
+
+// Fa arribar una comanda encuada a l'app. Si té un WebSocket subscrit s'hi envia
+// directament (primer es marca sent i després s'envia, de manera que com a molt es
+// lliura una vegada); si no, es desperta l'app amb un push perquè faci heartbeat.
+// Una comanda sent no torna mai a la cua: si el socket es tanca abans d'enviar-la
+// passa a failed i és el reintent qui la torna a encuar.
+pub async fn dispatch_command(
+    pool: &DbPool,
+    notifier: &dyn PushNotifier,
+    hub: &WsHub,
+    command: &Command,
+) {
+    hub.send_to_user(command.user_id, &WsEvent::command(command));
+
+    if hub.has_command_transport(command.user_id) {
+        match transition(pool, command.id, CommandStatus::Queued, CommandStatus::Sent, VIA_WEBSOCKET).await {
+            Ok(Some(sent)) => {
+                if hub.send_command(&sent) {
+                    hub.send_to_user(sent.user_id, &WsEvent::command(&sent));
+                    return;
+                }
+
+                // El socket s'ha tancat entremig: no s'ha lliurat, així que es pot reintentar
+                if let Err(e) = fail_undelivered(pool, &sent).await {
+                    log::error!("Failed to mark command {} as undelivered: {}", sent.id, e);
+                }
+                return;
+            }
+            // Ja s'ha lliurat per una altra via
+            Ok(None) => return,
+            Err(e) => log::error!("Failed to deliver command {} over WebSocket: {}", command.id, e),
+        }
+    }
+
+    push_notifier::notify_command_queued(
+        pool,
+        notifier,
+        command.user_id,
+        command.id,
+        command.device_id,
+    )
+    .await;
+}
+
+// Envia pel WebSocket les comandes que esperaven a la cua (p.ex. en subscriure's)
+pub async fn flush_to_websocket(pool: &DbPool, hub: &WsHub, user_id: Uuid) -> Result<usize, CommandError> {
+    let delivered = deliver_pending(pool, user_id, 50, VIA_WEBSOCKET).await?;
+
+    let mut sent = 0;
+    for command in delivered {
+        if hub.send_command(&command) {
+            hub.send_to_user(user_id, &WsEvent::command(&command));
+            sent += 1;
+        } else {
+            fail_undelivered(pool, &command).await?;
+        }
+    }
+
+    Ok(sent)
+}
+
+// Marca com a fallida una comanda que s'havia marcat sent però que no s'ha pogut enviar
+async fn fail_undelivered(pool: &DbPool, command: &Command) -> Result<(), CommandError> {
+    let command_id = command.id;
+    let next = CommandStatus::Failed;
+    ensure_transition(&CommandStatus::Sent, &next)?;
+
+    let conn = pool
+        .get()
+        .await
+        .map_err(|e| CommandError::Database(e.to_string()))?;
+
+    conn.interact(move |conn| {
+        conn.transaction(|conn| {
+            let updated = diesel::update(
+                commands::table
+                    .find(command_id)
+                    .filter(commands::status.eq(CommandStatus::Sent.to_string())),
+            )
+            .set((
+                commands::status.eq(next.to_string()),
+                commands::error_message.eq(UNDELIVERED),
+                commands::updated_at.eq(Utc::now()),
+            ))
+            .execute(conn)?;
+
+            if updated > 0 {
+                record_event(conn, command_id, &next, Some(UNDELIVERED.to_string()))?;
+            }
+            Ok(())
+        })
+    })
+    .await
+    .map_err(|e| CommandError::Database(e.to_string()))?
+}
+
+// Comanda de l'usuari amb el seu historial, del més antic al més recent
+pub async fn get_command_history(
+    pool: &DbPool,
+    user_id: Uuid,
+    command_id: Uuid,
+) -> Result<(Command, Vec<CommandEvent>), CommandError> {
+    let conn = pool
+        .get()
+        .await
+        .map_err(|e| CommandError::Database(e.to_string()))?;
+
+    conn.interact(move |conn| {
+        let command = commands::table
+            .find(command_id)
+            .filter(commands::user_id.eq(user_id))
+            .first::<Command>(conn)?;
+
+        let events = command_events::table
+            .filter(command_events::command_id.eq(command_id))
+            .order(command_events::created_at.asc())
+            .load::<CommandEvent>(conn)?;
+
+        Ok((command, events))
+    })
+    .await
+    .map_err(|e| CommandError::Database(e.to_string()))?
+}
+
+// Tasca en segon pla: fa caducar les comandes encallades i reintenta les fallides
+pub async fn run_command_processor(
+    pool: DbPool,
+    notifier: Arc<dyn PushNotifier>,
+    hub: Arc<WsHub>,
+) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(TICK_SECONDS));
+    loop {
+        interval.tick().await;
+        match process_commands(&pool).await {
+            Ok((expired, requeued)) => {
+                for command in &expired {
+                    hub.send_to_user(command.user_id, &WsEvent::command(command));
+                }
+                for command in &requeued {
+                    dispatch_command(&pool, notifier.as_ref(), hub.as_ref(), command).await;
+                }
+            }
+            Err(e) => log::error!("Command processor failed: {}", e),
+        }
+    }
+}
+
+// Retorna les comandes que han caducat o han quedat obsoletes i les que s'han tornat a encuar
+pub async fn process_commands(pool: &DbPool) -> Result<(Vec<Command>, Vec<Command>), CommandError> {
+    let conn = pool
+        .get()
+        .await
+        .map_err(|e| CommandError::Database(e.to_string()))?;
+
+    conn.interact(|conn| {
+        let mut expired = expire_stale_commands(conn)?;
+        expired.extend(expire_ended_slot_commands(conn)?);
+        let superseded = supersede_failed_commands(conn)?;
+        let requeued = requeue_failed_commands(conn)?;
+
+        if !expired.is_empty() || !superseded.is_empty() || !requeued.is_empty() {
+            log::info!(
+                "Expired {} commands, superseded {}, re-queued {}",
+                expired.len(),
+                superseded.len(),
+                requeued.len()
+            );
+        }
+        // Les superseded també es notifiquen com a canvi d'estat
+        expired.extend(superseded);
+        Ok((expired, requeued))
+    })
+    .await
+    .map_err(|e| CommandError::Database(e.to_string()))?
+}
+
+fn expire_stale_commands(conn: &mut PgConnection) -> Result<Vec<Command>, CommandError> {
+    let cutoff = Utc::now() - Duration::minutes(EXPIRE_MINUTES);
+    let stale = commands::table
+        .filter(commands::status.eq_any([
+            CommandStatus::Queued.to_string(),
+            CommandStatus::Sent.to_string(),
+        ]))
+        .filter(commands::updated_at.le(cutoff))
+        .load::<Command>(conn)?;
+
+    let mut expired = Vec::new();
+    for command in stale.into_iter().filter(Command::should_expire) {
+        // Una comanda encuada no ha arribat a l'app i es pot reintentar; una d'enviada
+        // pot haver-se executat i queda expired, sense reintents.
+        let current = command.get_status();
+        let (next, reason) = match current {
+            CommandStatus::Queued => (
+                CommandStatus::Failed,
+                format!("Not delivered within {} minutes", EXPIRE_MINUTES),
+            ),
+            _ => (
+                CommandStatus::Expired,
+                format!("No result reported within {} minutes", EXPIRE_MINUTES),
+            ),
+        };
+        ensure_transition(&current, &next)?;
+
+        let updated = conn.transaction(|conn| {
+            // Només si ningú l'ha modificat mentrestant
+            let updated = diesel::update(
+                commands::table
+                    .find(command.id)
+                    .filter(commands::status.eq(&command.status))
+                    .filter(commands::updated_at.eq(command.updated_at)),
+            )
+            .set((
+                commands::status.eq(next.to_string()),
+                commands::error_message.eq(&reason),
+                commands::updated_at.eq(Utc::now()),
+            ))
+            .get_result::<Command>(conn)
+            .optional()?;
+
+            if updated.is_some() {
+                record_event(conn, command.id, &next, Some(reason.clone()))?;
+            }
+            Ok::<_, diesel::result::Error>(updated)
+        })?;
+
+        expired.extend(updated);
+    }
+
+    Ok(expired)
+}
+
+// Una comanda fallida d'un horari només té sentit mentre dura el seu slot: si el slot
+// ja s'ha acabat, reintentar-la encendria o apagaria el dispositiu fora d'hora.
+fn expire_ended_slot_commands(conn: &mut PgConnection) -> Result<Vec<Command>, CommandError> {
+    let next = CommandStatus::Expired;
+    ensure_transition(&CommandStatus::Failed, &next)?;
+
+    let now = Utc::now();
+    let failed = commands::table
+        .filter(commands::status.eq(CommandStatus::Failed.to_string()))
+        .filter(commands::retry_count.lt(MAX_RETRIES))
+        .filter(commands::schedule_id.is_not_null())
+        .load::<Command>(conn)?;
+
+    let mut expired = Vec::new();
+    for command in failed {
+        let Some(schedule_id) = command.schedule_id else {
+            continue;
+        };
+        let Some(slot_end) = schedule_executor::fired_slot_end(conn, schedule_id, command.created_at)? else {
+            continue;
+        };
+        if now < slot_end {
+            continue;
+        }
+
+        let reason = format!("Schedule slot ended at {} before a retry", slot_end.to_rfc3339());
+        let updated = conn.transaction(|conn| {
+            let updated = diesel::update(
+                commands::table
+                    .find(command.id)
+                    .filter(commands::status.eq(CommandStatus::Failed.to_string()))
+                    .filter(commands::retry_count.eq(command.retry_count)),
+            )
+            .set((
+                commands::status.eq(next.to_string()),
+                commands::error_message.eq(&reason),
+                commands::updated_at.eq(Utc::now()),
+            ))
+            .get_result::<Command>(conn)
+            .optional()?;
+
+            if updated.is_some() {
+                record_event(conn, command.id, &next, Some(reason.clone()))?;
+            }
+            Ok::<_, diesel::result::Error>(updated)
+        })?;
+
+        expired.extend(updated);
+    }
+
+    Ok(expired)
+}
+
+// Una comanda més nova del mateix tipus pel mateix dispositiu deixa obsoletes les
+// fallides anteriors (p.ex. el slot següent). Es marquen superseded d'una sola consulta perquè surtin
+// de la cua de reintents per sempre.
+fn supersede_failed_commands(conn: &mut PgConnection) -> Result<Vec<Command>, CommandError> {
+    ensure_transition(&CommandStatus::Failed, &CommandStatus::Superseded)?;
+
+    let newer = diesel::alias!(commands as newer);
+    conn.transaction(|conn| {
+        let superseded = diesel::update(
+            commands::table
+                .filter(commands::status.eq(CommandStatus::Failed.to_string()))
+                .filter(commands::retry_count.lt(MAX_RETRIES))
+                .filter(diesel::dsl::exists(
+                    newer
+                        .filter(newer.field(commands::device_id).eq(commands::device_id))
+                        .filter(newer.field(commands::command_type).eq(commands::command_type))
+                        .filter(newer.field(commands::created_at).gt(commands::created_at)),
+                )),
+        )
+        .set((
+            commands::status.eq(CommandStatus::Superseded.to_string()),
+            commands::updated_at.eq(Utc::now()),
+        ))
+        .get_results::<Command>(conn)?;
+
+        for command in &superseded {
+            record_event(
+                conn,
+                command.id,
+                &CommandStatus::Superseded,
+                Some(format!("Replaced by a newer {} command for the device", command.command_type)),
+            )?;
+        }
+        Ok(superseded)
+    })
+}
+
+fn requeue_failed_commands(conn: &mut PgConnection) -> Result<Vec<Command>, CommandError> {
+    ensure_transition(&CommandStatus::Failed, &CommandStatus::Queued)?;
+
+    let now = Utc::now();
+    let failed = commands::table
+        .filter(commands::status.eq(CommandStatus::Failed.to_string()))
+        .filter(commands::retry_count.lt(MAX_RETRIES))
+        .load::<Command>(conn)?;
+
+    let mut requeued = Vec::new();
+    for command in failed {
+        if !command.is_retriable() || command.retry_due_at() > now {
+            continue;
+        }
+
+        let retry = command.retry_count + 1;
+        let updated = conn.transaction(|conn| {
+            let updated = diesel::update(
+                commands::table
+                    .find(command.id)
+                    .filter(commands::status.eq(CommandStatus::Failed.to_string()))
+                    .filter(commands::retry_count.eq(command.retry_count)),
+            )
+            .set((
+                commands::status.eq(CommandStatus::Queued.to_string()),
+                commands::retry_count.eq(retry),
+                commands::error_message.eq(None::<String>),
+                commands::executed_at.eq(None::<chrono::DateTime<Utc>>),
+                commands::updated_at.eq(Utc::now()),
+            ))
+            .get_result::<Command>(conn)
+            .optional()?;
+
+            if updated.is_some() {
+                let message = format!(
+                    "Retry {} after: {}",
+                    retry,
+                    command.error_message.as_deref().unwrap_or("unknown error")
+                );
+                record_event(conn, command.id, &CommandStatus::Queued, Some(message))?;
+            }
+            Ok::<_, diesel::result::Error>(updated)
+        })?;
+
+        requeued.extend(updated);
+    }
+
+    Ok(requeued)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        device::NewDevice,
+        rule::NewRule,
+        schedule::{NewSchedule, TimeSlot},
+        user::NewUser,
+    };
+    use crate::schema::{devices, rules, schedules, users};
+    use rust_decimal::Decimal;
+    use diesel_migrations::MigrationHarness;
+    use std::collections::BTreeSet;
+
+    // Base de dades de proves: `TEST_DATABASE_URL=... cargo test -- --ignored`
+    fn test_connection() -> PgConnection {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+        let mut conn = PgConnection::establish(&url).expect("Failed to connect to database");
+        conn.run_pending_migrations(crate::MIGRATIONS)
+            .expect("Failed to run migrations");
+        conn
+    }
+
+    fn test_device(conn: &mut PgConnection) -> (Uuid, Uuid) {
+        let user_id = Uuid::new_v4();
+        diesel::insert_into(users::table)
+            .values(&NewUser {
+                id: user_id,
+                google_sub: format!("test-{}", user_id),
+                email: "commands@example.com".to_string(),
+                name: "Commands".to_string(),
+                picture: None,
+            })
+            .execute(conn)
+            .unwrap();
+        let device_id = Uuid::new_v4();
+        diesel::insert_into(devices::table)
+            .values(&NewDevice {
+                id: device_id,
+                user_id,
+                structure_id: None,
+                google_device_id: format!("test-{}", device_id),
+                name: "Boiler".to_string(),
+                device_type: "SWITCH".to_string(),
+                room: None,
+                capabilities_json: json!({ "traits": ["OnOff", "Brightness"] }),
+                last_seen_at: Utc::now(),
+            })
+            .execute(conn)
+            .unwrap();
+        (user_id, device_id)
+    }
+
+    fn failed_command(
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        device_id: Uuid,
+        command_type: &str,
+        retry_count: i32,
+        age_minutes: i64,
+    ) -> Uuid {
+        let id = Uuid::new_v4();
+        diesel::insert_into(commands::table)
+            .values(&NewCommand {
+                id,
+                user_id,
+                device_id,
+                command_type: command_type.to_string(),
+                payload_json: json!({ "on": true }),
+                status: CommandStatus::Failed.to_string(),
+                retry_count,
+                schedule_id: None,
+            })
+            .execute(conn)
+            .unwrap();
+        let at = Utc::now() - Duration::minutes(age_minutes);
+        diesel::update(commands::table.find(id))
+            .set((commands::created_at.eq(at), commands::updated_at.eq(at)))
+            .execute(conn)
+            .unwrap();
+        id
+    }
+
+    fn command_status(conn: &mut PgConnection, id: Uuid) -> String {
+        commands::table
+            .find(id)
+            .select(commands::status)
+            .first::<String>(conn)
+            .unwrap()
+    }
+
+    #[test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    fn newer_commands_supersede_failed_ones_and_leave_the_retry_queue() {
+        let mut conn = test_connection();
+        conn.test_transaction::<_, diesel::result::Error, _>(|conn| {
+            let (user_id, device_id) = test_device(conn);
+
+            let old = failed_command(conn, user_id, device_id, "on_off", 0, 30);
+            // Més nova que `old`, però d'un altre tipus: no la substitueix ni queda substituïda
+            let brightness = failed_command(conn, user_id, device_id, "brightness", 0, 25);
+            let exhausted = failed_command(conn, user_id, device_id, "on_off", MAX_RETRIES, 20);
+            let latest = failed_command(conn, user_id, device_id, "on_off", 0, 10);
+
+            // La base de dades pot tenir altres comandes: només es miren les d'aquest dispositiu
+            let ids = |commands: Vec<Command>| {
+                commands
+                    .into_iter()
+                    .filter(|c| c.device_id == device_id)
+                    .map(|c| c.id)
+                    .collect::<BTreeSet<_>>()
+            };
+
+            assert_eq!(ids(supersede_failed_commands(conn).unwrap()), BTreeSet::from([old]));
+
+            assert_eq!(command_status(conn, old), "superseded");
+            assert_eq!(command_status(conn, exhausted), "failed");
+
+            // Es reintenten la més nova de cada tipus, i la superseded ja no torna a sortir
+            assert_eq!(
+                ids(requeue_failed_commands(conn).unwrap()),
+                BTreeSet::from([brightness, latest])
+            );
+            assert!(ids(supersede_failed_commands(conn).unwrap()).is_empty());
+            assert_eq!(command_status(conn, old), "superseded");
+            Ok(())
+        });
+    }
+
+    #[test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    fn schedule_commands_are_not_retried_after_their_slot_ends() {
+        let mut conn = test_connection();
+        conn.test_transaction::<_, diesel::result::Error, _>(|conn| {
+            let (user_id, device_id) = test_device(conn);
+            let rule_id = Uuid::new_v4();
+            diesel::insert_into(rules::table)
+                .values(&NewRule {
+                    id: rule_id,
+                    user_id,
+                    device_id,
+                    rule_type: "MIN_HOURS_CHEAPEST".to_string(),
+                    params_json: json!({ "min_hours_per_day": 2 }),
+                    timezone: "Europe/Madrid".to_string(),
+                    priority: 1,
+                    enabled: true,
+                    applicability_json: json!({}),
+                })
+                .execute(conn)?;
+
+            // Un slot que ja s'ha acabat i un que encara dura
+            let now = Utc::now();
+            let slot = |from: i64, to: i64| TimeSlot {
+                start: String::new(),
+                end: String::new(),
+                action: "on".to_string(),
+                start_at: Some(now + Duration::hours(from)),
+                end_at: Some(now + Duration::hours(to)),
+                rule_id: None,
+            };
+            let schedule_id = Uuid::new_v4();
+            diesel::insert_into(schedules::table)
+                .values(&NewSchedule {
+                    id: schedule_id,
+                    user_id,
+                    device_id,
+                    rule_id,
+                    date: now.date_naive(),
+                    slots_json: serde_json::to_value(vec![slot(-3, -1), slot(-1, 1)]).unwrap(),
+                    total_cost: Decimal::ZERO,
+                    status: "active".to_string(),
+                })
+                .execute(conn)?;
+
+            let ended = failed_command(conn, user_id, device_id, "on_off", 0, 120);
+            let running = failed_command(conn, user_id, device_id, "on_off", 0, 30);
+            let manual = failed_command(conn, user_id, device_id, "brightness", 0, 120);
+            diesel::update(commands::table.filter(commands::id.eq_any([ended, running])))
+                .set(commands::schedule_id.eq(Some(schedule_id)))
+                .execute(conn)?;
+
+            let expired: Vec<Uuid> = expire_ended_slot_commands(conn)
+                .unwrap()
+                .into_iter()
+                .filter(|c| c.device_id == device_id)
+                .map(|c| c.id)
+                .collect();
+            assert_eq!(expired, vec![ended]);
+            assert_eq!(command_status(conn, ended), "expired");
+
+            // La del slot vigent i la manual es continuen reintentant
+            let requeued: BTreeSet<Uuid> = requeue_failed_commands(conn)
+                .unwrap()
+                .into_iter()
+                .filter(|c| c.device_id == device_id)
+                .map(|c| c.id)
+                .collect();
+            assert_eq!(requeued, BTreeSet::from([running, manual]));
+            Ok(())
+        });
+    }
+}
@@ -1,6 +1,13 @@
 // Services module
-// TODO: Afegir serveis per:
-// - FCM (push notifications)
-// - Price fetching (API de preus elèctrics)
-// - Schedule optimization
-// - Command processing
+pub mod command_processor;
+pub mod optimizer;
+pub mod price_alerts;
+pub mod price_cache;
+pub mod price_fetcher;
+pub mod push_notifier;
+pub mod schedule_builder;
+pub mod schedule_executor;
+pub mod settings;
+pub mod tariffs;
+pub mod tasks;
+pub mod ws_hub;
@@ -0,0 +1,1525 @@
+use crate::models::{
+    price,
+    rule::{
+        DeadlineEnergyParams, MinHoursCheapestParams, PriceThresholdParams, RuleType, TimeWindow,
+        XHoursWithinWindowsParams, WINDOW_STEP_MINUTES,
+    },
+    schedule::{PricePeriod, TimeSlot},
+};
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use rust_decimal::Decimal;
+use serde_json::Value as JsonValue;
+use std::collections::BTreeSet;
+use uuid::Uuid;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+#[derive(Debug, thiserror::Error)]
+pub enum OptimizerError {
+    #[error("No prices available for the requested day")]
+    NoPrices,
+    #[error("Invalid rule parameters: {0}")]
+    InvalidParams(String),
+    #[error("No schedule satisfies the rule: {0}")]
+    Infeasible(String),
+}
+
+// Resultat de l'optimització, a punt per desar com a `NewSchedule`
+#[derive(Debug, Clone)]
+pub struct OptimizedSchedule {
+    pub slots: Vec<TimeSlot>,
+    // Cost per kW de càrrega (€/kWh × hores enceses)
+    pub total_cost: Decimal,
+    pub total_hours: f32,
+    // Cost de les mateixes hores al preu mitjà del dia
+    pub average_cost: Decimal,
+    pub savings: Decimal,
+    pub savings_percentage: Option<f32>,
+}
+
+// Unitat mínima de planificació (un període de preus de 60 o 15 minuts, o una
+// fracció si les finestres o el bloc mínim el tallen). Les unitats van en ordre
+// cronològic: els dies de canvi horari n'hi ha 23 o 25 hores i `start_minute`
+// (hora de rellotge, per comparar amb les finestres) pot saltar o repetir-se.
+#[derive(Debug, Clone)]
+struct Unit {
+    start: DateTime<Utc>,
+    start_minute: u32,
+    minutes: u32,
+    price: Decimal,
+}
+
+impl Unit {
+    fn cost(&self) -> Decimal {
+        self.price * Decimal::from(self.minutes) / Decimal::from(60)
+    }
+}
+
+// Restriccions comunes a tots els tipus de regla, expressades en unitats
+#[derive(Debug, Clone, Copy)]
+struct Constraints {
+    required_units: usize,
+    min_block_units: usize,
+    max_blocks: Option<usize>,
+    max_units: Option<usize>,
+}
+
+impl Constraints {
+    // `unit_minutes` ha de dividir `target_minutes` i `min_block_minutes`
+    fn new(
+        target_minutes: u32,
+        min_block_minutes: u32,
+        max_switches: Option<u8>,
+        unit_minutes: u32,
+    ) -> Self {
+        Self {
+            required_units: target_minutes.div_ceil(unit_minutes) as usize,
+            min_block_units: min_block_minutes.div_ceil(unit_minutes).max(1) as usize,
+            max_blocks: max_switches.map(|s| s as usize),
+            max_units: None,
+        }
+    }
+}
+
+// Punt d'entrada comú: interpreta els paràmetres segons el tipus de regla
+pub fn optimize_rule(
+    rule_type: RuleType,
+    params: &JsonValue,
+    prices: &[PricePeriod],
+) -> Result<OptimizedSchedule, OptimizerError> {
+    match rule_type {
+        RuleType::MinHoursCheapest => {
+            let params: MinHoursCheapestParams = serde_json::from_value(params.clone())
+                .map_err(|e| OptimizerError::InvalidParams(e.to_string()))?;
+            optimize_min_hours(prices, &params)
+        }
+        RuleType::XHoursWithinWindows => {
+            let params: XHoursWithinWindowsParams = serde_json::from_value(params.clone())
+                .map_err(|e| OptimizerError::InvalidParams(e.to_string()))?;
+            optimize_within_windows(prices, &params)
+        }
+        RuleType::PriceThreshold => {
+            let params: PriceThresholdParams = serde_json::from_value(params.clone())
+                .map_err(|e| OptimizerError::InvalidParams(e.to_string()))?;
+            optimize_price_threshold(prices, &params)
+        }
+        // Les sessions de càrrega travessen la mitjanit: es planifiquen amb
+        // `optimize_deadline_energy` i es retallen per dies amb `clip_to_day`
+        RuleType::DeadlineEnergy => Err(OptimizerError::InvalidParams(
+            "DEADLINE_ENERGY rules are planned per charging session".to_string(),
+        )),
+    }
+}
+
+pub fn optimize_min_hours(
+    prices: &[PricePeriod],
+    params: &MinHoursCheapestParams,
+) -> Result<OptimizedSchedule, OptimizerError> {
+    let min_run_block = params.min_run_block.unwrap_or(0) as u32;
+    let units = price_units(prices)?;
+    let unit_minutes = common_resolution(&units, &[min_run_block])?;
+    let units = split_units(units, unit_minutes);
+
+    let day_minutes: u32 = units.iter().map(|u| u.minutes).sum();
+    let target_minutes = params.min_hours_per_day as u32 * 60;
+    if target_minutes > day_minutes {
+        return Err(OptimizerError::Infeasible(format!(
+            "{} hours requested but the day only has {:.2}",
+            params.min_hours_per_day,
+            day_minutes as f32 / 60.0
+        )));
+    }
+
+    let constraints = Constraints::new(
+        target_minutes,
+        min_run_block,
+        params.max_switches_per_day,
+        unit_minutes,
+    );
+
+    let allowed = vec![true; units.len()];
+    let costs: Vec<Decimal> = units.iter().map(Unit::cost).collect();
+    let selection = select_units(&costs, &allowed, constraints).ok_or_else(|| {
+        OptimizerError::Infeasible(format!(
+            "cannot fit {} hours in blocks of at least {} minutes with at most {} switches",
+            params.min_hours_per_day,
+            min_run_block.max(unit_minutes),
+            params
+                .max_switches_per_day
+                .map_or("unlimited".to_string(), |s| s.to_string())
+        ))
+    })?;
+
+    Ok(build_schedule(&units, &selection))
+}
+
+pub fn optimize_within_windows(
+    prices: &[PricePeriod],
+    params: &XHoursWithinWindowsParams,
+) -> Result<OptimizedSchedule, OptimizerError> {
+    if params.allowed_windows.is_empty() {
+        return Err(OptimizerError::InvalidParams(
+            "at least one allowed window is required".to_string(),
+        ));
+    }
+
+    let ranges = window_ranges(&params.allowed_windows)?;
+    let min_run_block = params.min_run_block.unwrap_or(0) as u32;
+
+    // Granularitat comuna: els períodes es parteixen si una finestra comença o acaba a mig període
+    let units = price_units(prices)?;
+    let boundaries: Vec<u32> = ranges
+        .iter()
+        .flat_map(|(start, end)| [*start, *end])
+        .chain([min_run_block])
+        .collect();
+    let unit_minutes = common_resolution(&units, &boundaries)?;
+    let units = split_units(units, unit_minutes);
+    let allowed: Vec<bool> = units
+        .iter()
+        .map(|u| {
+            ranges
+                .iter()
+                .any(|(start, end)| u.start_minute >= *start && u.start_minute + u.minutes <= *end)
+        })
+        .collect();
+
+    let available_minutes: u32 = units
+        .iter()
+        .zip(&allowed)
+        .filter(|(_, a)| **a)
+        .map(|(u, _)| u.minutes)
+        .sum();
+    let target_minutes = params.target_hours_per_day as u32 * 60;
+    if target_minutes > available_minutes {
+        return Err(OptimizerError::Infeasible(format!(
+            "{} hours requested but the allowed windows only cover {:.2} hours",
+            params.target_hours_per_day,
+            available_minutes as f32 / 60.0
+        )));
+    }
+
+    let constraints = Constraints::new(
+        target_minutes,
+        min_run_block,
+        params.max_switches_per_day,
+        unit_minutes,
+    );
+
+    let costs: Vec<Decimal> = units.iter().map(Unit::cost).collect();
+    let selection = select_units(&costs, &allowed, constraints).ok_or_else(|| {
+        OptimizerError::Infeasible(format!(
+            "cannot fit {} hours inside the allowed windows in blocks of at least {} minutes with at most {} switches",
+            params.target_hours_per_day,
+            min_run_block.max(unit_minutes),
+            params
+                .max_switches_per_day
+                .map_or("unlimited".to_string(), |s| s.to_string())
+        ))
+    })?;
+
+    Ok(build_schedule(&units, &selection))
+}
+
+// Encén totes les unitats amb preu igual o inferior al límit. Amb el cost de cada
+// unitat relatiu al límit, les barates resten i la mateixa programació dinàmica
+// decideix quins trams curts val la pena allargar per complir el bloc mínim, quines
+// hores cal afegir per arribar a `min_hours` i quines sobren per sobre de `max_hours`.
+pub fn optimize_price_threshold(
+    prices: &[PricePeriod],
+    params: &PriceThresholdParams,
+) -> Result<OptimizedSchedule, OptimizerError> {
+    let threshold = match (params.max_price, params.percentile) {
+        (Some(max_price), None) => max_price,
+        (None, Some(p)) => price::percentile(prices, p).ok_or(OptimizerError::NoPrices)?,
+        _ => {
+            return Err(OptimizerError::InvalidParams(
+                "exactly one of max_price or percentile is required".to_string(),
+            ))
+        }
+    };
+
+    let min_run_block = params.min_run_block.unwrap_or(0) as u32;
+    let units = price_units(prices)?;
+    let unit_minutes = common_resolution(&units, &[min_run_block])?;
+    let units = split_units(units, unit_minutes);
+
+    let day_minutes: u32 = units.iter().map(|u| u.minutes).sum();
+    let target_minutes = params.min_hours.unwrap_or(0) as u32 * 60;
+    if target_minutes > day_minutes {
+        return Err(OptimizerError::Infeasible(format!(
+            "{} hours requested but the day only has {:.2}",
+            params.min_hours.unwrap_or(0),
+            day_minutes as f32 / 60.0
+        )));
+    }
+
+    let constraints = Constraints {
+        max_units: params
+            .max_hours
+            .map(|h| (h as u32 * 60 / unit_minutes) as usize),
+        ..Constraints::new(target_minutes, min_run_block, None, unit_minutes)
+    };
+
+    // El marge fa que els preus exactament iguals al límit també comptin com a barats
+    let margin = Decimal::new(1, 9);
+    let costs: Vec<Decimal> = units
+        .iter()
+        .map(|u| (u.price - threshold - margin) * Decimal::from(u.minutes) / Decimal::from(60))
+        .collect();
+
+    let allowed = vec![true; units.len()];
+    let selection = select_units(&costs, &allowed, constraints).ok_or_else(|| {
+        OptimizerError::Infeasible(format!(
+            "cannot fit between {} and {} hours in blocks of at least {} minutes",
+            params.min_hours.unwrap_or(0),
+            params.max_hours.unwrap_or(24),
+            min_run_block.max(unit_minutes)
+        ))
+    })?;
+
+    Ok(build_schedule(&units, &selection))
+}
+
+// Tria les unitats més barates de la sessió [inici, fi) fins a cobrir els minuts
+// de càrrega. `prices` ha d'incloure tota la sessió (normalment el dia de l'inici i
+// el següent). Si la càrrega no omple l'última unitat, la més cara de les triades
+// només s'encén el temps que falta.
+pub fn optimize_deadline_energy(
+    prices: &[PricePeriod],
+    params: &DeadlineEnergyParams,
+    session: Interval,
+) -> Result<OptimizedSchedule, OptimizerError> {
+    let (session_start, session_end) = session;
+    let charge_minutes = params
+        .charge_minutes()
+        .ok_or_else(|| OptimizerError::InvalidParams("charger_kw must be positive".to_string()))?;
+    let deadline = TimeWindow::parse_time(&params.deadline).map_err(OptimizerError::InvalidParams)?;
+    let ranges = match &params.allowed_window {
+        Some(window) => window_ranges(std::slice::from_ref(window))?,
+        None => vec![(0, MINUTES_PER_DAY)],
+    };
+
+    let units = price_units(prices)?;
+    let boundaries: Vec<u32> = ranges
+        .iter()
+        .flat_map(|(start, end)| [*start, *end])
+        .chain([deadline])
+        .collect();
+    let unit_minutes = common_resolution(&units, &boundaries)?;
+    let mut units: Vec<Unit> = split_units(units, unit_minutes)
+        .into_iter()
+        .filter(|u| session_start <= u.start && u.start < session_end)
+        .collect();
+
+    // Sense els preus de tota la sessió no es pot planificar
+    let covered: u32 = units.iter().map(|u| u.minutes).sum();
+    if (covered as i64) < (session_end - session_start).num_minutes() {
+        return Err(OptimizerError::NoPrices);
+    }
+
+    let allowed: Vec<bool> = units
+        .iter()
+        .map(|u| {
+            ranges
+                .iter()
+                .any(|(start, end)| u.start_minute >= *start && u.start_minute + u.minutes <= *end)
+        })
+        .collect();
+
+    let available_minutes: u32 = units
+        .iter()
+        .zip(&allowed)
+        .filter(|(_, a)| **a)
+        .map(|(u, _)| u.minutes)
+        .sum();
+    if charge_minutes > available_minutes {
+        return Err(OptimizerError::Infeasible(format!(
+            "{:.2} hours of charging needed but only {:.2} are available before {}",
+            charge_minutes as f32 / 60.0,
+            available_minutes as f32 / 60.0,
+            params.deadline
+        )));
+    }
+
+    let constraints = Constraints::new(charge_minutes, 0, None, unit_minutes);
+    let costs: Vec<Decimal> = units.iter().map(Unit::cost).collect();
+    let mut selection = select_units(&costs, &allowed, constraints).ok_or_else(|| {
+        OptimizerError::Infeasible(format!(
+            "cannot fit {:.2} hours of charging before {}",
+            charge_minutes as f32 / 60.0,
+            params.deadline
+        ))
+    })?;
+
+    // Tram parcial: s'escurça la unitat triada més cara (la darrera si n'hi ha diverses)
+    let excess = constraints.required_units as u32 * unit_minutes - charge_minutes;
+    if excess > 0 {
+        let partial = (0..units.len())
+            .filter(|&i| selection[i])
+            .max_by_key(|&i| units[i].price);
+        if let Some(i) = partial {
+            let on_minutes = units[i].minutes - excess;
+            let rest = Unit {
+                start: units[i].start + Duration::minutes(on_minutes as i64),
+                start_minute: units[i].start_minute + on_minutes,
+                minutes: excess,
+                price: units[i].price,
+            };
+            units[i].minutes = on_minutes;
+            units.insert(i + 1, rest);
+            selection.insert(i + 1, false);
+        }
+    }
+
+    Ok(build_schedule(&units, &selection))
+}
+
+// Retalla sessions que travessen la mitjanit al dia dels preus `prices` (en la zona
+// `tz`): l'horari resultant cobreix tot el dia i s'encén allà on alguna sessió ho fa.
+pub fn clip_to_day(
+    sessions: &[OptimizedSchedule],
+    prices: &[PricePeriod],
+    tz: Tz,
+) -> Result<OptimizedSchedule, OptimizerError> {
+    let units = price_units(prices)?;
+    let day = build_schedule(&units, &vec![false; units.len()]);
+
+    let schedules: Vec<(Uuid, OptimizedSchedule)> = std::iter::once(day)
+        .chain(sessions.iter().cloned())
+        .map(|schedule| (Uuid::nil(), schedule))
+        .collect();
+    merge_schedules(&schedules, prices, tz).ok_or(OptimizerError::NoPrices)
+}
+
+// Bloc continu de `minutes` minuts més barat que comença a partir de `earliest` i
+// acaba abans de `deadline`. Els inicis van en quarts d'hora i només es tenen en
+// compte els blocs coberts del tot per `prices`. Retorna l'inici i el cost per kW.
+pub fn cheapest_block(
+    prices: &[PricePeriod],
+    minutes: u32,
+    earliest: DateTime<Utc>,
+    deadline: DateTime<Utc>,
+) -> Option<(DateTime<Utc>, Decimal)> {
+    let step = 15 * 60;
+    let duration = Duration::minutes(minutes as i64);
+    let mut start = DateTime::from_timestamp(earliest.timestamp().div_euclid(step) * step, 0)?;
+    if start < earliest {
+        start += Duration::seconds(step);
+    }
+
+    let mut best: Option<(DateTime<Utc>, Decimal)> = None;
+    while start + duration <= deadline {
+        let end = start + duration;
+        let mut covered = 0;
+        let mut cost = Decimal::ZERO;
+        for p in prices {
+            let p_end = p.start + Duration::minutes(p.minutes as i64);
+            let overlap = (end.min(p_end) - start.max(p.start)).num_minutes().max(0);
+            covered += overlap;
+            cost += p.price * Decimal::from(overlap) / Decimal::from(60);
+        }
+
+        if covered == minutes as i64 && best.is_none_or(|(_, best_cost)| cost < best_cost) {
+            best = Some((start, cost));
+        }
+        start += Duration::seconds(step);
+    }
+
+    best.map(|(start, cost)| (start, cost.round_dp(6)))
+}
+
+fn window_ranges(windows: &[TimeWindow]) -> Result<Vec<(u32, u32)>, OptimizerError> {
+    let mut ranges = Vec::new();
+    for window in windows {
+        ranges.extend(window.minute_ranges().map_err(OptimizerError::InvalidParams)?);
+    }
+    Ok(ranges)
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// Minuts de la unitat més gran que divideix tots els períodes de preu i els
+// límits indicats (finestres, bloc mínim). Les regles validen els límits en quarts
+// d'hora; per sota, la programació dinàmica creixeria massa (1.440 unitats per dia).
+fn common_resolution(units: &[Unit], boundaries: &[u32]) -> Result<u32, OptimizerError> {
+    let minutes = units
+        .iter()
+        .map(|u| u.minutes)
+        .chain(boundaries.iter().copied())
+        .fold(0, gcd);
+
+    if minutes < WINDOW_STEP_MINUTES {
+        return Err(OptimizerError::InvalidParams(format!(
+            "window bounds and blocks must be multiples of {} minutes",
+            WINDOW_STEP_MINUTES
+        )));
+    }
+    Ok(minutes)
+}
+
+fn price_units(prices: &[PricePeriod]) -> Result<Vec<Unit>, OptimizerError> {
+    if prices.is_empty() {
+        return Err(OptimizerError::NoPrices);
+    }
+
+    let mut units: Vec<Unit> = prices
+        .iter()
+        .map(|p| Unit {
+            start: p.start,
+            start_minute: p.hour as u32 * 60 + p.minute as u32,
+            minutes: p.minutes,
+            price: p.price,
+        })
+        .collect();
+    units.sort_by_key(|u| u.start);
+    Ok(units)
+}
+
+// Parteix cada unitat en trossos de `minutes` amb el mateix preu
+fn split_units(units: Vec<Unit>, minutes: u32) -> Vec<Unit> {
+    units
+        .into_iter()
+        .flat_map(|u| {
+            (0..u.minutes / minutes).map(move |k| Unit {
+                start: u.start + Duration::minutes((k * minutes) as i64),
+                start_minute: u.start_minute + k * minutes,
+                minutes,
+                price: u.price,
+            })
+        })
+        .collect()
+}
+
+// Programació dinàmica exacta sobre (unitats enceses, blocs, durada del bloc actual).
+// Tria com a mínim `required_units` (i com a molt `max_units`) unitats permeses
+// minimitzant la suma de `costs`, amb blocs d'almenys `min_block_units` i com a molt
+// `max_blocks` engegades. Retorna quines unitats queden enceses, o `None` si les
+// restriccions no es poden complir.
+fn select_units(costs: &[Decimal], allowed: &[bool], constraints: Constraints) -> Option<Vec<bool>> {
+    let n = costs.len();
+    let required = constraints.required_units;
+    let min_block = constraints.min_block_units.max(1);
+
+    // Sense mínim ni costos negatius no val la pena encendre res
+    if required == 0 && costs.iter().all(|c| !c.is_sign_negative()) {
+        return Some(vec![false; n]);
+    }
+    let cap = match constraints.max_units {
+        Some(max) if max < required => return None,
+        Some(max) => max.min(n),
+        None => required,
+    };
+
+    // Cada bloc té almenys `min_block` unitats i entre dos blocs n'hi ha una d'apagada,
+    // així que mai hi caben més de (n + 1) / (min_block + 1) blocs. Sense límit
+    // d'engegades no cal comptar-les: la dimensió de blocs es redueix a 1.
+    let max_blocks = constraints
+        .max_blocks
+        .map(|b| b.min(((n + 1) / (min_block + 1)).max(1)));
+    if max_blocks == Some(0) {
+        return None;
+    }
+
+    // Estat: h = unitats enceses (fins a `max_units`, o saturat a `required` si no
+    // n'hi ha), b = blocs iniciats, r = durada del bloc actual (0 = apagat, saturat a `min_block`)
+    let h_dim = cap + 1;
+    let b_dim = max_blocks.map_or(1, |b| b + 1);
+    let r_dim = min_block + 1;
+    let states = h_dim * b_dim * r_dim;
+    let index = |h: usize, b: usize, r: usize| (h * b_dim + b) * r_dim + r;
+
+    // Per cada capa: cost mínim (cost, blocs) i estat anterior per reconstruir
+    let mut best: Vec<Option<(Decimal, usize)>> = vec![None; states];
+    let mut parents: Vec<Vec<usize>> = Vec::with_capacity(n);
+    best[index(0, 0, 0)] = Some((Decimal::ZERO, 0));
+
+    for (i, &unit_cost) in costs.iter().enumerate() {
+        let mut next: Vec<Option<(Decimal, usize)>> = vec![None; states];
+        let mut parent = vec![usize::MAX; states];
+
+        let mut relax = |next: &mut Vec<Option<(Decimal, usize)>>, to: usize, from: usize, value: (Decimal, usize)| {
+            if next[to].is_none_or(|current| value < current) {
+                next[to] = Some(value);
+                parent[to] = from;
+            }
+        };
+
+        for h in 0..h_dim {
+            for b in 0..b_dim {
+                for r in 0..r_dim {
+                    let from = index(h, b, r);
+                    let Some((cost, blocks)) = best[from] else {
+                        continue;
+                    };
+
+                    // Apagar (o continuar apagat): només si el bloc actual ja és prou llarg
+                    if r == 0 || r >= min_block {
+                        relax(&mut next, index(h, b, 0), from, (cost, blocks));
+                    }
+
+                    if !allowed[i] {
+                        continue;
+                    }
+
+                    // Encendre (nou bloc) o continuar encès
+                    let h_next = match constraints.max_units {
+                        Some(_) if h == cap => continue,
+                        Some(_) => h + 1,
+                        None => (h + 1).min(required),
+                    };
+                    if r == 0 {
+                        if let Some(b_next) = max_blocks.map_or(Some(0), |max| (b < max).then_some(b + 1)) {
+                            relax(
+                                &mut next,
+                                index(h_next, b_next, 1),
+                                from,
+                                (cost + unit_cost, blocks + 1),
+                            );
+                        }
+                    } else {
+                        relax(
+                            &mut next,
+                            index(h_next, b, (r + 1).min(min_block)),
+                            from,
+                            (cost + unit_cost, blocks),
+                        );
+                    }
+                }
+            }
+        }
+
+        parents.push(parent);
+        best = next;
+    }
+
+    // Estat final amb prou unitats i sense cap bloc a mitges
+    let (mut state, _) = (required..h_dim)
+        .flat_map(|h| (0..b_dim).map(move |b| (h, b)))
+        .flat_map(|(h, b)| [index(h, b, 0), index(h, b, min_block)])
+        .filter_map(|s| best[s].map(|v| (s, v)))
+        .min_by(|a, b| a.1.cmp(&b.1))?;
+
+    // Reconstruir: una unitat és encesa si l'estat després d'ella té r > 0
+    let mut selection = vec![false; n];
+    for i in (0..n).rev() {
+        selection[i] = state % r_dim > 0;
+        state = parents[i][state];
+    }
+
+    Some(selection)
+}
+
+fn build_schedule(units: &[Unit], selection: &[bool]) -> OptimizedSchedule {
+    let day_minutes: u32 = units.iter().map(|u| u.minutes).sum();
+    let day_cost: Decimal = units.iter().map(Unit::cost).sum();
+
+    let on_units = units.iter().zip(selection).filter(|(_, on)| **on);
+    let on_minutes: u32 = on_units.clone().map(|(u, _)| u.minutes).sum();
+    let total_cost: Decimal = on_units.map(|(u, _)| u.cost()).sum();
+
+    summarize(build_slots(units, selection), total_cost, on_minutes, day_cost, day_minutes)
+}
+
+// Cost, hores i estalvi respecte encendre les mateixes hores al preu mitjà del dia
+fn summarize(
+    slots: Vec<TimeSlot>,
+    total_cost: Decimal,
+    on_minutes: u32,
+    day_cost: Decimal,
+    day_minutes: u32,
+) -> OptimizedSchedule {
+    let average_cost = if day_minutes > 0 {
+        day_cost * Decimal::from(on_minutes) / Decimal::from(day_minutes)
+    } else {
+        Decimal::ZERO
+    };
+    let savings = average_cost - total_cost;
+    let savings_percentage = if average_cost.is_zero() {
+        None
+    } else {
+        (savings / average_cost * Decimal::from(100))
+            .round_dp(2)
+            .try_into()
+            .ok()
+    };
+
+    OptimizedSchedule {
+        slots,
+        total_cost: total_cost.round_dp(4),
+        total_hours: on_minutes as f32 / 60.0,
+        average_cost: average_cost.round_dp(4),
+        savings: savings.round_dp(4),
+        savings_percentage,
+    }
+}
+
+type Interval = (DateTime<Utc>, DateTime<Utc>);
+
+// Combina els horaris de les regles d'un mateix dispositiu. El dispositiu s'encén
+// quan qualsevol regla el vol encès (així es compleixen totes) i cada slot
+// s'atribueix a la primera regla que l'encén. `schedules` va per prioritat
+// descendent: el primer defineix el dia, amb preus `prices` en la zona `tz`. Els
+// trams d'altres regles que cauen fora d'aquest dia (regles amb una altra zona
+// horària) es descarten.
+pub fn merge_schedules(
+    schedules: &[(Uuid, OptimizedSchedule)],
+    prices: &[PricePeriod],
+    tz: Tz,
+) -> Option<OptimizedSchedule> {
+    let (_, primary) = schedules.first()?;
+    let first_slot = primary.slots.first()?;
+    let last_slot = primary.slots.last()?;
+    let (day_start, day_end) = (first_slot.start_at?, last_slot.end_at?);
+
+    // Trams encesos de cada regla
+    let on_ranges: Vec<(Uuid, Vec<Interval>)> = schedules
+        .iter()
+        .map(|(rule_id, schedule)| {
+            let ranges = schedule
+                .slots
+                .iter()
+                .filter(|s| s.is_on())
+                .filter_map(|s| Some((s.start_at?, s.end_at?)))
+                .collect();
+            (*rule_id, ranges)
+        })
+        .collect();
+
+    let mut bounds = BTreeSet::from([day_start, day_end]);
+    for (_, ranges) in &on_ranges {
+        for &(start, end) in ranges {
+            bounds.extend([start, end].into_iter().filter(|t| day_start < *t && *t < day_end));
+        }
+    }
+
+    // Regla que encén cada tram elemental; els trams consecutius iguals s'agrupen
+    let bounds: Vec<DateTime<Utc>> = bounds.into_iter().collect();
+    let mut pieces: Vec<(DateTime<Utc>, DateTime<Utc>, Option<Uuid>)> = Vec::new();
+    for pair in bounds.windows(2) {
+        let (start, end) = (pair[0], pair[1]);
+        let owner = on_ranges
+            .iter()
+            .find(|(_, ranges)| ranges.iter().any(|(s, e)| *s <= start && end <= *e))
+            .map(|(rule_id, _)| *rule_id);
+        match pieces.last_mut() {
+            Some(last) if last.2 == owner => last.1 = end,
+            _ => pieces.push((start, end, owner)),
+        }
+    }
+
+    let cost_between = |start: DateTime<Utc>, end: DateTime<Utc>| -> Decimal {
+        prices
+            .iter()
+            .map(|p| {
+                let p_end = p.start + Duration::minutes(p.minutes as i64);
+                let overlap = (end.min(p_end) - start.max(p.start)).num_minutes().max(0);
+                p.price * Decimal::from(overlap) / Decimal::from(60)
+            })
+            .sum()
+    };
+
+    let mut slots = Vec::with_capacity(pieces.len());
+    let mut total_cost = Decimal::ZERO;
+    let mut on_minutes = 0;
+    for &(start, end, owner) in &pieces {
+        if owner.is_some() {
+            total_cost += cost_between(start, end);
+            on_minutes += (end - start).num_minutes() as u32;
+        }
+        slots.push(TimeSlot {
+            start: start.with_timezone(&tz).format("%H:%M").to_string(),
+            // El final del dia conserva l'etiqueta original ("24:00")
+            end: if end == day_end {
+                last_slot.end.clone()
+            } else {
+                end.with_timezone(&tz).format("%H:%M").to_string()
+            },
+            action: if owner.is_some() { "on" } else { "off" }.to_string(),
+            start_at: Some(start),
+            end_at: Some(end),
+            rule_id: owner,
+        });
+    }
+
+    let day_minutes: u32 = prices.iter().map(|p| p.minutes).sum();
+    let day_cost = cost_between(day_start, day_end);
+
+    Some(summarize(slots, total_cost, on_minutes, day_cost, day_minutes))
+}
+
+// Agrupa unitats consecutives amb la mateixa acció en slots que cobreixen tot el dia
+fn build_slots(units: &[Unit], selection: &[bool]) -> Vec<TimeSlot> {
+    let mut slots: Vec<TimeSlot> = Vec::new();
+    let mut first = 0;
+
+    for i in 0..units.len() {
+        let next = units.get(i + 1);
+        if next.is_some() && selection[i + 1] == selection[i] {
+            continue;
+        }
+
+        // L'hora final és l'inici de la unitat següent (així el salt de primavera
+        // mostra 01:00-03:00 i no 01:00-02:00), o mitjanit al final del dia
+        let last = &units[i];
+        let end_minute = match next {
+            Some(next) => next.start_minute,
+            None => (last.start_minute + last.minutes).min(MINUTES_PER_DAY),
+        };
+        slots.push(TimeSlot {
+            start: format_minutes(units[first].start_minute),
+            end: format_minutes(end_minute),
+            action: if selection[i] { "on" } else { "off" }.to_string(),
+            start_at: Some(units[first].start),
+            end_at: Some(last.start + Duration::minutes(last.minutes as i64)),
+            rule_id: None,
+        });
+        first = i + 1;
+    }
+
+    slots
+}
+
+// Minuts des de mitjanit a "HH:MM" (1440 => "24:00")
+fn format_minutes(minutes: u32) -> String {
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Datelike, NaiveDate, TimeZone, Timelike};
+
+    const MADRID: Tz = chrono_tz::Europe::Madrid;
+
+    // Preus horaris d'un dia normal de Madrid, en €/MWh
+    fn hourly(prices_mwh: &[i64]) -> Vec<PricePeriod> {
+        let start = MADRID.with_ymd_and_hms(2026, 10, 19, 0, 0, 0).unwrap().with_timezone(&Utc);
+        prices_mwh
+            .iter()
+            .enumerate()
+            .map(|(i, &p)| PricePeriod {
+                start: start + Duration::hours(i as i64),
+                hour: i as u8,
+                minute: 0,
+                minutes: 60,
+                price: Decimal::new(p, 3),
+            })
+            .collect()
+    }
+
+    // 24 hores a 100 €/MWh amb els preus indicats per hora
+    fn day_with(cheap: &[(usize, i64)]) -> Vec<PricePeriod> {
+        let mut prices = vec![100; 24];
+        for &(hour, price) in cheap {
+            prices[hour] = price;
+        }
+        hourly(&prices)
+    }
+
+    fn min_hours(hours: u8, min_run_block: Option<u16>, max_switches: Option<u8>) -> MinHoursCheapestParams {
+        MinHoursCheapestParams {
+            min_hours_per_day: hours,
+            max_switches_per_day: max_switches,
+            min_run_block,
+        }
+    }
+
+    fn on_slots(schedule: &OptimizedSchedule) -> Vec<(String, String)> {
+        schedule
+            .slots
+            .iter()
+            .filter(|s| s.is_on())
+            .map(|s| (s.start.clone(), s.end.clone()))
+            .collect()
+    }
+
+    fn slot(start: &str, end: &str) -> (String, String) {
+        (start.to_string(), end.to_string())
+    }
+
+    #[test]
+    fn picks_the_cheapest_hours_and_covers_the_whole_day() {
+        let prices = day_with(&[(3, 10), (4, 20), (14, 30)]);
+        let schedule = optimize_min_hours(&prices, &min_hours(3, None, None)).unwrap();
+
+        assert_eq!(on_slots(&schedule), vec![slot("03:00", "05:00"), slot("14:00", "15:00")]);
+        assert_eq!(schedule.slots.first().unwrap().start, "00:00");
+        assert_eq!(schedule.slots.last().unwrap().end, "24:00");
+        assert_eq!(schedule.total_cost, Decimal::new(60, 3));
+        assert_eq!(schedule.total_hours, 3.0);
+    }
+
+    #[test]
+    fn reports_savings_against_the_daily_average() {
+        let prices = day_with(&[(3, 10), (4, 20)]);
+        let schedule = optimize_min_hours(&prices, &min_hours(2, None, None)).unwrap();
+
+        // Mitjana del dia: (22 × 100 + 10 + 20) / 24 = 92.9166 €/MWh
+        assert_eq!(schedule.average_cost, Decimal::new(1858, 4));
+        assert_eq!(schedule.savings, Decimal::new(1558, 4));
+        assert_eq!(schedule.savings_percentage, Some(83.86));
+    }
+
+    #[test]
+    fn min_run_block_keeps_blocks_long_enough() {
+        // Les dues hores més barates estan separades; amb blocs de 2 hores surt
+        // més a compte el parell 10-11
+        let prices = day_with(&[(3, 10), (10, 10), (11, 50)]);
+
+        let free = optimize_min_hours(&prices, &min_hours(2, None, None)).unwrap();
+        assert_eq!(on_slots(&free), vec![slot("03:00", "04:00"), slot("10:00", "11:00")]);
+
+        let blocked = optimize_min_hours(&prices, &min_hours(2, Some(120), None)).unwrap();
+        assert_eq!(on_slots(&blocked), vec![slot("10:00", "12:00")]);
+        assert_eq!(blocked.total_cost, Decimal::new(60, 3));
+    }
+
+    #[test]
+    fn min_run_block_may_run_longer_than_the_target() {
+        // 90 minuts de bloc mínim amb hores senceres: una hora no n'hi ha prou
+        let prices = day_with(&[(5, 10), (6, 20)]);
+        let schedule = optimize_min_hours(&prices, &min_hours(1, Some(90), None)).unwrap();
+
+        assert_eq!(on_slots(&schedule), vec![slot("05:00", "06:30")]);
+        assert_eq!(schedule.total_hours, 1.5);
+    }
+
+    #[test]
+    fn max_switches_limits_the_number_of_blocks() {
+        let prices = day_with(&[(2, 10), (6, 10), (14, 10), (15, 20), (16, 90)]);
+
+        let free = optimize_min_hours(&prices, &min_hours(3, None, None)).unwrap();
+        assert_eq!(on_slots(&free).len(), 3);
+
+        let limited = optimize_min_hours(&prices, &min_hours(3, None, Some(2))).unwrap();
+        assert_eq!(on_slots(&limited), vec![slot("02:00", "03:00"), slot("14:00", "16:00")]);
+        assert_eq!(limited.total_cost, Decimal::new(40, 3));
+
+        let single = optimize_min_hours(&prices, &min_hours(3, None, Some(1))).unwrap();
+        assert_eq!(on_slots(&single), vec![slot("14:00", "17:00")]);
+    }
+
+    #[test]
+    fn exact_search_beats_greedy_padding_of_the_cheapest_hours() {
+        // Les quatre hores més barates són aïllades (5 €/MWh). Triar-les i allargar-les
+        // fins al bloc mínim costa 2 × (5 + 100); l'òptim són dos parells a 20 €/MWh.
+        let prices = day_with(&[
+            (3, 5),
+            (8, 5),
+            (15, 5),
+            (21, 5),
+            (11, 20),
+            (12, 20),
+            (17, 20),
+            (18, 20),
+        ]);
+        let schedule = optimize_min_hours(&prices, &min_hours(4, Some(120), None)).unwrap();
+
+        assert_eq!(on_slots(&schedule), vec![slot("11:00", "13:00"), slot("17:00", "19:00")]);
+        assert_eq!(schedule.total_cost, Decimal::new(80, 3));
+    }
+
+    // Cerca exhaustiva de referència per a `select_units`
+    fn brute_force(costs: &[Decimal], constraints: Constraints) -> Option<Decimal> {
+        let n = costs.len();
+        (0u32..1 << n)
+            .filter_map(|mask| {
+                let on: Vec<bool> = (0..n).map(|i| mask & (1 << i) != 0).collect();
+                let count = on.iter().filter(|o| **o).count();
+                let mut blocks = Vec::new();
+                let mut run = 0;
+                for &o in on.iter().chain([&false]) {
+                    if o {
+                        run += 1;
+                    } else if run > 0 {
+                        blocks.push(run);
+                        run = 0;
+                    }
+                }
+                let valid = count >= constraints.required_units
+                    && constraints.max_units.is_none_or(|m| count <= m)
+                    && blocks.iter().all(|b| *b >= constraints.min_block_units)
+                    && constraints.max_blocks.is_none_or(|m| blocks.len() <= m);
+                valid.then(|| (0..n).filter(|&i| on[i]).map(|i| costs[i]).sum::<Decimal>())
+            })
+            .min()
+    }
+
+    #[test]
+    fn select_units_matches_brute_force() {
+        // Generador congruencial: casos deterministes sense dependències
+        let mut seed: u64 = 42;
+        let mut next = |modulo: u64| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) % modulo
+        };
+
+        for _ in 0..300 {
+            let n = 4 + next(7) as usize;
+            let costs: Vec<Decimal> = (0..n).map(|_| Decimal::from(next(50) as i64 - 10)).collect();
+            let constraints = Constraints {
+                required_units: next(n as u64) as usize,
+                min_block_units: 1 + next(3) as usize,
+                max_blocks: (next(2) == 0).then(|| 1 + next(3) as usize),
+                max_units: (next(2) == 0).then(|| n - next(3) as usize),
+            };
+
+            let expected = brute_force(&costs, constraints);
+            let selection = select_units(&costs, &vec![true; n], constraints);
+            let actual = selection.map(|s| (0..n).filter(|&i| s[i]).map(|i| costs[i]).sum::<Decimal>());
+            assert_eq!(actual, expected, "costs {:?} with {:?}", costs, constraints);
+        }
+    }
+
+    fn within_windows(hours: u8, windows: &[(&str, &str)], min_run_block: Option<u16>) -> XHoursWithinWindowsParams {
+        XHoursWithinWindowsParams {
+            target_hours_per_day: hours,
+            allowed_windows: windows
+                .iter()
+                .map(|(start, end)| TimeWindow {
+                    start: start.to_string(),
+                    end: end.to_string(),
+                })
+                .collect(),
+            max_switches_per_day: None,
+            min_run_block,
+        }
+    }
+
+    #[test]
+    fn windows_that_wrap_midnight_use_both_ends_of_the_day() {
+        // Les hores més barates del dia cauen al migdia, fora de la finestra
+        let prices = day_with(&[(12, 1), (13, 1), (14, 1), (23, 10), (1, 20), (5, 30), (6, 5)]);
+        let schedule = optimize_within_windows(&prices, &within_windows(3, &[("22:00", "06:00")], None)).unwrap();
+
+        assert_eq!(
+            on_slots(&schedule),
+            vec![slot("01:00", "02:00"), slot("05:00", "06:00"), slot("23:00", "24:00")]
+        );
+    }
+
+    #[test]
+    fn blocks_can_cross_midnight_inside_a_wrapping_window() {
+        // El bloc mínim de 2 hores només cap a 22-24 o a 00-02
+        let prices = day_with(&[(23, 10), (22, 20), (0, 15), (1, 90)]);
+        let schedule =
+            optimize_within_windows(&prices, &within_windows(2, &[("22:00", "02:00")], Some(120))).unwrap();
+
+        assert_eq!(on_slots(&schedule), vec![slot("22:00", "24:00")]);
+    }
+
+    #[test]
+    fn windows_split_price_periods_at_quarter_hours() {
+        let prices = day_with(&[(6, 10), (7, 20)]);
+        let schedule = optimize_within_windows(&prices, &within_windows(1, &[("06:30", "07:30")], None)).unwrap();
+
+        assert_eq!(on_slots(&schedule), vec![slot("06:30", "07:30")]);
+        assert_eq!(schedule.total_cost, Decimal::new(15, 3));
+    }
+
+    #[test]
+    fn rejects_window_bounds_off_the_quarter_hour_grid() {
+        // Amb límits a minuts l'optimitzador hauria de treballar amb 1.440 unitats
+        let prices = day_with(&[]);
+        let params = XHoursWithinWindowsParams {
+            max_switches_per_day: Some(4),
+            ..within_windows(8, &[("00:01", "23:59")], Some(120))
+        };
+
+        assert!(matches!(
+            optimize_within_windows(&prices, &params),
+            Err(OptimizerError::InvalidParams(_))
+        ));
+    }
+
+    #[test]
+    fn the_largest_valid_rules_stay_tractable() {
+        // Quarts d'hora, bloc mínim d'una hora i el màxim d'engegades
+        let start = MADRID.with_ymd_and_hms(2026, 10, 19, 0, 0, 0).unwrap().with_timezone(&Utc);
+        let prices: Vec<PricePeriod> = (0..96)
+            .map(|i| PricePeriod {
+                start: start + Duration::minutes(i * 15),
+                hour: (i / 4) as u8,
+                minute: (i % 4 * 15) as u8,
+                minutes: 15,
+                price: Decimal::new((i * 37) % 23 + 50, 3),
+            })
+            .collect();
+
+        let schedule = optimize_min_hours(&prices, &min_hours(20, Some(60), Some(24))).unwrap();
+        assert_eq!(schedule.total_hours, 20.0);
+        assert!(schedule.slots.iter().filter(|s| s.is_on()).all(|s| s.duration_minutes() >= 60));
+    }
+
+    #[test]
+    fn fails_without_prices() {
+        assert!(matches!(
+            optimize_min_hours(&[], &min_hours(2, None, None)),
+            Err(OptimizerError::NoPrices)
+        ));
+    }
+
+    #[test]
+    fn fails_when_the_rule_cannot_be_satisfied() {
+        let prices = day_with(&[]);
+
+        // Més hores de les que té el dia
+        assert!(matches!(
+            optimize_min_hours(&prices, &min_hours(25, None, None)),
+            Err(OptimizerError::Infeasible(_))
+        ));
+        // Cap engegada permesa
+        assert!(matches!(
+            optimize_min_hours(&prices, &min_hours(2, None, Some(0))),
+            Err(OptimizerError::Infeasible(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_params_that_do_not_match_the_rule_type() {
+        let prices = day_with(&[]);
+        let params = serde_json::json!({ "target_hours_per_day": 2 });
+
+        assert!(matches!(
+            optimize_rule(RuleType::MinHoursCheapest, &params, &prices),
+            Err(OptimizerError::InvalidParams(_))
+        ));
+    }
+
+    #[test]
+    fn schedules_are_anchored_to_utc_instants() {
+        let prices = day_with(&[(0, 10)]);
+        let schedule = optimize_min_hours(&prices, &min_hours(1, None, None)).unwrap();
+        let first = &schedule.slots[0];
+
+        assert_eq!(
+            first.start_at,
+            Some(Utc.from_utc_datetime(&NaiveDate::from_ymd_opt(2026, 10, 18).unwrap().and_hms_opt(22, 0, 0).unwrap()))
+        );
+        assert_eq!(first.end_at, first.start_at.map(|s| s + Duration::hours(1)));
+    }
+
+    // Preus horaris d'un dia de Madrid amb l'etiqueta local de cada hora: els dies de
+    // canvi d'hora en surten 23 (sense les 02:00) o 25 (amb les 02:00 repetides)
+    fn local_day(date: NaiveDate, price_mwh: impl Fn(u32, usize) -> i64) -> Vec<PricePeriod> {
+        let midnight = |d: NaiveDate| {
+            MADRID
+                .from_local_datetime(&d.and_hms_opt(0, 0, 0).unwrap())
+                .earliest()
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        let (start, end) = (midnight(date), midnight(date + Duration::days(1)));
+
+        (0..(end - start).num_hours())
+            .map(|i| {
+                let at = start + Duration::hours(i);
+                let hour = at.with_timezone(&MADRID).hour();
+                PricePeriod {
+                    start: at,
+                    hour: hour as u8,
+                    minute: 0,
+                    minutes: 60,
+                    price: Decimal::new(price_mwh(hour, i as usize), 3),
+                }
+            })
+            .collect()
+    }
+
+    fn utc(y: i32, m: u32, d: u32, h: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn spring_forward_day_has_23_hours_and_no_02_00() {
+        let prices = local_day(NaiveDate::from_ymd_opt(2026, 3, 29).unwrap(), |hour, _| match hour {
+            1 | 3 => 10,
+            _ => 100,
+        });
+        assert_eq!(prices.len(), 23);
+
+        let schedule = optimize_min_hours(&prices, &min_hours(2, None, Some(1))).unwrap();
+
+        // Les 01:00 i les 03:00 són hores seguides: un sol bloc de dues hores reals
+        assert_eq!(on_slots(&schedule), vec![slot("01:00", "04:00")]);
+        let on = schedule.slots.iter().find(|s| s.is_on()).unwrap();
+        assert_eq!(on.start_at, Some(utc(2026, 3, 29, 0)));
+        assert_eq!(on.end_at, Some(utc(2026, 3, 29, 2)));
+        assert_eq!(schedule.total_hours, 2.0);
+
+        // El dia cobreix 23 hores, de mitjanit a mitjanit
+        let first = schedule.slots.first().unwrap();
+        let last = schedule.slots.last().unwrap();
+        assert_eq!((first.start.as_str(), last.end.as_str()), ("00:00", "24:00"));
+        assert_eq!(first.start_at, Some(utc(2026, 3, 28, 23)));
+        assert_eq!(last.end_at, Some(utc(2026, 3, 29, 22)));
+
+        // Mitjana sobre 23 hores: (21 × 100 + 2 × 10) / 23 = 92.17 €/MWh
+        let expected = Decimal::new(2120, 3) * Decimal::from(2) / Decimal::from(23);
+        assert_eq!(schedule.average_cost, expected.round_dp(4));
+    }
+
+    #[test]
+    fn fall_back_day_has_25_hours_and_tells_the_repeated_02_00_apart() {
+        // Només la segona 02:00 (la d'hivern, 01:00 UTC) és barata
+        let prices = local_day(NaiveDate::from_ymd_opt(2026, 10, 25).unwrap(), |_, i| match i {
+            3 => 10,
+            _ => 100,
+        });
+        assert_eq!(prices.len(), 25);
+        assert_eq!(prices.iter().filter(|p| p.hour == 2).count(), 2);
+
+        let schedule = optimize_min_hours(&prices, &min_hours(1, None, None)).unwrap();
+        let on: Vec<&TimeSlot> = schedule.slots.iter().filter(|s| s.is_on()).collect();
+
+        assert_eq!(on.len(), 1);
+        assert_eq!(on[0].start, "02:00");
+        assert_eq!(on[0].start_at, Some(utc(2026, 10, 25, 1)));
+        assert_eq!(on[0].end_at, Some(utc(2026, 10, 25, 2)));
+        assert_eq!(schedule.total_cost, Decimal::new(10, 3));
+
+        // Els slots cobreixen les 25 hores sense forats
+        let first = schedule.slots.first().unwrap();
+        let last = schedule.slots.last().unwrap();
+        assert_eq!(first.start_at, Some(utc(2026, 10, 24, 22)));
+        assert_eq!(last.end_at, Some(utc(2026, 10, 25, 23)));
+        assert_eq!(last.end, "24:00");
+        for pair in schedule.slots.windows(2) {
+            assert_eq!(pair[0].end_at, pair[1].start_at);
+        }
+    }
+
+    #[test]
+    fn fall_back_day_can_use_all_25_hours() {
+        let prices = local_day(NaiveDate::from_ymd_opt(2026, 10, 25).unwrap(), |_, _| 100);
+        let schedule = optimize_min_hours(&prices, &min_hours(25, None, None)).unwrap();
+
+        assert_eq!(schedule.total_hours, 25.0);
+        assert_eq!(schedule.slots.len(), 1);
+        assert!(matches!(
+            optimize_min_hours(&prices, &min_hours(26, None, None)),
+            Err(OptimizerError::Infeasible(_))
+        ));
+    }
+
+    // Trams encesos d'un horari combinat amb la regla a què s'atribueixen
+    fn owned_slots(schedule: &OptimizedSchedule) -> Vec<(String, String, Option<Uuid>)> {
+        schedule
+            .slots
+            .iter()
+            .filter(|s| s.is_on())
+            .map(|s| (s.start.clone(), s.end.clone(), s.rule_id))
+            .collect()
+    }
+
+    fn owned(start: &str, end: &str, rule_id: Uuid) -> (String, String, Option<Uuid>) {
+        (start.to_string(), end.to_string(), Some(rule_id))
+    }
+
+    // Dues regles que es trepitgen: A encén 03-05 i B encén 04-07
+    fn overlapping_rules() -> (Vec<PricePeriod>, OptimizedSchedule, OptimizedSchedule) {
+        let prices = day_with(&[(3, 10), (4, 20)]);
+        let a = optimize_min_hours(&prices, &min_hours(2, None, None)).unwrap();
+        let b_prices = day_with(&[(4, 10), (5, 10), (6, 10)]);
+        let b = optimize_min_hours(&b_prices, &min_hours(3, None, None)).unwrap();
+        assert_eq!(on_slots(&a), vec![slot("03:00", "05:00")]);
+        assert_eq!(on_slots(&b), vec![slot("04:00", "07:00")]);
+        (prices, a, b)
+    }
+
+    #[test]
+    fn merge_turns_the_device_on_when_any_rule_wants_it() {
+        let (prices, a, b) = overlapping_rules();
+        let (rule_a, rule_b) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let merged = merge_schedules(&[(rule_a, a), (rule_b, b)], &prices, MADRID).unwrap();
+
+        // L'encavalcament 04-05 és de la regla de més prioritat (la primera)
+        assert_eq!(
+            owned_slots(&merged),
+            vec![owned("03:00", "05:00", rule_a), owned("05:00", "07:00", rule_b)]
+        );
+        assert_eq!(merged.total_hours, 4.0);
+        // Cost amb els preus del dia: 10 + 20 + 100 + 100 €/MWh
+        assert_eq!(merged.total_cost, Decimal::new(230, 3));
+
+        // Els slots cobreixen tot el dia sense forats
+        assert_eq!(merged.slots.first().unwrap().start, "00:00");
+        assert_eq!(merged.slots.last().unwrap().end, "24:00");
+        for pair in merged.slots.windows(2) {
+            assert_eq!(pair[0].end_at, pair[1].start_at);
+        }
+    }
+
+    #[test]
+    fn merge_attributes_shared_slots_by_priority() {
+        let (prices, a, b) = overlapping_rules();
+        let (rule_a, rule_b) = (Uuid::new_v4(), Uuid::new_v4());
+
+        // Amb B primer, l'hora compartida passa a B
+        let merged = merge_schedules(&[(rule_b, b), (rule_a, a)], &prices, MADRID).unwrap();
+
+        assert_eq!(
+            owned_slots(&merged),
+            vec![owned("03:00", "04:00", rule_a), owned("04:00", "07:00", rule_b)]
+        );
+        assert_eq!(merged.total_hours, 4.0);
+    }
+
+    #[test]
+    fn merge_keeps_the_day_of_a_primary_rule_without_on_slots() {
+        let (prices, a, _) = overlapping_rules();
+        let units = price_units(&prices).unwrap();
+        let idle = build_schedule(&units, &vec![false; units.len()]);
+        let (primary, rule_a) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let merged = merge_schedules(&[(primary, idle), (rule_a, a)], &prices, MADRID).unwrap();
+
+        assert_eq!(owned_slots(&merged), vec![owned("03:00", "05:00", rule_a)]);
+        assert_eq!(merged.slots.len(), 3);
+        assert_eq!(merged.slots[0].rule_id, None);
+        assert_eq!(merged.slots.first().unwrap().start, "00:00");
+        assert_eq!(merged.slots.last().unwrap().end, "24:00");
+        assert_eq!(merged.total_cost, Decimal::new(30, 3));
+    }
+
+    fn threshold(max_price_mwh: i64, max_hours: Option<u8>) -> PriceThresholdParams {
+        PriceThresholdParams {
+            max_price: Some(Decimal::new(max_price_mwh, 3)),
+            percentile: None,
+            min_hours: None,
+            max_hours,
+            min_run_block: None,
+        }
+    }
+
+    #[test]
+    fn threshold_includes_prices_equal_to_the_limit() {
+        let prices = day_with(&[(2, 50), (3, 51), (9, 49)]);
+        let schedule = optimize_price_threshold(&prices, &threshold(50, None)).unwrap();
+
+        assert_eq!(on_slots(&schedule), vec![slot("02:00", "03:00"), slot("09:00", "10:00")]);
+        assert_eq!(schedule.total_cost, Decimal::new(99, 3));
+    }
+
+    #[test]
+    fn threshold_keeps_the_device_off_when_every_price_is_above_it() {
+        let prices = day_with(&[(2, 50)]);
+        let schedule = optimize_price_threshold(&prices, &threshold(40, None)).unwrap();
+
+        assert!(on_slots(&schedule).is_empty());
+        assert_eq!(schedule.total_hours, 0.0);
+        assert_eq!(schedule.slots.len(), 1);
+        assert_eq!(slot(&schedule.slots[0].start, &schedule.slots[0].end), slot("00:00", "24:00"));
+    }
+
+    #[test]
+    fn threshold_max_hours_keeps_only_the_cheapest_qualifying_hours() {
+        let prices = day_with(&[(1, 30), (5, 10), (8, 40), (12, 20), (20, 50)]);
+
+        let free = optimize_price_threshold(&prices, &threshold(50, None)).unwrap();
+        assert_eq!(free.total_hours, 5.0);
+
+        let capped = optimize_price_threshold(&prices, &threshold(50, Some(2))).unwrap();
+        assert_eq!(on_slots(&capped), vec![slot("05:00", "06:00"), slot("12:00", "13:00")]);
+        assert_eq!(capped.total_hours, 2.0);
+        assert_eq!(capped.total_cost, Decimal::new(30, 3));
+    }
+
+    #[test]
+    fn threshold_max_hours_counts_quarter_hour_periods() {
+        // 8 quarts d'hora per sota del límit, com a molt una hora encesa
+        let start = MADRID.with_ymd_and_hms(2026, 10, 19, 0, 0, 0).unwrap().with_timezone(&Utc);
+        let prices: Vec<PricePeriod> = (0..96)
+            .map(|i| PricePeriod {
+                start: start + Duration::minutes(15 * i),
+                hour: (i / 4) as u8,
+                minute: (15 * (i % 4)) as u8,
+                minutes: 15,
+                price: Decimal::new(if (8..16).contains(&i) { 10 + i } else { 100 }, 3),
+            })
+            .collect();
+
+        let schedule = optimize_price_threshold(&prices, &threshold(50, Some(1))).unwrap();
+        assert_eq!(on_slots(&schedule), vec![slot("02:00", "03:00")]);
+        assert_eq!(schedule.total_hours, 1.0);
+    }
+
+    // Preus de dos dies seguits de Madrid (per a sessions de càrrega que travessen la mitjanit)
+    fn two_days(
+        first: NaiveDate,
+        price_mwh: impl Fn(NaiveDate, u32) -> i64,
+    ) -> (Vec<PricePeriod>, Vec<PricePeriod>) {
+        let second = first + Duration::days(1);
+        (
+            local_day(first, |hour, _| price_mwh(first, hour)),
+            local_day(second, |hour, _| price_mwh(second, hour)),
+        )
+    }
+
+    fn charge(energy_kwh: Decimal, charger_kw: Decimal) -> DeadlineEnergyParams {
+        DeadlineEnergyParams {
+            energy_kwh,
+            charger_kw,
+            deadline: "07:00".to_string(),
+            allowed_window: None,
+        }
+    }
+
+    // Minuts encesos abans de l'hora límit de la sessió
+    fn on_minutes_before(schedule: &OptimizedSchedule, deadline: DateTime<Utc>) -> i64 {
+        schedule
+            .slots
+            .iter()
+            .filter(|s| s.is_on())
+            .map(|s| {
+                let (start, end) = (s.start_at.unwrap(), s.end_at.unwrap());
+                assert!(end <= deadline);
+                (end - start).num_minutes()
+            })
+            .sum()
+    }
+
+    #[test]
+    fn deadline_energy_shortens_the_dearest_unit_to_the_missing_minutes() {
+        let day = NaiveDate::from_ymd_opt(2026, 10, 19).unwrap();
+        let (today, tomorrow) = two_days(day, |date, hour| match (date.day(), hour) {
+            (20, 2) => 10,
+            (20, 3) => 20,
+            _ => 100,
+        });
+        // 10 kWh a 8 kW: 75 minuts, una hora sencera i un quart de la següent
+        let params = charge(Decimal::from(10), Decimal::from(8));
+        assert_eq!(params.charge_minutes(), Some(75));
+        let session = params.session(day, MADRID).unwrap();
+
+        let prices = [today, tomorrow].concat();
+        let schedule = optimize_deadline_energy(&prices, &params, session).unwrap();
+
+        assert_eq!(on_slots(&schedule), vec![slot("02:00", "03:15")]);
+        let on = schedule.slots.iter().find(|s| s.is_on()).unwrap();
+        assert_eq!(on.start_at, Some(utc(2026, 10, 20, 0)));
+        assert_eq!(on.end_at, Some(utc(2026, 10, 20, 1) + Duration::minutes(15)));
+
+        // Energia carregada abans de l'hora límit
+        let minutes = on_minutes_before(&schedule, session.1);
+        assert_eq!(Decimal::from(minutes) * params.charger_kw / Decimal::from(60), params.energy_kwh);
+        assert_eq!(schedule.total_cost, Decimal::new(15, 3));
+    }
+
+    #[test]
+    fn deadline_energy_rounds_non_whole_charge_minutes_up() {
+        let day = NaiveDate::from_ymd_opt(2026, 10, 19).unwrap();
+        let (today, tomorrow) = two_days(day, |date, hour| match (date.day(), hour) {
+            (20, 1) => 10,
+            _ => 100,
+        });
+        // 10 kWh a 7.4 kW són 81.08 minuts: se'n carreguen 82
+        let params = charge(Decimal::from(10), Decimal::new(74, 1));
+        assert_eq!(params.charge_minutes(), Some(82));
+        let session = params.session(day, MADRID).unwrap();
+
+        let schedule = optimize_deadline_energy(&[today, tomorrow].concat(), &params, session).unwrap();
+
+        let minutes = on_minutes_before(&schedule, session.1);
+        assert_eq!(minutes, 82);
+        assert!(Decimal::from(minutes) * params.charger_kw / Decimal::from(60) >= params.energy_kwh);
+    }
+
+    #[test]
+    fn deadline_energy_needs_the_prices_of_the_whole_session() {
+        let day = NaiveDate::from_ymd_opt(2026, 10, 19).unwrap();
+        let (today, _) = two_days(day, |_, _| 100);
+        let params = charge(Decimal::from(10), Decimal::from(8));
+        let session = params.session(day, MADRID).unwrap();
+
+        assert!(matches!(
+            optimize_deadline_energy(&today, &params, session),
+            Err(OptimizerError::NoPrices)
+        ));
+    }
+
+    #[test]
+    fn sessions_crossing_midnight_are_split_between_both_days() {
+        let day = NaiveDate::from_ymd_opt(2026, 10, 19).unwrap();
+        let (today, tomorrow) = two_days(day, |date, hour| match (date.day(), hour) {
+            (19, 23) => 10,
+            (20, 1) => 10,
+            _ => 100,
+        });
+        let params = charge(Decimal::from(16), Decimal::from(8));
+        let session = params.session(day, MADRID).unwrap();
+        let planned =
+            optimize_deadline_energy(&[today.clone(), tomorrow.clone()].concat(), &params, session).unwrap();
+        assert_eq!(planned.total_hours, 2.0);
+
+        let first = clip_to_day(std::slice::from_ref(&planned), &today, MADRID).unwrap();
+        assert_eq!(on_slots(&first), vec![slot("23:00", "24:00")]);
+        assert_eq!(first.slots.first().unwrap().start, "00:00");
+
+        let second = clip_to_day(std::slice::from_ref(&planned), &tomorrow, MADRID).unwrap();
+        assert_eq!(on_slots(&second), vec![slot("01:00", "02:00")]);
+        assert_eq!(second.slots.last().unwrap().end, "24:00");
+
+        // Entre els dos dies es carrega tota l'energia
+        assert_eq!(first.total_hours + second.total_hours, 2.0);
+    }
+
+    // Instant local de Madrid del 19 d'octubre de 2026
+    fn madrid_at(hour: u32, minute: u32) -> DateTime<Utc> {
+        MADRID.with_ymd_and_hms(2026, 10, 19, hour, minute, 0).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn cheapest_block_starts_on_the_quarter_hour_grid() {
+        let prices = day_with(&[(10, 10)]);
+
+        // A les 10:07 el primer inici possible és a les 10:15
+        let (start, cost) = cheapest_block(&prices, 30, madrid_at(10, 7), madrid_at(23, 0)).unwrap();
+        assert_eq!(start, madrid_at(10, 15));
+        assert_eq!(cost, Decimal::new(5, 3));
+
+        let (start, _) = cheapest_block(&prices, 60, madrid_at(9, 50), madrid_at(23, 0)).unwrap();
+        assert_eq!(start, madrid_at(10, 0));
+    }
+
+    #[test]
+    fn cheapest_block_follows_quarter_hour_prices() {
+        let start = madrid_at(0, 0);
+        let prices: Vec<PricePeriod> = (0..96)
+            .map(|i| PricePeriod {
+                start: start + Duration::minutes(15 * i),
+                hour: (i / 4) as u8,
+                minute: (15 * (i % 4)) as u8,
+                minutes: 15,
+                price: Decimal::new(if (42..44).contains(&i) { 10 } else { 100 }, 3),
+            })
+            .collect();
+
+        let (start, cost) = cheapest_block(&prices, 30, madrid_at(0, 0), madrid_at(23, 0)).unwrap();
+        assert_eq!(start, madrid_at(10, 30));
+        assert_eq!(cost, Decimal::new(5, 3));
+    }
+
+    #[test]
+    fn cheapest_block_must_end_before_the_deadline() {
+        let prices = day_with(&[(10, 10), (11, 10)]);
+
+        assert!(cheapest_block(&prices, 120, madrid_at(10, 0), madrid_at(11, 45)).is_none());
+        // El més tard possible és el més barat: 09:45-11:45 aprofita 1 h 45 de les barates
+        let (start, cost) = cheapest_block(&prices, 120, madrid_at(9, 0), madrid_at(11, 45)).unwrap();
+        assert_eq!(start, madrid_at(9, 45));
+        assert_eq!(cost, Decimal::new(425, 4));
+
+        // Sense preus per a tot el bloc no hi ha cap candidat
+        assert!(cheapest_block(&prices, 60, madrid_at(23, 30), madrid_at(23, 0) + Duration::hours(3)).is_none());
+    }
+}
@@ -0,0 +1,454 @@
+use crate::{
+    models::{
+        command::NewAutomationLog,
+        price::{PriceAlert, PriceTier, TierThresholds},
+        schedule::PricePeriod,
+    },
+    schema::{automation_logs, price_alerts},
+    services::{
+        push_notifier::{self, PushData, PushKind, PushNotifier},
+        schedule_builder::ScheduleError,
+        settings, tariffs,
+        ws_hub::{WsEvent, WsHub},
+    },
+    DbPool,
+};
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use rust_decimal::Decimal;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+const TICK_SECONDS: u64 = 60;
+
+// Tram de períodes consecutius de la mateixa franja
+#[derive(Debug, Clone)]
+pub struct PriceRun {
+    pub tier: PriceTier,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub min_price: Decimal,
+    pub max_price: Decimal,
+}
+
+impl PriceRun {
+    pub fn minutes(&self) -> i64 {
+        (self.end - self.start).num_minutes()
+    }
+}
+
+// Avís a punt d'enviar
+#[derive(Debug, Clone)]
+pub struct DueAlert {
+    pub user_id: Uuid,
+    pub tier: PriceTier,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub message: String,
+}
+
+pub fn load_subscription(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+) -> Result<Option<PriceAlert>, diesel::result::Error> {
+    price_alerts::table
+        .find(user_id)
+        .first::<PriceAlert>(conn)
+        .optional()
+}
+
+// Desa la configuració; els avisos ja enviats es conserven
+pub fn save_subscription(
+    conn: &mut PgConnection,
+    alert: &PriceAlert,
+) -> Result<PriceAlert, diesel::result::Error> {
+    diesel::insert_into(price_alerts::table)
+        .values(alert)
+        .on_conflict(price_alerts::user_id)
+        .do_update()
+        .set((
+            price_alerts::enabled.eq(excluded(price_alerts::enabled)),
+            price_alerts::notify_cheap.eq(excluded(price_alerts::notify_cheap)),
+            price_alerts::notify_expensive.eq(excluded(price_alerts::notify_expensive)),
+            price_alerts::cheap_percentile.eq(excluded(price_alerts::cheap_percentile)),
+            price_alerts::expensive_percentile.eq(excluded(price_alerts::expensive_percentile)),
+            price_alerts::cheap_threshold.eq(excluded(price_alerts::cheap_threshold)),
+            price_alerts::expensive_threshold.eq(excluded(price_alerts::expensive_threshold)),
+            price_alerts::min_hours.eq(excluded(price_alerts::min_hours)),
+            price_alerts::lead_minutes.eq(excluded(price_alerts::lead_minutes)),
+            price_alerts::updated_at.eq(Utc::now()),
+        ))
+        .get_result::<PriceAlert>(conn)
+}
+
+// Agrupa els períodes consecutius de la mateixa franja
+pub fn find_runs(prices: &[PricePeriod], thresholds: &TierThresholds) -> Vec<PriceRun> {
+    let mut runs: Vec<PriceRun> = Vec::new();
+
+    for period in prices {
+        let tier = thresholds.classify(period.price);
+        let end = period.start + Duration::minutes(period.minutes as i64);
+
+        match runs.last_mut() {
+            Some(run) if run.tier == tier && run.end == period.start => {
+                run.end = end;
+                run.min_price = run.min_price.min(period.price);
+                run.max_price = run.max_price.max(period.price);
+            }
+            _ => runs.push(PriceRun {
+                tier,
+                start: period.start,
+                end,
+                min_price: period.price,
+                max_price: period.price,
+            }),
+        }
+    }
+
+    runs
+}
+
+// Tasca en segon pla: avisa quan comença (o està a punt de començar) un tram barat o car
+pub async fn run_price_alerts(pool: DbPool, notifier: Arc<dyn PushNotifier>, hub: Arc<WsHub>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(TICK_SECONDS));
+    loop {
+        interval.tick().await;
+        match collect_due_alerts(&pool).await {
+            Ok(alerts) => {
+                for alert in &alerts {
+                    deliver_alert(&pool, notifier.as_ref(), hub.as_ref(), alert).await;
+                }
+            }
+            Err(e) => log::error!("Price alert check failed: {}", e),
+        }
+    }
+}
+
+// Retorna els avisos que toca enviar ara, ja marcats com a enviats i registrats
+pub async fn collect_due_alerts(pool: &DbPool) -> Result<Vec<DueAlert>, ScheduleError> {
+    let conn = pool
+        .get()
+        .await
+        .map_err(|e| ScheduleError::Database(e.to_string()))?;
+
+    conn.interact(|conn| {
+        let now = Utc::now();
+        let subscriptions = price_alerts::table
+            .filter(price_alerts::enabled.eq(true))
+            .filter(price_alerts::notify_cheap.or(price_alerts::notify_expensive))
+            .load::<PriceAlert>(conn)?;
+
+        let mut due = Vec::new();
+        for subscription in subscriptions {
+            let user_id = subscription.user_id;
+            match check_subscription(conn, &subscription, now) {
+                Ok(alerts) => due.extend(alerts),
+                Err(e) => log::error!("Failed to check price alerts for user {}: {}", user_id, e),
+            }
+        }
+
+        Ok::<_, diesel::result::Error>(due)
+    })
+    .await
+    .map_err(|e| ScheduleError::Database(e.to_string()))?
+    .map_err(|e| ScheduleError::Database(e.to_string()))
+}
+
+fn check_subscription(
+    conn: &mut PgConnection,
+    subscription: &PriceAlert,
+    now: DateTime<Utc>,
+) -> Result<Vec<DueAlert>, diesel::result::Error> {
+    let user_settings = settings::load_settings(conn, subscription.user_id)?;
+    let tz = user_settings.tz();
+    let today = now.with_timezone(&tz).date_naive();
+
+    let tariff = tariffs::user_tariff(conn, subscription.user_id)?;
+    let Some(prices) = tariffs::load_tariff_prices(conn, &tariff, today, tz)? else {
+        return Ok(Vec::new());
+    };
+    let Some(thresholds) = subscription.tier_config().thresholds(&prices) else {
+        return Ok(Vec::new());
+    };
+    let day_min = prices.iter().map(|p| p.price).min();
+    let day_max = prices.iter().map(|p| p.price).max();
+
+    let lead = Duration::minutes(subscription.lead_minutes as i64);
+    let mut due = Vec::new();
+    for run in find_runs(&prices, &thresholds) {
+        let wanted = match run.tier {
+            PriceTier::Cheap => subscription.notify_cheap,
+            PriceTier::Expensive => subscription.notify_expensive,
+            PriceTier::Normal => false,
+        };
+        if !wanted
+            || run.minutes() < subscription.min_hours as i64 * 60
+            || now < run.start - lead
+            || now >= run.end
+        {
+            continue;
+        }
+
+        // El tram conté el preu més baix (o més alt) del dia
+        let extreme = match run.tier {
+            PriceTier::Cheap => day_min == Some(run.min_price),
+            _ => day_max == Some(run.max_price),
+        };
+        let message = alert_message(&user_settings.language, run.tier, extreme, &run, tz);
+
+        if claim_alert(conn, subscription.user_id, &run, &message)? {
+            due.push(DueAlert {
+                user_id: subscription.user_id,
+                tier: run.tier,
+                start: run.start,
+                end: run.end,
+                message,
+            });
+        }
+    }
+
+    Ok(due)
+}
+
+// Marca el tram com a avisat i el registra. L'actualització condicional garanteix
+// que cada tram s'avisa una sola vegada encara que hi hagi diverses instàncies.
+fn claim_alert(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    run: &PriceRun,
+    message: &str,
+) -> Result<bool, diesel::result::Error> {
+    conn.transaction(|conn| {
+        let subscription = price_alerts::table.find(user_id);
+        let claimed = match run.tier {
+            PriceTier::Cheap => diesel::update(subscription.filter(
+                price_alerts::last_cheap_alert
+                    .is_null()
+                    .or(price_alerts::last_cheap_alert.lt(run.start)),
+            ))
+            .set(price_alerts::last_cheap_alert.eq(run.start))
+            .execute(conn)?,
+            PriceTier::Expensive => diesel::update(subscription.filter(
+                price_alerts::last_expensive_alert
+                    .is_null()
+                    .or(price_alerts::last_expensive_alert.lt(run.start)),
+            ))
+            .set(price_alerts::last_expensive_alert.eq(run.start))
+            .execute(conn)?,
+            PriceTier::Normal => 0,
+        };
+
+        if claimed == 0 {
+            return Ok(false);
+        }
+
+        diesel::insert_into(automation_logs::table)
+            .values(&NewAutomationLog {
+                id: Uuid::new_v4(),
+                user_id,
+                device_id: None,
+                rule_id: None,
+                action: "price_alert".to_string(),
+                details_json: Some(json!({
+                    "tier": run.tier,
+                    "start": run.start,
+                    "end": run.end,
+                    "min_price": run.min_price,
+                    "max_price": run.max_price,
+                    "message": message,
+                })),
+            })
+            .execute(conn)?;
+
+        Ok(true)
+    })
+}
+
+// Text de l'avís en l'idioma de l'usuari, amb les hores locals del tram
+fn alert_message(language: &str, tier: PriceTier, extreme: bool, run: &PriceRun, tz: Tz) -> String {
+    let start = run.start.with_timezone(&tz).format("%H:%M");
+    let end = run.end.with_timezone(&tz).format("%H:%M");
+
+    match (language, tier, extreme) {
+        ("ca", PriceTier::Cheap, true) => format!("L'electricitat més barata del dia, de {} a {}", start, end),
+        ("ca", PriceTier::Cheap, false) => format!("Electricitat barata de {} a {}", start, end),
+        ("ca", _, true) => format!("L'electricitat més cara del dia, de {} a {}", start, end),
+        ("ca", _, false) => format!("Electricitat cara de {} a {}", start, end),
+        ("es", PriceTier::Cheap, true) => format!("La electricidad más barata del día, de {} a {}", start, end),
+        ("es", PriceTier::Cheap, false) => format!("Electricidad barata de {} a {}", start, end),
+        ("es", _, true) => format!("La electricidad más cara del día, de {} a {}", start, end),
+        ("es", _, false) => format!("Electricidad cara de {} a {}", start, end),
+        (_, PriceTier::Cheap, true) => format!("Cheapest electricity of the day from {} to {}", start, end),
+        (_, PriceTier::Cheap, false) => format!("Cheap electricity from {} to {}", start, end),
+        (_, _, true) => format!("Most expensive electricity of the day from {} to {}", start, end),
+        (_, _, false) => format!("Expensive electricity from {} to {}", start, end),
+    }
+}
+
+// Envia l'avís per push i pel WebSocket. Els errors de push només es registren.
+pub async fn deliver_alert(pool: &DbPool, notifier: &dyn PushNotifier, hub: &WsHub, alert: &DueAlert) {
+    hub.send_to_user(
+        alert.user_id,
+        &WsEvent::PriceAlert {
+            tier: alert.tier.as_str().to_string(),
+            start: alert.start,
+            end: alert.end,
+            message: alert.message.clone(),
+        },
+    );
+
+    let data = PushData::from([
+        ("type".to_string(), "price_alert".to_string()),
+        ("tier".to_string(), alert.tier.as_str().to_string()),
+        ("start".to_string(), alert.start.to_rfc3339()),
+        ("end".to_string(), alert.end.to_rfc3339()),
+        ("message".to_string(), alert.message.clone()),
+    ]);
+
+    let kind = PushKind::PriceAlert;
+    match push_notifier::notify_user(pool, notifier, alert.user_id, kind, data).await {
+        Ok(report) => log::info!(
+            "Price alert ({}) for user {} delivered to {} sessions",
+            alert.tier.as_str(),
+            alert.user_id,
+            report.delivered
+        ),
+        Err(e) => log::warn!("Failed to push price alert to user {}: {}", alert.user_id, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::NewUser;
+    use crate::schema::users;
+    use diesel_migrations::MigrationHarness;
+
+    fn start() -> DateTime<Utc> {
+        "2026-10-19T00:00:00Z".parse().unwrap()
+    }
+
+    // Períodes horaris a partir de mitjanit UTC, amb preus en cèntims d'euro
+    fn hourly(prices: &[(i64, i64)]) -> Vec<PricePeriod> {
+        prices
+            .iter()
+            .map(|&(hour, price)| PricePeriod {
+                start: start() + Duration::hours(hour),
+                hour: hour as u8,
+                minute: 0,
+                minutes: 60,
+                price: Decimal::new(price, 2),
+            })
+            .collect()
+    }
+
+    fn thresholds() -> TierThresholds {
+        TierThresholds {
+            cheap_max: Decimal::new(10, 2),
+            expensive_min: Decimal::new(20, 2),
+        }
+    }
+
+    fn spans(runs: &[PriceRun]) -> Vec<(PriceTier, i64, i64)> {
+        runs.iter()
+            .map(|r| (r.tier, (r.start - start()).num_hours(), (r.end - start()).num_hours()))
+            .collect()
+    }
+
+    #[test]
+    fn consecutive_periods_of_the_same_tier_form_one_run() {
+        let prices = hourly(&[(0, 5), (1, 8), (2, 10), (3, 15), (4, 25), (5, 30), (6, 9)]);
+        let runs = find_runs(&prices, &thresholds());
+
+        assert_eq!(
+            spans(&runs),
+            vec![
+                (PriceTier::Cheap, 0, 3),
+                (PriceTier::Normal, 3, 4),
+                (PriceTier::Expensive, 4, 6),
+                (PriceTier::Cheap, 6, 7),
+            ]
+        );
+        assert_eq!(runs[0].min_price, Decimal::new(5, 2));
+        assert_eq!(runs[0].max_price, Decimal::new(10, 2));
+        assert_eq!(runs[0].minutes(), 180);
+    }
+
+    #[test]
+    fn a_gap_between_periods_splits_the_run() {
+        // Falta l'hora 2
+        let prices = hourly(&[(0, 5), (1, 5), (3, 5), (4, 5)]);
+        let runs = find_runs(&prices, &thresholds());
+
+        assert_eq!(spans(&runs), vec![(PriceTier::Cheap, 0, 2), (PriceTier::Cheap, 3, 5)]);
+    }
+
+    #[test]
+    fn quarter_hour_periods_join_into_runs() {
+        let prices: Vec<PricePeriod> = (0..8)
+            .map(|i| PricePeriod {
+                start: start() + Duration::minutes(i * 15),
+                hour: (i / 4) as u8,
+                minute: (i % 4 * 15) as u8,
+                minutes: 15,
+                price: Decimal::new(if i < 6 { 5 } else { 15 }, 2),
+            })
+            .collect();
+        let runs = find_runs(&prices, &thresholds());
+
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].minutes(), 90);
+        assert_eq!(runs[1].tier, PriceTier::Normal);
+        assert!(find_runs(&[], &thresholds()).is_empty());
+    }
+
+    // Base de dades de proves: `TEST_DATABASE_URL=... cargo test -- --ignored`
+    fn test_connection() -> PgConnection {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+        let mut conn = PgConnection::establish(&url).expect("Failed to connect to database");
+        conn.run_pending_migrations(crate::MIGRATIONS)
+            .expect("Failed to run migrations");
+        conn
+    }
+
+    #[test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    fn each_run_is_alerted_only_once() {
+        let mut conn = test_connection();
+        conn.test_transaction::<_, diesel::result::Error, _>(|conn| {
+            let user_id = Uuid::new_v4();
+            diesel::insert_into(users::table)
+                .values(&NewUser {
+                    id: user_id,
+                    google_sub: format!("test-{}", user_id),
+                    email: "alerts@example.com".to_string(),
+                    name: "Alerts".to_string(),
+                    picture: None,
+                })
+                .execute(conn)?;
+            save_subscription(conn, &PriceAlert::defaults(user_id))?;
+
+            let runs = find_runs(&hourly(&[(0, 5), (1, 5), (2, 25), (3, 15), (4, 5)]), &thresholds());
+            let (cheap, expensive, later) = (&runs[0], &runs[1], &runs[3]);
+
+            assert!(claim_alert(conn, user_id, cheap, "cheap")?);
+            assert!(!claim_alert(conn, user_id, cheap, "cheap")?);
+
+            // Les franges es controlen per separat i un tram posterior torna a avisar
+            assert!(claim_alert(conn, user_id, expensive, "expensive")?);
+            assert!(claim_alert(conn, user_id, later, "later")?);
+            assert!(!claim_alert(conn, user_id, cheap, "cheap")?);
+
+            let logged: i64 = automation_logs::table
+                .filter(automation_logs::user_id.eq(user_id))
+                .filter(automation_logs::action.eq("price_alert"))
+                .count()
+                .get_result(conn)?;
+            assert_eq!(logged, 3);
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,64 @@
+use crate::{
+    models::{schedule::PricePeriod, tariff::PriceSeries},
+    services::schedule_builder,
+};
+use chrono::NaiveDate;
+use diesel::prelude::*;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+// Dies peninsulars (de totes les corbes) que es mantenen en memòria com a molt
+const MAX_CACHED_DAYS: usize = 124;
+
+type DayKey = (NaiveDate, PriceSeries);
+
+// Preus publicats per corba i dia peninsular. Els dies no publicats no es desen, així
+// que la consulta següent els torna a buscar a la base de dades.
+#[derive(Default)]
+pub struct PriceCache {
+    days: Mutex<BTreeMap<DayKey, Arc<Vec<PricePeriod>>>>,
+}
+
+impl PriceCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_or_load(
+        &self,
+        conn: &mut PgConnection,
+        series: PriceSeries,
+        date: NaiveDate,
+    ) -> Result<Option<Arc<Vec<PricePeriod>>>, diesel::result::Error> {
+        let key = (date, series);
+        if let Some(prices) = self.days.lock().ok().and_then(|days| days.get(&key).cloned()) {
+            return Ok(Some(prices));
+        }
+
+        let Some(prices) = schedule_builder::load_day_price(conn, series, date)?
+            .and_then(|dp| dp.get_prices().ok())
+        else {
+            return Ok(None);
+        };
+
+        let prices = Arc::new(prices);
+        if let Ok(mut days) = self.days.lock() {
+            days.insert(key, prices.clone());
+            // Es descarten els dies més antics
+            while days.len() > MAX_CACHED_DAYS {
+                days.pop_first();
+            }
+        }
+
+        Ok(Some(prices))
+    }
+
+    // S'ha de cridar quan es desen preus nous d'un dia
+    pub fn invalidate(&self, series: PriceSeries, date: NaiveDate) {
+        if let Ok(mut days) = self.days.lock() {
+            days.remove(&(date, series));
+        }
+    }
+}
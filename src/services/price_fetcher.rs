@@ -0,0 +1,845 @@
+use crate::{
+    models::{
+        schedule::{DayPrice, PricePeriod, NewDayPrice},
+        tariff::PriceSeries,
+    },
+    schema::day_prices,
+    services::{
+        price_cache::PriceCache,
+        schedule_builder, tasks,
+        ws_hub::{WsEvent, WsHub},
+    },
+    DbPool,
+};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use futures_util::future::BoxFuture;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{env, path::PathBuf, str::FromStr, sync::Arc};
+use uuid::Uuid;
+
+// Zona horària en què es publiquen els preus PVPC (península)
+pub const PRICE_TIMEZONE: Tz = chrono_tz::Europe::Madrid;
+
+// REE publica els preus de l'endemà cap a les 20:15 (hora peninsular)
+const DEFAULT_FETCH_CRON: &str = "0 30 20 * * *";
+const DEFAULT_MAX_RETRIES: u32 = 5;
+const DEFAULT_RETRY_DELAY_MINUTES: i64 = 5;
+
+const REE_DEFAULT_BASE_URL: &str = "https://apidatos.ree.es";
+const ESIOS_DEFAULT_BASE_URL: &str = "https://api.esios.ree.es";
+const PVPC_INDICATOR_ID: &str = "1001";
+const SPOT_INDICATOR_ID: &str = "600";
+const ESIOS_PENINSULA_GEO_ID: i64 = 8741;
+const ESIOS_SPAIN_GEO_ID: i64 = 3;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PriceError {
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Upstream returned status {0}")]
+    Status(reqwest::StatusCode),
+    #[error("Failed to read price file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse price data: {0}")]
+    Parse(String),
+    #[error("Prices for {0} are not published yet")]
+    NotPublished(NaiveDate),
+    #[error("Database error: {0}")]
+    Database(String),
+    #[error("Invalid price source configuration: {0}")]
+    Config(String),
+}
+
+// Font de preus intercanviable (REE, ESIOS, fitxers locals...)
+pub trait PriceSource: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Retorna els preus (€/kWh) de la corba i el dia indicats en hora local peninsular,
+    // en ordre cronològic: per hores o per quarts, amb 23 o 25 hores els dies de canvi horari
+    fn fetch_day(
+        &self,
+        date: NaiveDate,
+        series: PriceSeries,
+    ) -> BoxFuture<'_, Result<Vec<PricePeriod>, PriceError>>;
+}
+
+// Indicador de REE/ESIOS de cada corba
+fn indicator_id(series: PriceSeries) -> &'static str {
+    match series {
+        PriceSeries::Pvpc => PVPC_INDICATOR_ID,
+        PriceSeries::Spot => SPOT_INDICATOR_ID,
+    }
+}
+
+// Valor cru d'una font externa, en €/MWh
+#[derive(Debug, Clone)]
+struct RawPrice {
+    datetime: DateTime<FixedOffset>,
+    value_mwh: Decimal,
+}
+
+// Durades de període acceptades: hores o quarts d'hora (liquidació quart-horària)
+const RESOLUTIONS_MINUTES: [i64; 2] = [60, 15];
+
+// Converteix valors en €/MWh a preus en €/kWh del dia local demanat. La resolució
+// (60 o 15 minuts) es dedueix de la separació entre valors consecutius.
+fn normalize_prices(date: NaiveDate, mut raw: Vec<RawPrice>) -> Result<Vec<PricePeriod>, PriceError> {
+    raw.retain(|p| p.datetime.with_timezone(&PRICE_TIMEZONE).date_naive() == date);
+    raw.sort_by_key(|p| p.datetime);
+    raw.dedup_by_key(|p| p.datetime);
+
+    if raw.is_empty() {
+        return Err(PriceError::NotPublished(date));
+    }
+
+    let resolution = match raw.as_slice() {
+        [first, second, ..] => (second.datetime - first.datetime).num_minutes(),
+        _ => 60,
+    };
+    if !RESOLUTIONS_MINUTES.contains(&resolution) {
+        return Err(PriceError::Parse(format!(
+            "unsupported price resolution of {} minutes for {}",
+            resolution, date
+        )));
+    }
+
+    // El dia ha de quedar cobert de mitjanit a mitjanit sense forats: 24 hores,
+    // o 23/25 en els dies de canvi horari
+    let day_start = |date: NaiveDate| {
+        PRICE_TIMEZONE
+            .from_local_datetime(&date.and_time(NaiveTime::MIN))
+            .earliest()
+            .map(|dt| dt.with_timezone(&Utc))
+    };
+    let (Some(start), Some(end)) = (day_start(date), day_start(date + Duration::days(1))) else {
+        return Err(PriceError::Parse(format!("no local midnight for {}", date)));
+    };
+    let expected = ((end - start).num_minutes() / resolution) as usize;
+    if raw.len() != expected {
+        return Err(PriceError::Parse(format!(
+            "expected {} prices of {} minutes for {}, got {}",
+            expected,
+            resolution,
+            date,
+            raw.len()
+        )));
+    }
+
+    let step = Duration::minutes(resolution);
+    let mut period_start = start;
+    for p in &raw {
+        if p.datetime.with_timezone(&Utc) != period_start {
+            return Err(PriceError::Parse(format!(
+                "missing {}-minute price at {} for {}",
+                resolution,
+                period_start.with_timezone(&PRICE_TIMEZONE).format("%H:%M %:z"),
+                date
+            )));
+        }
+        period_start += step;
+    }
+
+    let kwh = Decimal::from(1000);
+    Ok(raw
+        .into_iter()
+        .map(|p| {
+            let local = p.datetime.with_timezone(&PRICE_TIMEZONE);
+            PricePeriod {
+                start: p.datetime.with_timezone(&Utc),
+                hour: local.hour() as u8,
+                minute: local.minute() as u8,
+                minutes: resolution as u32,
+                price: (p.value_mwh / kwh).round_dp(6),
+            }
+        })
+        .collect())
+}
+
+// Format de resposta de l'API apidatos de REE
+#[derive(Debug, Deserialize)]
+struct ReeResponse {
+    included: Vec<ReeIncluded>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReeIncluded {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    attributes: ReeAttributes,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReeAttributes {
+    values: Vec<ReeValue>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReeValue {
+    value: Decimal,
+    datetime: DateTime<FixedOffset>,
+}
+
+fn parse_ree(date: NaiveDate, body: &str, series: PriceSeries) -> Result<Vec<PricePeriod>, PriceError> {
+    let response: ReeResponse =
+        serde_json::from_str(body).map_err(|e| PriceError::Parse(e.to_string()))?;
+
+    let label = match series {
+        PriceSeries::Pvpc => "PVPC",
+        PriceSeries::Spot => "Precio mercado spot",
+    };
+    let series = response
+        .included
+        .into_iter()
+        .find(|s| s.id == indicator_id(series) || s.kind.starts_with(label))
+        .ok_or_else(|| PriceError::Parse(format!("{} series not found in REE response", label)))?;
+
+    let raw = series
+        .attributes
+        .values
+        .into_iter()
+        .map(|v| RawPrice {
+            datetime: v.datetime,
+            value_mwh: v.value,
+        })
+        .collect();
+
+    normalize_prices(date, raw)
+}
+
+// Format de resposta de l'indicador d'ESIOS
+#[derive(Debug, Deserialize)]
+struct EsiosResponse {
+    indicator: EsiosIndicator,
+}
+
+#[derive(Debug, Deserialize)]
+struct EsiosIndicator {
+    values: Vec<EsiosValue>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EsiosValue {
+    value: Decimal,
+    datetime: DateTime<FixedOffset>,
+    #[serde(default)]
+    geo_id: Option<i64>,
+}
+
+fn parse_esios(date: NaiveDate, body: &str, geo_id: i64) -> Result<Vec<PricePeriod>, PriceError> {
+    let response: EsiosResponse =
+        serde_json::from_str(body).map_err(|e| PriceError::Parse(e.to_string()))?;
+
+    let raw = response
+        .indicator
+        .values
+        .into_iter()
+        .filter(|v| v.geo_id.is_none_or(|g| g == geo_id))
+        .map(|v| RawPrice {
+            datetime: v.datetime,
+            value_mwh: v.value,
+        })
+        .collect();
+
+    normalize_prices(date, raw)
+}
+
+fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(30))
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+async fn read_body(response: reqwest::Response, date: NaiveDate) -> Result<String, PriceError> {
+    match response.status() {
+        reqwest::StatusCode::NOT_FOUND => Err(PriceError::NotPublished(date)),
+        status if !status.is_success() => Err(PriceError::Status(status)),
+        _ => Ok(response.text().await?),
+    }
+}
+
+// Font REE apidatos (pública, sense token)
+pub struct ReeSource {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl ReeSource {
+    pub fn new(base_url: Option<String>) -> Self {
+        Self {
+            client: http_client(),
+            base_url: base_url.unwrap_or_else(|| REE_DEFAULT_BASE_URL.to_string()),
+        }
+    }
+}
+
+impl PriceSource for ReeSource {
+    fn name(&self) -> &'static str {
+        "ree"
+    }
+
+    fn fetch_day(
+        &self,
+        date: NaiveDate,
+        series: PriceSeries,
+    ) -> BoxFuture<'_, Result<Vec<PricePeriod>, PriceError>> {
+        Box::pin(async move {
+            let url = format!(
+                "{}/es/datos/mercados/precios-mercados-tiempo-real",
+                self.base_url.trim_end_matches('/')
+            );
+            // Sense time_trunc: REE retorna la resolució nativa (quarts d'hora des
+            // d'octubre de 2025) i no la mitjana horària
+            let response = self
+                .client
+                .get(url)
+                .query(&[
+                    ("start_date", format!("{}T00:00", date)),
+                    ("end_date", format!("{}T23:59", date)),
+                ])
+                .send()
+                .await?;
+
+            let body = read_body(response, date).await?;
+            parse_ree(date, &body, series)
+        })
+    }
+}
+
+// Font ESIOS (indicadors 1001 i 600, requereix token)
+pub struct EsiosSource {
+    client: reqwest::Client,
+    base_url: String,
+    token: String,
+}
+
+impl EsiosSource {
+    pub fn new(base_url: Option<String>, token: String) -> Self {
+        Self {
+            client: http_client(),
+            base_url: base_url.unwrap_or_else(|| ESIOS_DEFAULT_BASE_URL.to_string()),
+            token,
+        }
+    }
+}
+
+impl PriceSource for EsiosSource {
+    fn name(&self) -> &'static str {
+        "esios"
+    }
+
+    fn fetch_day(
+        &self,
+        date: NaiveDate,
+        series: PriceSeries,
+    ) -> BoxFuture<'_, Result<Vec<PricePeriod>, PriceError>> {
+        Box::pin(async move {
+            let url = format!(
+                "{}/indicators/{}",
+                self.base_url.trim_end_matches('/'),
+                indicator_id(series)
+            );
+            let response = self
+                .client
+                .get(url)
+                .header("Accept", "application/json; application/vnd.esios-api-v1+json")
+                .header("x-api-key", &self.token)
+                .query(&[
+                    ("start_date", format!("{}T00:00:00", date)),
+                    ("end_date", format!("{}T23:59:59", date)),
+                ])
+                .send()
+                .await?;
+
+            let body = read_body(response, date).await?;
+            // El PVPC es publica per zones i el preu spot per països
+            let geo_id = match series {
+                PriceSeries::Pvpc => ESIOS_PENINSULA_GEO_ID,
+                PriceSeries::Spot => ESIOS_SPAIN_GEO_ID,
+            };
+            parse_esios(date, &body, geo_id)
+        })
+    }
+}
+
+// Font local: llegeix `<dir>/<YYYY-MM-DD>.json` en format REE (per a tests i desenvolupament)
+pub struct FileSource {
+    dir: PathBuf,
+}
+
+impl FileSource {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl PriceSource for FileSource {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn fetch_day(
+        &self,
+        date: NaiveDate,
+        series: PriceSeries,
+    ) -> BoxFuture<'_, Result<Vec<PricePeriod>, PriceError>> {
+        Box::pin(async move {
+            let path = self.dir.join(format!("{}.json", date));
+            let body = match tokio::fs::read_to_string(&path).await {
+                Ok(body) => body,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(PriceError::NotPublished(date))
+                }
+                Err(e) => return Err(e.into()),
+            };
+            parse_ree(date, &body, series)
+        })
+    }
+}
+
+// Construeix la font configurada a partir de les variables d'entorn:
+// PRICE_SOURCE (ree | esios | file), PRICE_API_URL, PRICE_API_TOKEN i PRICE_FILES_DIR
+pub fn source_from_env() -> Result<Arc<dyn PriceSource>, PriceError> {
+    let base_url = env::var("PRICE_API_URL").ok().filter(|s| !s.is_empty());
+
+    match env::var("PRICE_SOURCE").unwrap_or_else(|_| "ree".to_string()).as_str() {
+        "esios" => {
+            let token = env::var("PRICE_API_TOKEN")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .ok_or_else(|| PriceError::Config("PRICE_API_TOKEN must be set for ESIOS".to_string()))?;
+            Ok(Arc::new(EsiosSource::new(base_url, token)))
+        }
+        "file" => {
+            let dir = env::var("PRICE_FILES_DIR").unwrap_or_else(|_| "./prices".to_string());
+            Ok(Arc::new(FileSource::new(dir)))
+        }
+        "ree" => Ok(Arc::new(ReeSource::new(base_url))),
+        other => Err(PriceError::Config(format!("unknown PRICE_SOURCE: {}", other))),
+    }
+}
+
+// Desa (o substitueix) els preus d'un dia per a una corba
+pub async fn store_day_prices(
+    pool: &DbPool,
+    date: NaiveDate,
+    series: PriceSeries,
+    prices: Vec<PricePeriod>,
+    source: &str,
+) -> Result<DayPrice, PriceError> {
+    let conn = pool
+        .get()
+        .await
+        .map_err(|e| PriceError::Database(e.to_string()))?;
+
+    let new_day_price = NewDayPrice {
+        id: Uuid::new_v4(),
+        date,
+        timezone: PRICE_TIMEZONE.name().to_string(),
+        prices_json: serde_json::to_value(&prices).map_err(|e| PriceError::Parse(e.to_string()))?,
+        source: source.to_string(),
+        resolution_minutes: prices.first().map_or(60, |p| p.minutes as i32),
+        tariff: series.as_str().to_string(),
+    };
+
+    conn.interact(move |conn| {
+        diesel::insert_into(day_prices::table)
+            .values(&new_day_price)
+            .on_conflict((day_prices::date, day_prices::timezone, day_prices::tariff))
+            .do_update()
+            .set((
+                day_prices::prices_json.eq(excluded(day_prices::prices_json)),
+                day_prices::source.eq(excluded(day_prices::source)),
+                day_prices::resolution_minutes.eq(excluded(day_prices::resolution_minutes)),
+            ))
+            .get_result::<DayPrice>(conn)
+    })
+    .await
+    .map_err(|e| PriceError::Database(e.to_string()))?
+    .map_err(|e| PriceError::Database(e.to_string()))
+}
+
+pub async fn has_day_prices(
+    pool: &DbPool,
+    date: NaiveDate,
+    series: PriceSeries,
+) -> Result<bool, PriceError> {
+    let conn = pool
+        .get()
+        .await
+        .map_err(|e| PriceError::Database(e.to_string()))?;
+
+    let count = conn
+        .interact(move |conn| {
+            day_prices::table
+                .filter(day_prices::date.eq(date))
+                .filter(day_prices::timezone.eq(PRICE_TIMEZONE.name()))
+                .filter(day_prices::tariff.eq(series.as_str()))
+                .count()
+                .get_result::<i64>(conn)
+        })
+        .await
+        .map_err(|e| PriceError::Database(e.to_string()))?
+        .map_err(|e| PriceError::Database(e.to_string()))?;
+
+    Ok(count > 0)
+}
+
+pub async fn fetch_and_store(
+    pool: &DbPool,
+    source: &dyn PriceSource,
+    series: PriceSeries,
+    date: NaiveDate,
+) -> Result<DayPrice, PriceError> {
+    let prices = source.fetch_day(date, series).await?;
+    let count = prices.len();
+    let day_price = store_day_prices(pool, date, series, prices, source.name()).await?;
+    log::info!(
+        "Stored {} {} prices for {} from {}",
+        count,
+        series.as_str(),
+        date,
+        source.name()
+    );
+    Ok(day_price)
+}
+
+// Reintenta amb espera exponencial (5, 10, 20... minuts)
+async fn fetch_with_retries(
+    pool: &DbPool,
+    source: &dyn PriceSource,
+    series: PriceSeries,
+    date: NaiveDate,
+    max_retries: u32,
+    retry_delay: Duration,
+) -> Result<DayPrice, PriceError> {
+    let mut attempt = 0;
+    loop {
+        match fetch_and_store(pool, source, series, date).await {
+            Ok(day_price) => return Ok(day_price),
+            Err(e) if attempt < max_retries => {
+                let delay = retry_delay * 2i32.pow(attempt);
+                log::warn!(
+                    "Failed to fetch {} prices for {} (attempt {}): {}. Retrying in {} minutes",
+                    series.as_str(),
+                    date,
+                    attempt + 1,
+                    e,
+                    delay.num_minutes()
+                );
+                tokio::time::sleep(delay.to_std().unwrap_or_default()).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Tasca en segon pla: recupera els dies que falten en arrencar i després
+// descarrega els preus de l'endemà de cada corba cada dia segons PRICE_FETCH_CRON
+pub async fn run_price_scheduler(
+    pool: DbPool,
+    source: Arc<dyn PriceSource>,
+    hub: Arc<WsHub>,
+    cache: Arc<PriceCache>,
+) {
+    let cron_expr = env::var("PRICE_FETCH_CRON").unwrap_or_else(|_| DEFAULT_FETCH_CRON.to_string());
+    let schedule = cron::Schedule::from_str(&cron_expr).expect("Invalid PRICE_FETCH_CRON");
+    let max_retries = env::var("PRICE_FETCH_RETRIES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_RETRIES);
+    let retry_delay = Duration::minutes(DEFAULT_RETRY_DELAY_MINUTES);
+
+    // Recuperar avui (i demà si ja s'han publicat) si no són a la base de dades
+    let today = Utc::now().with_timezone(&PRICE_TIMEZONE).date_naive();
+    for series in PriceSeries::ALL {
+        for date in [today, today + Duration::days(1)] {
+            match has_day_prices(&pool, date, series).await {
+                Ok(true) => {}
+                Ok(false) => match fetch_and_store(&pool, source.as_ref(), series, date).await {
+                    Ok(_) => {
+                        cache.invalidate(series, date);
+                        hub.broadcast(&WsEvent::day_prices(date, series));
+                    }
+                    Err(e) => log::warn!("Initial {} price fetch for {} failed: {}", series.as_str(), date, e),
+                },
+                Err(e) => log::error!("Failed to check stored prices for {}: {}", date, e),
+            }
+        }
+    }
+
+    for next_run in schedule.upcoming(PRICE_TIMEZONE) {
+        let wait = (next_run.with_timezone(&Utc) - Utc::now()).to_std().unwrap_or_default();
+        log::info!("Next price fetch scheduled at {}", next_run);
+        tokio::time::sleep(wait).await;
+
+        let tomorrow = next_run.date_naive() + Duration::days(1);
+        let mut fetched = false;
+        for series in PriceSeries::ALL {
+            match fetch_with_retries(&pool, source.as_ref(), series, tomorrow, max_retries, retry_delay).await {
+                Ok(_) => {
+                    cache.invalidate(series, tomorrow);
+                    hub.broadcast(&WsEvent::day_prices(tomorrow, series));
+                    fetched = true;
+                }
+                Err(e) => log::error!(
+                    "Giving up fetching {} prices for {}: {}",
+                    series.as_str(),
+                    tomorrow,
+                    e
+                ),
+            }
+        }
+
+        // Amb els preus nous ja es poden planificar els horaris de demà, una sola vegada
+        // per a totes les sèries
+        if !fetched {
+            continue;
+        }
+        match schedule_builder::rebuild_all_schedules(&pool).await {
+            Ok(results) => {
+                for (user_id, result) in &results {
+                    hub.send_to_user(*user_id, &WsEvent::schedules_rebuilt(result));
+                }
+            }
+            Err(e) => log::error!("Failed to rebuild schedules after fetching prices: {}", e),
+        }
+        if let Err(e) = tasks::replan_open_tasks(&pool).await {
+            log::error!("Failed to replan tasks after fetching prices: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const FIXTURES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/prices");
+
+    fn fixture(path: &str) -> String {
+        std::fs::read_to_string(format!("{}/{}", FIXTURES_DIR, path)).expect("missing fixture")
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn hours(prices: &[PricePeriod]) -> Vec<u8> {
+        prices.iter().map(|p| p.hour).collect()
+    }
+
+    // Els períodes han d'anar seguits en UTC, sense forats ni repeticions
+    fn assert_contiguous(prices: &[PricePeriod]) {
+        for pair in prices.windows(2) {
+            assert_eq!(pair[1].start - pair[0].start, Duration::minutes(pair[0].minutes as i64));
+        }
+    }
+
+    #[test]
+    fn parses_ree_spring_forward_day_with_23_periods() {
+        let prices = parse_ree(date("2026-03-29"), &fixture("ree/2026-03-29.json"), PriceSeries::Pvpc).unwrap();
+
+        assert_eq!(prices.len(), 23);
+        assert_eq!(&hours(&prices)[..4], &[0, 1, 3, 4]);
+        assert!(prices.iter().all(|p| p.minutes == 60 && p.minute == 0));
+        assert_eq!(prices[0].start.to_rfc3339(), "2026-03-28T23:00:00+00:00");
+        assert_contiguous(&prices);
+    }
+
+    #[test]
+    fn parses_ree_fall_back_day_with_25_periods() {
+        let prices = parse_ree(date("2026-10-25"), &fixture("ree/2026-10-25.json"), PriceSeries::Pvpc).unwrap();
+
+        assert_eq!(prices.len(), 25);
+        assert_eq!(&hours(&prices)[..5], &[0, 1, 2, 2, 3]);
+        assert_eq!(prices[2].start.to_rfc3339(), "2026-10-25T00:00:00+00:00");
+        assert_eq!(prices[3].start.to_rfc3339(), "2026-10-25T01:00:00+00:00");
+        assert_contiguous(&prices);
+    }
+
+    #[test]
+    fn parses_ree_quarter_hour_day_with_96_periods() {
+        let prices = parse_ree(date("2026-10-19"), &fixture("ree/2026-10-19.json"), PriceSeries::Pvpc).unwrap();
+
+        assert_eq!(prices.len(), 96);
+        assert!(prices.iter().all(|p| p.minutes == 15));
+        let labels: Vec<(u8, u8)> = prices[..5].iter().map(|p| (p.hour, p.minute)).collect();
+        assert_eq!(labels, vec![(0, 0), (0, 15), (0, 30), (0, 45), (1, 0)]);
+        assert_eq!(prices[0].start.to_rfc3339(), "2026-10-18T22:00:00+00:00");
+        assert_eq!(prices[95].start.to_rfc3339(), "2026-10-19T21:45:00+00:00");
+        assert_eq!(prices[1].price, Decimal::new(1695, 4));
+        assert_contiguous(&prices);
+    }
+
+    #[test]
+    fn converts_ree_prices_to_eur_per_kwh_for_each_series() {
+        let body = fixture("ree/2026-10-25.json");
+        let pvpc = parse_ree(date("2026-10-25"), &body, PriceSeries::Pvpc).unwrap();
+        let spot = parse_ree(date("2026-10-25"), &body, PriceSeries::Spot).unwrap();
+
+        assert_eq!(pvpc[0].price, Decimal::new(11, 2));
+        assert_eq!(pvpc[1].price, Decimal::new(1695, 4));
+        assert_eq!(spot[0].price, Decimal::new(6, 2));
+    }
+
+    #[test]
+    fn parses_esios_dst_days_for_the_requested_geo() {
+        let spring = parse_esios(date("2026-03-29"), &fixture("esios/2026-03-29.json"), ESIOS_PENINSULA_GEO_ID).unwrap();
+        let fall = parse_esios(date("2026-10-25"), &fixture("esios/2026-10-25.json"), ESIOS_PENINSULA_GEO_ID).unwrap();
+
+        assert_eq!(spring.len(), 23);
+        assert_eq!(fall.len(), 25);
+        assert_eq!(&hours(&fall)[..5], &[0, 1, 2, 2, 3]);
+        // La península comença a 110 €/MWh i Canàries a 90
+        assert_eq!(fall[0].price, Decimal::new(11, 2));
+        assert_contiguous(&fall);
+    }
+
+    #[test]
+    fn rejects_days_that_are_not_published_or_incomplete() {
+        let body = fixture("ree/2026-10-25.json");
+        assert!(matches!(
+            parse_ree(date("2026-10-26"), &body, PriceSeries::Pvpc),
+            Err(PriceError::NotPublished(_))
+        ));
+
+        let raw: Vec<RawPrice> = (0..12)
+            .map(|h| RawPrice {
+                datetime: DateTime::parse_from_rfc3339(&format!("2026-10-19T{:02}:00:00+02:00", h)).unwrap(),
+                value_mwh: Decimal::from(100),
+            })
+            .collect();
+        assert!(matches!(normalize_prices(date("2026-10-19"), raw), Err(PriceError::Parse(_))));
+    }
+
+    // `count` preus horaris de 100 €/MWh a partir de `first`
+    fn raw_hours(first: &str, count: i64) -> Vec<RawPrice> {
+        let first = DateTime::parse_from_rfc3339(first).unwrap();
+        (0..count)
+            .map(|h| RawPrice {
+                datetime: first + Duration::hours(h),
+                value_mwh: Decimal::from(100),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_series_must_match_the_length_of_the_day() {
+        // Dia normal de 24 hores amb una hora de menys
+        let short = raw_hours("2026-10-19T00:00:00+02:00", 23);
+        assert!(matches!(normalize_prices(date("2026-10-19"), short), Err(PriceError::Parse(_))));
+
+        // El dia de 25 hores no pot arribar amb només 24 valors
+        let mut fall = raw_hours("2026-10-25T00:00:00+02:00", 25);
+        assert_eq!(normalize_prices(date("2026-10-25"), fall.clone()).unwrap().len(), 25);
+        fall.remove(3);
+        assert!(matches!(normalize_prices(date("2026-10-25"), fall), Err(PriceError::Parse(_))));
+
+        // I el de 23 hores tampoc amb 22
+        let mut spring = raw_hours("2026-03-29T00:00:00+01:00", 23);
+        assert_eq!(normalize_prices(date("2026-03-29"), spring.clone()).unwrap().len(), 23);
+        spring.pop();
+        assert!(matches!(normalize_prices(date("2026-03-29"), spring), Err(PriceError::Parse(_))));
+    }
+
+    #[test]
+    fn the_series_must_be_contiguous_from_midnight() {
+        // Tantes hores com el dia, però començant a la 01:00 i acabant l'endemà
+        let late = raw_hours("2026-10-19T01:00:00+02:00", 24);
+        assert!(matches!(normalize_prices(date("2026-10-19"), late), Err(PriceError::Parse(_))));
+
+        // Una hora desplaçada deixa un forat encara que el nombre de valors quadri
+        let mut gappy = raw_hours("2026-10-19T00:00:00+02:00", 24);
+        gappy[10].datetime += Duration::minutes(30);
+        let err = normalize_prices(date("2026-10-19"), gappy).unwrap_err();
+        assert!(err.to_string().contains("10:00"), "{}", err);
+
+        // Quarts d'hora amb un quart de menys al mig
+        let mut quarters: Vec<RawPrice> = (0..96)
+            .map(|q| RawPrice {
+                datetime: DateTime::parse_from_rfc3339("2026-10-19T00:00:00+02:00").unwrap()
+                    + Duration::minutes(q * 15),
+                value_mwh: Decimal::from(100),
+            })
+            .collect();
+        assert_eq!(normalize_prices(date("2026-10-19"), quarters.clone()).unwrap().len(), 96);
+        quarters.remove(40);
+        assert!(matches!(normalize_prices(date("2026-10-19"), quarters), Err(PriceError::Parse(_))));
+    }
+
+    #[tokio::test]
+    async fn file_source_reads_ree_fixtures() {
+        let source = FileSource::new(format!("{}/ree", FIXTURES_DIR));
+
+        let prices = source.fetch_day(date("2026-03-29"), PriceSeries::Pvpc).await.unwrap();
+        assert_eq!(prices.len(), 23);
+        assert!(matches!(
+            source.fetch_day(date("2026-01-01"), PriceSeries::Pvpc).await,
+            Err(PriceError::NotPublished(_))
+        ));
+    }
+
+    // Servidor HTTP local que respon una sola petició amb `status` i `body`.
+    // Retorna l'URL base i la línia de la petició rebuda.
+    async fn mock_server(status: &'static str, body: String) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let read = socket.read(&mut buf).await.unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..read]);
+            }
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request).lines().next().unwrap_or_default().to_string()
+        });
+
+        (url, handle)
+    }
+
+    #[tokio::test]
+    async fn ree_source_fetches_from_the_configured_url() {
+        let (url, server) = mock_server("200 OK", fixture("ree/2026-10-25.json")).await;
+        let source = ReeSource::new(Some(url));
+
+        let prices = source.fetch_day(date("2026-10-25"), PriceSeries::Pvpc).await.unwrap();
+        let request_line = server.await.unwrap();
+
+        assert_eq!(prices.len(), 25);
+        assert!(request_line.starts_with("GET /es/datos/mercados/precios-mercados-tiempo-real?"));
+        assert!(request_line.contains("start_date=2026-10-25T00%3A00"));
+        // Es demana la resolució nativa, no la mitjana horària
+        assert!(!request_line.contains("time_trunc"));
+    }
+
+    #[tokio::test]
+    async fn esios_source_maps_missing_days_to_not_published() {
+        let (url, server) = mock_server("404 Not Found", String::new()).await;
+        let source = EsiosSource::new(Some(url), "token".to_string());
+
+        let result = source.fetch_day(date("2026-10-25"), PriceSeries::Pvpc).await;
+        let request_line = server.await.unwrap();
+
+        assert!(matches!(result, Err(PriceError::NotPublished(_))));
+        assert!(request_line.starts_with("GET /indicators/1001?"));
+    }
+}
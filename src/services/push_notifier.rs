@@ -0,0 +1,408 @@
+use crate::{schema::mobile_sessions, DbPool};
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use futures_util::future::BoxFuture;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+use uuid::Uuid;
+
+// Sessions sense heartbeat durant més temps no reben notificacions
+const SESSION_MAX_AGE_DAYS: i64 = 30;
+
+// FCM accepta com a molt 1000 tokens per missatge multicast
+const FCM_MAX_TOKENS: usize = 1000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PushError {
+    #[error("FCM error: {0}")]
+    Fcm(String),
+    #[error("Database error: {0}")]
+    Database(String),
+}
+
+// Missatge de dades (sense notificació visible) que desperta l'app
+pub type PushData = BTreeMap<String, String>;
+
+// Tipus de missatge. Cada tipus té la seva collapse key, de manera que FCM només
+// substitueix missatges pendents del mateix tipus, i el seu temps de vida.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushKind {
+    Command,
+    PriceAlert,
+}
+
+impl PushKind {
+    pub fn collapse_key(&self) -> &'static str {
+        match self {
+            PushKind::Command => "commands",
+            PushKind::PriceAlert => "price_alerts",
+        }
+    }
+
+    // Segons. Una comanda caduca als 5 minuts; un avís de preu val fins a una hora.
+    pub fn time_to_live(&self) -> i32 {
+        match self {
+            PushKind::Command => 300,
+            PushKind::PriceAlert => 3600,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PushReport {
+    pub delivered: usize,
+    // Tokens que FCM considera invàlids i que s'han d'esborrar
+    pub invalid_tokens: Vec<String>,
+}
+
+pub trait PushNotifier: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn send<'a>(
+        &'a self,
+        tokens: &'a [String],
+        kind: PushKind,
+        data: &'a PushData,
+    ) -> BoxFuture<'a, Result<PushReport, PushError>>;
+}
+
+// Client FCM amb la clau de servidor (API HTTP legacy)
+pub struct FcmNotifier {
+    client: fcm::Client,
+    server_key: String,
+}
+
+impl FcmNotifier {
+    pub fn new(server_key: String) -> Self {
+        Self {
+            client: fcm::Client::new(),
+            server_key,
+        }
+    }
+}
+
+// Missatge de dades d'alta prioritat amb la collapse key i el temps de vida del tipus
+fn build_message<'a>(
+    server_key: &'a str,
+    tokens: &'a [String],
+    kind: PushKind,
+    data: &PushData,
+) -> Result<fcm::Message<'a>, PushError> {
+    let mut builder = fcm::MessageBuilder::new_multi(server_key, tokens);
+    builder
+        .data(data)
+        .map_err(|e| PushError::Fcm(e.to_string()))?
+        .priority(fcm::Priority::High)
+        .collapse_key(kind.collapse_key())
+        .time_to_live(kind.time_to_live());
+    Ok(builder.finalize())
+}
+
+impl PushNotifier for FcmNotifier {
+    fn name(&self) -> &'static str {
+        "fcm"
+    }
+
+    fn send<'a>(
+        &'a self,
+        tokens: &'a [String],
+        kind: PushKind,
+        data: &'a PushData,
+    ) -> BoxFuture<'a, Result<PushReport, PushError>> {
+        Box::pin(async move {
+            let mut report = PushReport::default();
+
+            for chunk in tokens.chunks(FCM_MAX_TOKENS) {
+                let message = build_message(&self.server_key, chunk, kind, data)?;
+                let response = self
+                    .client
+                    .send(message)
+                    .await
+                    .map_err(|e| PushError::Fcm(e.to_string()))?;
+
+                if let Some(error) = response.error {
+                    return Err(PushError::Fcm(format!("{:?}", error)));
+                }
+
+                // Els resultats venen en el mateix ordre que els tokens
+                for (token, result) in chunk.iter().zip(response.results.unwrap_or_default()) {
+                    match result.error {
+                        None => report.delivered += 1,
+                        Some(
+                            fcm::ErrorReason::NotRegistered
+                            | fcm::ErrorReason::InvalidRegistration
+                            | fcm::ErrorReason::MismatchSenderId,
+                        ) => report.invalid_tokens.push(token.clone()),
+                        Some(error) => {
+                            log::warn!("FCM rejected message for a token: {:?}", error)
+                        }
+                    }
+                }
+            }
+
+            Ok(report)
+        })
+    }
+}
+
+// Implementació en memòria: no envia res, només guarda els missatges.
+// S'utilitza quan no hi ha clau FCM configurada i a les proves.
+#[derive(Default)]
+pub struct InMemoryNotifier {
+    sent: Mutex<Vec<(Vec<String>, PushKind, PushData)>>,
+    invalid_tokens: Vec<String>,
+}
+
+impl InMemoryNotifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Tokens que el fals servidor reportarà com a invàlids
+    #[cfg(test)]
+    pub fn with_invalid_tokens(invalid_tokens: Vec<String>) -> Self {
+        Self {
+            invalid_tokens,
+            ..Self::default()
+        }
+    }
+
+    #[cfg(test)]
+    pub fn sent(&self) -> Vec<(Vec<String>, PushKind, PushData)> {
+        self.sent.lock().map(|sent| sent.clone()).unwrap_or_default()
+    }
+}
+
+impl PushNotifier for InMemoryNotifier {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn send<'a>(
+        &'a self,
+        tokens: &'a [String],
+        kind: PushKind,
+        data: &'a PushData,
+    ) -> BoxFuture<'a, Result<PushReport, PushError>> {
+        Box::pin(async move {
+            log::debug!("Push {:?} to {} tokens (not sent): {:?}", kind, tokens.len(), data);
+
+            if let Ok(mut sent) = self.sent.lock() {
+                sent.push((tokens.to_vec(), kind, data.clone()));
+            }
+
+            let (invalid, valid): (Vec<String>, Vec<String>) = tokens
+                .iter()
+                .cloned()
+                .partition(|t| self.invalid_tokens.contains(t));
+
+            Ok(PushReport {
+                delivered: valid.len(),
+                invalid_tokens: invalid,
+            })
+        })
+    }
+}
+
+pub fn notifier_from_key(server_key: &str) -> Arc<dyn PushNotifier> {
+    if server_key.is_empty() {
+        log::warn!("FCM_SERVER_KEY not set, push notifications are disabled");
+        Arc::new(InMemoryNotifier::new())
+    } else {
+        Arc::new(FcmNotifier::new(server_key.to_string()))
+    }
+}
+
+// Envia un missatge a totes les sessions mòbils actives de l'usuari i esborra les
+// sessions amb tokens invàlids
+pub async fn notify_user(
+    pool: &DbPool,
+    notifier: &dyn PushNotifier,
+    user_id: Uuid,
+    kind: PushKind,
+    data: PushData,
+) -> Result<PushReport, PushError> {
+    let conn = pool
+        .get()
+        .await
+        .map_err(|e| PushError::Database(e.to_string()))?;
+
+    let since = Utc::now() - Duration::days(SESSION_MAX_AGE_DAYS);
+    let tokens = conn
+        .interact(move |conn| {
+            mobile_sessions::table
+                .filter(mobile_sessions::user_id.eq(user_id))
+                .filter(mobile_sessions::last_heartbeat.ge(since))
+                .select(mobile_sessions::device_token)
+                .distinct()
+                .load::<String>(conn)
+        })
+        .await
+        .map_err(|e| PushError::Database(e.to_string()))?
+        .map_err(|e| PushError::Database(e.to_string()))?;
+
+    if tokens.is_empty() {
+        return Ok(PushReport::default());
+    }
+
+    let report = notifier.send(&tokens, kind, &data).await?;
+
+    if !report.invalid_tokens.is_empty() {
+        let invalid = report.invalid_tokens.clone();
+        let removed = conn
+            .interact(move |conn| {
+                diesel::delete(
+                    mobile_sessions::table
+                        .filter(mobile_sessions::user_id.eq(user_id))
+                        .filter(mobile_sessions::device_token.eq_any(invalid)),
+                )
+                .execute(conn)
+            })
+            .await
+            .map_err(|e| PushError::Database(e.to_string()))?
+            .map_err(|e| PushError::Database(e.to_string()))?;
+
+        log::info!("Removed {} mobile sessions with invalid push tokens for user {}", removed, user_id);
+    }
+
+    Ok(report)
+}
+
+// Avisa l'app que té una comanda pendent. Els errors només es registren:
+// l'app igualment rebrà la comanda al següent heartbeat.
+pub async fn notify_command_queued(
+    pool: &DbPool,
+    notifier: &dyn PushNotifier,
+    user_id: Uuid,
+    command_id: Uuid,
+    device_id: Uuid,
+) {
+    let data = PushData::from([
+        ("type".to_string(), "command_queued".to_string()),
+        ("command_id".to_string(), command_id.to_string()),
+        ("device_id".to_string(), device_id.to_string()),
+    ]);
+
+    match notify_user(pool, notifier, user_id, PushKind::Command, data).await {
+        Ok(report) => log::debug!(
+            "Push for command {} delivered to {} sessions via {}",
+            command_id,
+            report.delivered,
+            notifier.name()
+        ),
+        Err(e) => log::warn!("Failed to push command {} to user {}: {}", command_id, user_id, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::{NewMobileSession, NewUser};
+    use crate::schema::users;
+    use deadpool_diesel::postgres::{Manager, Pool};
+    use diesel_migrations::MigrationHarness;
+
+    // Base de dades de proves: `TEST_DATABASE_URL=... cargo test -- --ignored`
+    async fn test_pool() -> DbPool {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+        let pool = Pool::builder(Manager::new(url, deadpool_diesel::Runtime::Tokio1))
+            .max_size(2)
+            .build()
+            .expect("Failed to create database pool");
+        let conn = pool.get().await.expect("Failed to get DB connection");
+        conn.interact(|conn| conn.run_pending_migrations(crate::MIGRATIONS).map(|_| ()))
+            .await
+            .expect("Failed to interact with connection")
+            .expect("Failed to run migrations");
+        pool
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn invalid_tokens_are_pruned_from_mobile_sessions() {
+        let pool = test_pool().await;
+        let conn = pool.get().await.unwrap();
+        let user_id = Uuid::new_v4();
+
+        conn.interact(move |conn| {
+            diesel::insert_into(users::table)
+                .values(&NewUser {
+                    id: user_id,
+                    google_sub: format!("test-{}", user_id),
+                    email: "push@example.com".to_string(),
+                    name: "Push".to_string(),
+                    picture: None,
+                })
+                .execute(conn)?;
+            for token in ["good-token", "stale-token"] {
+                diesel::insert_into(mobile_sessions::table)
+                    .values(&NewMobileSession {
+                        id: Uuid::new_v4(),
+                        user_id,
+                        device_token: token.to_string(),
+                        platform: "android".to_string(),
+                        app_version: "1.0.0".to_string(),
+                    })
+                    .execute(conn)?;
+            }
+            Ok::<_, diesel::result::Error>(())
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+        let notifier = InMemoryNotifier::with_invalid_tokens(vec!["stale-token".to_string()]);
+        let data = PushData::from([("type".to_string(), "command_queued".to_string())]);
+        let report = notify_user(&pool, &notifier, user_id, PushKind::Command, data.clone())
+            .await
+            .unwrap();
+
+        let remaining = conn
+            .interact(move |conn| {
+                let remaining = mobile_sessions::table
+                    .filter(mobile_sessions::user_id.eq(user_id))
+                    .select(mobile_sessions::device_token)
+                    .load::<String>(conn);
+                diesel::delete(users::table.find(user_id)).execute(conn)?;
+                remaining
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(report.delivered, 1);
+        assert_eq!(report.invalid_tokens, vec!["stale-token".to_string()]);
+        assert_eq!(remaining, vec!["good-token".to_string()]);
+
+        let sent = notifier.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].1, PushKind::Command);
+        assert_eq!(sent[0].2, data);
+        assert_eq!(sent[0].0.len(), 2);
+    }
+
+    #[test]
+    fn fcm_messages_carry_the_collapse_key_and_ttl_of_their_kind() {
+        let tokens = vec!["token-a".to_string(), "token-b".to_string()];
+        let data = PushData::from([("type".to_string(), "price_alert".to_string())]);
+
+        let body = |kind: PushKind| {
+            let message = build_message("server-key", &tokens, kind, &data).unwrap();
+            assert_eq!(message.api_key, "server-key");
+            serde_json::to_value(&message.body).unwrap()
+        };
+
+        let command = body(PushKind::Command);
+        assert_eq!(command["collapse_key"], "commands");
+        assert_eq!(command["time_to_live"], 300);
+        assert_eq!(command["priority"], "high");
+        assert_eq!(command["registration_ids"], serde_json::json!(["token-a", "token-b"]));
+        assert_eq!(command["data"], serde_json::json!({ "type": "price_alert" }));
+
+        let alert = body(PushKind::PriceAlert);
+        assert_eq!(alert["collapse_key"], "price_alerts");
+        assert_eq!(alert["time_to_live"], 3600);
+    }
+}
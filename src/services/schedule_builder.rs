@@ -0,0 +1,553 @@
+use crate::{
+    models::{
+        rule::{DeadlineEnergyParams, Rule, RuleApplicability, RuleType},
+        schedule::{DayPrice, PricePeriod, NewSchedule, Schedule},
+        tariff::{PriceSeries, Tariff},
+    },
+    schema::{day_prices, rules, schedules},
+    services::{
+        optimizer::{self, OptimizedSchedule, OptimizerError},
+        price_fetcher::PRICE_TIMEZONE,
+        tariffs,
+    },
+    DbPool,
+};
+use chrono::{Duration, NaiveDate, Timelike, Utc};
+use chrono_tz::Tz;
+use diesel::dsl::case_when;
+use diesel::prelude::*;
+use diesel::sql_types::{Integer, Nullable};
+use diesel::upsert::excluded;
+use serde::Serialize;
+use std::collections::{hash_map::Entry, BTreeSet, HashMap};
+use uuid::Uuid;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScheduleError {
+    #[error("Database error: {0}")]
+    Database(String),
+}
+
+// Regla que no s'ha pogut planificar per a una data
+#[derive(Debug, Clone, Serialize)]
+pub struct RebuildFailure {
+    pub rule_id: Uuid,
+    pub device_id: Uuid,
+    pub date: NaiveDate,
+    pub error: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct RebuildResult {
+    pub schedules: Vec<Schedule>,
+    pub failures: Vec<RebuildFailure>,
+}
+
+// Dispositiu i data d'un horari
+type DeviceDay = (Uuid, NaiveDate);
+
+// Preus de la tarifa de l'usuari per dia i zona horària (None si no estan publicats)
+type DayPricesCache = HashMap<(NaiveDate, Tz), Option<Vec<PricePeriod>>>;
+
+pub fn rule_timezone(rule: &Rule) -> Tz {
+    rule.timezone.parse().unwrap_or(PRICE_TIMEZONE)
+}
+
+pub fn load_day_price(
+    conn: &mut PgConnection,
+    series: PriceSeries,
+    date: NaiveDate,
+) -> Result<Option<DayPrice>, diesel::result::Error> {
+    day_prices::table
+        .filter(day_prices::date.eq(date))
+        .filter(day_prices::timezone.eq(PRICE_TIMEZONE.name()))
+        .filter(day_prices::tariff.eq(series.as_str()))
+        .first::<DayPrice>(conn)
+        .optional()
+}
+
+// Preus d'un dia natural en la zona horària `tz`, amb els dies peninsulars d'una
+// corba publicada obtinguts de `load`. Els preus es publiquen per dies peninsulars,
+// així que per a altres zones (p.ex. Atlantic/Canary, una hora menys) es reagrupen
+// segons l'instant UTC de cada hora. Cal que el dia peninsular corresponent estigui
+// publicat; les hores locals que cauen en un dia peninsular encara no publicat
+// queden fora de l'horari.
+pub fn local_prices<E>(
+    date: NaiveDate,
+    tz: Tz,
+    mut load: impl FnMut(NaiveDate) -> Result<Option<Vec<PricePeriod>>, E>,
+) -> Result<Option<Vec<PricePeriod>>, E> {
+    if tz == PRICE_TIMEZONE {
+        return load(date);
+    }
+    if load(date)?.is_none() {
+        return Ok(None);
+    }
+
+    let mut local = Vec::new();
+    for price_date in [date - Duration::days(1), date, date + Duration::days(1)] {
+        for price in load(price_date)?.unwrap_or_default() {
+            let start = price.start.with_timezone(&tz);
+            if start.date_naive() == date {
+                local.push(PricePeriod {
+                    hour: start.hour() as u8,
+                    minute: start.minute() as u8,
+                    ..price
+                });
+            }
+        }
+    }
+
+    Ok(Some(local))
+}
+
+// Planifica la sessió de càrrega d'una regla DEADLINE_ENERGY que comença el dia
+// `date`, amb els preus d'aquell dia i del següent obtinguts de `load`
+pub fn plan_session<E>(
+    params: &DeadlineEnergyParams,
+    date: NaiveDate,
+    tz: Tz,
+    mut load: impl FnMut(NaiveDate) -> Result<Option<Vec<PricePeriod>>, E>,
+) -> Result<Result<OptimizedSchedule, OptimizerError>, E> {
+    let Some(session) = params.session(date, tz) else {
+        return Ok(Err(OptimizerError::InvalidParams(format!(
+            "invalid deadline '{}'",
+            params.deadline
+        ))));
+    };
+
+    let mut prices = Vec::new();
+    for date in [date, date + Duration::days(1)] {
+        match load(date)? {
+            Some(day) => prices.extend(day),
+            None => return Ok(Err(OptimizerError::NoPrices)),
+        }
+    }
+
+    Ok(optimizer::optimize_deadline_energy(&prices, params, session))
+}
+
+// Horari del dia `date` d'una regla DEADLINE_ENERGY: la part de la sessió d'ahir
+// que acaba avui i la de la sessió d'avui que cau abans de mitjanit. Les sessions
+// sense preus de l'endemà encara no es planifiquen (es faran quan es publiquin), i
+// només hi ha sessió els dies en què s'aplica la regla (el dia en què comença).
+fn plan_deadline_day<E>(
+    params: &DeadlineEnergyParams,
+    applicability: &RuleApplicability,
+    date: NaiveDate,
+    tz: Tz,
+    prices: &[PricePeriod],
+    mut load: impl FnMut(NaiveDate) -> Result<Option<Vec<PricePeriod>>, E>,
+) -> Result<Result<Option<OptimizedSchedule>, OptimizerError>, E> {
+    let mut sessions = Vec::new();
+    for start in [date - Duration::days(1), date] {
+        if !applicability.applies_on(start) {
+            continue;
+        }
+        match plan_session(params, start, tz, &mut load)? {
+            Ok(session) => sessions.push(session),
+            Err(OptimizerError::NoPrices) => {}
+            Err(e) => return Ok(Err(e)),
+        }
+    }
+
+    if sessions.is_empty() {
+        return Ok(Ok(None));
+    }
+    Ok(optimizer::clip_to_day(&sessions, prices, tz).map(Some))
+}
+
+// Preus de la tarifa per a un dia i zona, consultats una sola vegada per reconstrucció
+fn cached_prices(
+    conn: &mut PgConnection,
+    tariff: &Tariff,
+    cache: &mut DayPricesCache,
+    date: NaiveDate,
+    tz: Tz,
+) -> Result<Option<Vec<PricePeriod>>, diesel::result::Error> {
+    match cache.entry((date, tz)) {
+        Entry::Occupied(entry) => Ok(entry.get().clone()),
+        Entry::Vacant(entry) => Ok(entry
+            .insert(tariffs::load_tariff_prices(conn, tariff, date, tz)?)
+            .clone()),
+    }
+}
+
+// Recalcula els horaris d'avui i demà per a totes les regles actives de l'usuari.
+// Els horaris existents d'aquestes dates es substitueixen dins d'una transacció,
+// excepte els dels dispositius amb alguna regla que no s'ha pogut planificar.
+pub async fn rebuild_user_schedules(
+    pool: &DbPool,
+    user_id: Uuid,
+) -> Result<RebuildResult, ScheduleError> {
+    let conn = pool
+        .get()
+        .await
+        .map_err(|e| ScheduleError::Database(e.to_string()))?;
+
+    conn.interact(move |conn| rebuild_user_schedules_sync(conn, user_id))
+        .await
+        .map_err(|e| ScheduleError::Database(e.to_string()))?
+        .map_err(|e| ScheduleError::Database(e.to_string()))
+}
+
+fn rebuild_user_schedules_sync(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+) -> Result<RebuildResult, diesel::result::Error> {
+    // Les regles d'un mateix dispositiu es combinen; en ordre de prioritat perquè
+    // cada slot s'atribueixi a la regla de més prioritat que l'encén
+    let user_rules = rules::table
+        .filter(rules::user_id.eq(user_id))
+        .filter(rules::enabled.eq(true))
+        .order((rules::priority.desc(), rules::created_at.asc()))
+        .load::<Rule>(conn)?;
+    let tariff = tariffs::user_tariff(conn, user_id)?;
+
+    let mut prices_by_date = DayPricesCache::new();
+    let mut optimized_by_device: Vec<(DeviceDay, Vec<(&Rule, OptimizedSchedule)>)> = Vec::new();
+    let mut failures = Vec::new();
+
+    let today = Utc::now().with_timezone(&PRICE_TIMEZONE).date_naive();
+    let mut dates = BTreeSet::from([today, today + Duration::days(1)]);
+
+    for rule in &user_rules {
+        let tz = rule_timezone(rule);
+        let local_today = Utc::now().with_timezone(&tz).date_naive();
+
+        for date in [local_today, local_today + Duration::days(1)] {
+            dates.insert(date);
+
+            let mut fail = |error: String| {
+                failures.push(RebuildFailure {
+                    rule_id: rule.id,
+                    device_id: rule.device_id,
+                    date,
+                    error,
+                })
+            };
+
+            let rule_type = match rule.get_rule_type() {
+                Ok(rule_type) => rule_type,
+                Err(e) => {
+                    fail(e);
+                    continue;
+                }
+            };
+            let applicability = match rule.get_applicability() {
+                Ok(applicability) => applicability,
+                Err(e) => {
+                    fail(e);
+                    continue;
+                }
+            };
+
+            // Els dies en què no s'aplica la regla no tenen horari (les sessions de
+            // càrrega, en canvi, depenen del dia en què comencen)
+            if rule_type != RuleType::DeadlineEnergy && !applicability.applies_on(date) {
+                continue;
+            }
+
+            let prices = cached_prices(conn, &tariff, &mut prices_by_date, date, tz)?;
+            let Some(prices) = prices else {
+                fail(format!("Prices for {} are not available yet", date));
+                continue;
+            };
+
+            let optimized = match rule_type {
+                RuleType::DeadlineEnergy => {
+                    match serde_json::from_value::<DeadlineEnergyParams>(rule.params_json.clone()) {
+                        Ok(params) => plan_deadline_day(&params, &applicability, date, tz, &prices, |date| {
+                            cached_prices(conn, &tariff, &mut prices_by_date, date, tz)
+                        })?
+                        .transpose(),
+                        Err(e) => Some(Err(OptimizerError::InvalidParams(e.to_string()))),
+                    }
+                }
+                _ => Some(optimizer::optimize_rule(rule_type, &rule.params_json, &prices)),
+            };
+
+            match optimized {
+                None => {}
+                Some(Ok(optimized)) => {
+                    let key = (rule.device_id, date);
+                    match optimized_by_device.iter_mut().find(|(k, _)| *k == key) {
+                        Some((_, rules)) => rules.push((rule, optimized)),
+                        None => optimized_by_device.push((key, vec![(rule, optimized)])),
+                    }
+                }
+                Some(Err(e)) => fail(e.to_string()),
+            }
+        }
+    }
+
+    // Un horari per dispositiu i dia; la regla de més prioritat defineix el dia
+    let mut planned: Vec<NewSchedule> = Vec::new();
+    for ((device_id, date), optimized) in optimized_by_device {
+        let (primary, _) = optimized[0];
+        let tz = rule_timezone(primary);
+        let prices = prices_by_date
+            .get(&(date, tz))
+            .and_then(|prices| prices.as_deref())
+            .unwrap_or_default();
+        let by_rule: Vec<(Uuid, OptimizedSchedule)> = optimized
+            .into_iter()
+            .map(|(rule, schedule)| (rule.id, schedule))
+            .collect();
+        let Some(merged) = optimizer::merge_schedules(&by_rule, prices, tz) else {
+            continue;
+        };
+
+        planned.push(NewSchedule {
+            id: Uuid::new_v4(),
+            user_id,
+            device_id,
+            rule_id: primary.id,
+            date,
+            slots_json: serde_json::to_value(&merged.slots)
+                .unwrap_or_else(|_| serde_json::json!([])),
+            total_cost: merged.total_cost,
+            status: "pending".to_string(),
+        });
+    }
+
+    // Si alguna regla d'un dispositiu ha fallat (p.ex. sense preus de demà) l'horari
+    // nou seria incomplet: es conserva l'existent i només es crea si no n'hi havia cap
+    let failed: BTreeSet<DeviceDay> = failures.iter().map(|f| (f.device_id, f.date)).collect();
+    let (planned, partial): (Vec<NewSchedule>, Vec<NewSchedule>) = planned
+        .into_iter()
+        .partition(|s| !failed.contains(&(s.device_id, s.date)));
+
+    let schedules = conn.transaction(|conn| {
+        // Eliminar els horaris d'aquestes dates que ja no tenen cap regla
+        for &date in &dates {
+            let kept: Vec<Uuid> = planned
+                .iter()
+                .map(|s| (s.device_id, s.date))
+                .chain(failed.iter().copied())
+                .filter(|(_, d)| *d == date)
+                .map(|(device_id, _)| device_id)
+                .collect();
+            diesel::delete(
+                schedules::table
+                    .filter(schedules::user_id.eq(user_id))
+                    .filter(schedules::date.eq(date))
+                    .filter(schedules::device_id.ne_all(kept)),
+            )
+            .execute(conn)?;
+        }
+
+        let mut schedules = Vec::new();
+        if !partial.is_empty() {
+            schedules = diesel::insert_into(schedules::table)
+                .values(&partial)
+                .on_conflict((schedules::device_id, schedules::date))
+                .do_nothing()
+                .get_results::<Schedule>(conn)?;
+        }
+
+        if planned.is_empty() {
+            return Ok(schedules);
+        }
+
+        let slots_unchanged = schedules::slots_json.eq(excluded(schedules::slots_json));
+        let replaced = diesel::insert_into(schedules::table)
+            .values(&planned)
+            .on_conflict((schedules::device_id, schedules::date))
+            .do_update()
+            .set((
+                schedules::rule_id.eq(excluded(schedules::rule_id)),
+                schedules::slots_json.eq(excluded(schedules::slots_json)),
+                schedules::total_cost.eq(excluded(schedules::total_cost)),
+                // Si els slots no canvien es conserva el progrés de l'executor; si canvien,
+                // l'horari torna a pending i l'executor torna a aplicar el slot vigent
+                schedules::status.eq(case_when(slots_unchanged, schedules::status)
+                    .otherwise(excluded(schedules::status))),
+                schedules::last_fired_slot.eq(case_when(slots_unchanged, schedules::last_fired_slot)
+                    .otherwise(None::<i32>.into_sql::<Nullable<Integer>>())),
+                schedules::updated_at.eq(Utc::now()),
+            ))
+            .get_results::<Schedule>(conn)?;
+
+        schedules.extend(replaced);
+        Ok::<_, diesel::result::Error>(schedules)
+    })?;
+
+    Ok(RebuildResult {
+        schedules,
+        failures,
+    })
+}
+
+// Prepara l'eliminació d'una regla. L'horari combinat d'un dispositiu apunta a la regla
+// de més prioritat i `schedules.rule_id` és ON DELETE CASCADE: esborrar-la s'emportaria
+// els slots de les altres regles. Per això es replanifica sense la regla i els horaris
+// que encara hi apunten (dies passats o que no s'han pogut replanificar) passen a una
+// altra regla del dispositiu. Si no n'hi ha cap, el cascade els esborra amb la regla.
+pub fn detach_rule_schedules(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    rule: &Rule,
+) -> Result<RebuildResult, diesel::result::Error> {
+    conn.transaction(|conn| {
+        diesel::update(rules::table.find(rule.id))
+            .set(rules::enabled.eq(false))
+            .execute(conn)?;
+
+        let result = rebuild_user_schedules_sync(conn, user_id)?;
+
+        let successor = rules::table
+            .filter(rules::device_id.eq(rule.device_id))
+            .filter(rules::id.ne(rule.id))
+            .order((rules::enabled.desc(), rules::priority.desc(), rules::created_at.asc()))
+            .select(rules::id)
+            .first::<Uuid>(conn)
+            .optional()?;
+        if let Some(successor) = successor {
+            diesel::update(schedules::table.filter(schedules::rule_id.eq(rule.id)))
+                .set(schedules::rule_id.eq(successor))
+                .execute(conn)?;
+        }
+
+        Ok(result)
+    })
+}
+
+// Recalcula els horaris de tots els usuaris amb regles actives (p.ex. quan arriben preus nous)
+pub async fn rebuild_all_schedules(pool: &DbPool) -> Result<Vec<(Uuid, RebuildResult)>, ScheduleError> {
+    let conn = pool
+        .get()
+        .await
+        .map_err(|e| ScheduleError::Database(e.to_string()))?;
+
+    let user_ids = conn
+        .interact(|conn| {
+            rules::table
+                .filter(rules::enabled.eq(true))
+                .select(rules::user_id)
+                .distinct()
+                .load::<Uuid>(conn)
+        })
+        .await
+        .map_err(|e| ScheduleError::Database(e.to_string()))?
+        .map_err(|e| ScheduleError::Database(e.to_string()))?;
+
+    // Un usuari que falla no deixa sense horaris la resta
+    let mut results = Vec::with_capacity(user_ids.len());
+    for user_id in user_ids {
+        let result = match rebuild_user_schedules(pool, user_id).await {
+            Ok(result) => result,
+            Err(e) => {
+                log::error!("Failed to rebuild schedules for user {}: {}", user_id, e);
+                continue;
+            }
+        };
+        log::info!(
+            "Rebuilt {} schedules for user {} ({} failures)",
+            result.schedules.len(),
+            user_id,
+            result.failures.len()
+        );
+        results.push((user_id, result));
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Datelike, TimeZone};
+    use rust_decimal::Decimal;
+    use std::convert::Infallible;
+
+    const MADRID: Tz = chrono_tz::Europe::Madrid;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 10, day).unwrap()
+    }
+
+    // Preus horaris d'un dia d'octubre a Madrid: 100 €/MWh excepte les hores barates
+    fn day_prices(date: NaiveDate, cheap: &[(u32, u32)]) -> Vec<PricePeriod> {
+        let start = MADRID
+            .with_ymd_and_hms(date.year(), date.month(), date.day(), 0, 0, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+        (0..24)
+            .map(|hour| PricePeriod {
+                start: start + Duration::hours(hour as i64),
+                hour: hour as u8,
+                minute: 0,
+                minutes: 60,
+                price: Decimal::new(
+                    if cheap.contains(&(date.day(), hour)) { 10 } else { 100 },
+                    3,
+                ),
+            })
+            .collect()
+    }
+
+    // Una hora de càrrega abans de les 07:00
+    fn params() -> DeadlineEnergyParams {
+        DeadlineEnergyParams {
+            energy_kwh: Decimal::from(8),
+            charger_kw: Decimal::from(8),
+            deadline: "07:00".to_string(),
+            allowed_window: None,
+        }
+    }
+
+    fn on_slots(schedule: &OptimizedSchedule) -> Vec<(String, String)> {
+        schedule
+            .slots
+            .iter()
+            .filter(|s| s.is_on())
+            .map(|s| (s.start.clone(), s.end.clone()))
+            .collect()
+    }
+
+    fn slot(start: &str, end: &str) -> (String, String) {
+        (start.to_string(), end.to_string())
+    }
+
+    // Les 03:00 del 20 són de la sessió del 19; les 22:00 del 20, de la del mateix dia
+    const CHEAP: [(u32, u32); 2] = [(20, 3), (20, 22)];
+
+    fn plan(applicability: &RuleApplicability, published: &[u32]) -> Option<OptimizedSchedule> {
+        let today = day_prices(date(20), &CHEAP);
+        plan_deadline_day(&params(), applicability, date(20), MADRID, &today, |day| {
+            Ok::<_, Infallible>(published.contains(&day.day()).then(|| day_prices(day, &CHEAP)))
+        })
+        .unwrap_or_else(|e| match e {})
+        .unwrap()
+    }
+
+    #[test]
+    fn deadline_day_combines_yesterdays_and_todays_sessions() {
+        let schedule = plan(&RuleApplicability::default(), &[19, 20, 21]).unwrap();
+
+        assert_eq!(on_slots(&schedule), vec![slot("03:00", "04:00"), slot("22:00", "23:00")]);
+        assert_eq!(schedule.slots.first().unwrap().start, "00:00");
+        assert_eq!(schedule.slots.last().unwrap().end, "24:00");
+    }
+
+    #[test]
+    fn deadline_day_waits_for_the_prices_of_tomorrow() {
+        // Sense els preus del 21 la sessió d'avui encara no es planifica
+        let schedule = plan(&RuleApplicability::default(), &[19, 20]).unwrap();
+        assert_eq!(on_slots(&schedule), vec![slot("03:00", "04:00")]);
+
+        assert!(plan(&RuleApplicability::default(), &[20]).is_none());
+    }
+
+    #[test]
+    fn deadline_day_only_plans_sessions_starting_on_applicable_days() {
+        let not_on_the_19th = RuleApplicability {
+            exception_dates: vec![date(19)],
+            ..RuleApplicability::default()
+        };
+        let schedule = plan(&not_on_the_19th, &[19, 20, 21]).unwrap();
+
+        assert_eq!(on_slots(&schedule), vec![slot("22:00", "23:00")]);
+    }
+}
@@ -0,0 +1,363 @@
+use crate::{
+    models::{
+        command::{Command, CommandStatus, NewAutomationLog, NewCommand, OnOffPayload},
+        rule::{Rule, TimeWindow},
+        schedule::{Schedule, TimeSlot},
+    },
+    schema::{automation_logs, commands, rules, schedules},
+    services::{
+        command_processor,
+        push_notifier::PushNotifier,
+        schedule_builder::{rule_timezone, ScheduleError},
+        ws_hub::WsHub,
+    },
+    DbPool,
+};
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use diesel::prelude::*;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+const TICK_SECONDS: u64 = 30;
+
+// Tasca en segon pla: a cada límit de slot encua la comanda on/off corresponent
+pub async fn run_schedule_executor(pool: DbPool, notifier: Arc<dyn PushNotifier>, hub: Arc<WsHub>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(TICK_SECONDS));
+    loop {
+        interval.tick().await;
+        match execute_due_slots(&pool).await {
+            Ok(queued) => {
+                for command in &queued {
+                    command_processor::dispatch_command(&pool, notifier.as_ref(), hub.as_ref(), command)
+                        .await;
+                }
+            }
+            Err(e) => log::error!("Schedule executor failed: {}", e),
+        }
+    }
+}
+
+// Retorna les comandes encuades en aquesta passada
+pub async fn execute_due_slots(pool: &DbPool) -> Result<Vec<Command>, ScheduleError> {
+    let conn = pool
+        .get()
+        .await
+        .map_err(|e| ScheduleError::Database(e.to_string()))?;
+
+    conn.interact(|conn| {
+        let now = Utc::now();
+        let today = now.date_naive();
+
+        // Marge d'un dia per banda per cobrir qualsevol zona horària
+        let candidates = schedules::table
+            .inner_join(rules::table)
+            .filter(schedules::status.eq_any(["pending", "active"]))
+            .filter(schedules::date.between(today - Duration::days(1), today + Duration::days(1)))
+            .select((schedules::all_columns, rules::all_columns))
+            .load::<(Schedule, Rule)>(conn)?;
+
+        let mut queued = Vec::new();
+        for (schedule, rule) in candidates {
+            let schedule_id = schedule.id;
+            match process_schedule(conn, schedule, &rule, now) {
+                Ok(Some(command)) => queued.push(command),
+                Ok(None) => {}
+                Err(e) => log::error!("Failed to execute schedule {}: {}", schedule_id, e),
+            }
+        }
+
+        Ok::<_, diesel::result::Error>(queued)
+    })
+    .await
+    .map_err(|e| ScheduleError::Database(e.to_string()))?
+    .map_err(|e| ScheduleError::Database(e.to_string()))
+}
+
+// Instant UTC d'una hora local "HH:MM" del dia de l'horari ("24:00" és mitjanit del dia següent)
+fn slot_instant(date: NaiveDate, time: &str, tz: Tz) -> Option<DateTime<Utc>> {
+    let minutes = TimeWindow::parse_time(time).ok()?;
+    let local = date.and_hms_opt(0, 0, 0)? + Duration::minutes(minutes as i64);
+
+    // En el salt horari de primavera l'hora local no existeix: s'avança una hora
+    tz.from_local_datetime(&local)
+        .earliest()
+        .or_else(|| tz.from_local_datetime(&(local + Duration::hours(1))).earliest())
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+// Límits UTC de cada slot. Els slots nous porten els instants UTC; els antics es
+// resolen amb l'hora local.
+fn slot_bounds(date: NaiveDate, slots: &[TimeSlot], tz: Tz) -> Option<Vec<(DateTime<Utc>, DateTime<Utc>)>> {
+    slots
+        .iter()
+        .map(|s| {
+            Some((
+                s.start_at.or_else(|| slot_instant(date, &s.start, tz))?,
+                s.end_at.or_else(|| slot_instant(date, &s.end, tz))?,
+            ))
+        })
+        .collect()
+}
+
+// Final del slot de l'horari que estava vigent a `fired_at`, l'hora en què es va
+// disparar la comanda. None si l'horari ja no existeix o no té cap slot a aquella hora.
+pub fn fired_slot_end(
+    conn: &mut PgConnection,
+    schedule_id: Uuid,
+    fired_at: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>, diesel::result::Error> {
+    let Some((schedule, rule)) = schedules::table
+        .inner_join(rules::table)
+        .filter(schedules::id.eq(schedule_id))
+        .select((schedules::all_columns, rules::all_columns))
+        .first::<(Schedule, Rule)>(conn)
+        .optional()?
+    else {
+        return Ok(None);
+    };
+
+    let slots = schedule.get_slots().unwrap_or_default();
+    Ok(slot_bounds(schedule.date, &slots, rule_timezone(&rule))
+        .unwrap_or_default()
+        .into_iter()
+        .find(|(start, end)| *start <= fired_at && fired_at < *end)
+        .map(|(_, end)| end))
+}
+
+fn process_schedule(
+    conn: &mut PgConnection,
+    schedule: Schedule,
+    rule: &Rule,
+    now: DateTime<Utc>,
+) -> Result<Option<Command>, diesel::result::Error> {
+    let tz = rule_timezone(rule);
+
+    let Ok(slots) = schedule.get_slots() else {
+        return finish_schedule(conn, &schedule, "failed", "Invalid slots_json").map(|_| None);
+    };
+
+    let Some(bounds) = slot_bounds(schedule.date, &slots, tz) else {
+        return finish_schedule(conn, &schedule, "failed", "Invalid slot times").map(|_| None);
+    };
+
+    // L'horari ja s'ha acabat: tancar-lo quan totes les comandes s'hagin resolt
+    let day_end = bounds.last().map(|(_, end)| *end);
+    if day_end.is_none_or(|end| now >= end) {
+        return complete_schedule(conn, &schedule).map(|_| None);
+    }
+
+    // Slot vigent ara mateix
+    let Some(current) = bounds.iter().position(|(start, end)| *start <= now && now < *end) else {
+        return Ok(None);
+    };
+
+    if schedule.last_fired_slot.is_some_and(|fired| fired as usize >= current) {
+        return Ok(None);
+    }
+
+    // Si el servidor ha estat aturat només s'aplica el slot vigent, no els intermedis
+    fire_slot(conn, &schedule, rule, current, &slots[current])
+}
+
+fn fire_slot(
+    conn: &mut PgConnection,
+    schedule: &Schedule,
+    rule: &Rule,
+    index: usize,
+    slot: &TimeSlot,
+) -> Result<Option<Command>, diesel::result::Error> {
+    let index = index as i32;
+
+    conn.transaction(|conn| {
+        // L'actualització condicional garanteix que un slot només es dispara una vegada,
+        // encara que hi hagi reinicis o diverses instàncies
+        let claimed = diesel::update(
+            schedules::table
+                .find(schedule.id)
+                .filter(
+                    schedules::last_fired_slot
+                        .is_null()
+                        .or(schedules::last_fired_slot.lt(index)),
+                ),
+        )
+        .set((
+            schedules::last_fired_slot.eq(index),
+            schedules::status.eq("active"),
+            schedules::updated_at.eq(Utc::now()),
+        ))
+        .execute(conn)?;
+
+        if claimed == 0 {
+            return Ok(None);
+        }
+
+        let payload = OnOffPayload { on: slot.is_on() };
+        let new_command = NewCommand {
+            id: Uuid::new_v4(),
+            user_id: schedule.user_id,
+            device_id: schedule.device_id,
+            command_type: "on_off".to_string(),
+            payload_json: serde_json::to_value(&payload).unwrap_or_else(|_| json!({})),
+            status: CommandStatus::Queued.to_string(),
+            retry_count: 0,
+            schedule_id: Some(schedule.id),
+        };
+
+        let command = command_processor::insert_command(conn, &new_command, "Fired by schedule")?;
+
+        diesel::insert_into(automation_logs::table)
+            .values(&NewAutomationLog {
+                id: Uuid::new_v4(),
+                user_id: schedule.user_id,
+                device_id: Some(schedule.device_id),
+                rule_id: Some(slot.rule_id.unwrap_or(rule.id)),
+                action: "schedule_slot_fired".to_string(),
+                details_json: Some(json!({
+                    "schedule_id": schedule.id,
+                    "date": schedule.date,
+                    "slot_index": index,
+                    "slot": slot,
+                    "command_id": command.id,
+                })),
+            })
+            .execute(conn)?;
+
+        log::info!(
+            "Schedule {} fired slot {} ({}) for device {}",
+            schedule.id,
+            index,
+            slot.action,
+            schedule.device_id
+        );
+
+        Ok(Some(command))
+    })
+}
+
+// Passa l'horari a completed o failed segons el resultat de les seves comandes
+fn complete_schedule(conn: &mut PgConnection, schedule: &Schedule) -> Result<(), diesel::result::Error> {
+    if schedule.last_fired_slot.is_none() {
+        return finish_schedule(conn, schedule, "failed", "No slot was executed");
+    }
+
+    let statuses = commands::table
+        .filter(commands::schedule_id.eq(schedule.id))
+        .select(commands::status)
+        .load::<String>(conn)?;
+
+    let pending = [CommandStatus::Queued.to_string(), CommandStatus::Sent.to_string()];
+    if statuses.iter().any(|s| pending.contains(s)) {
+        return Ok(());
+    }
+
+    let unsuccessful = [
+        CommandStatus::Failed.to_string(),
+        CommandStatus::Expired.to_string(),
+        CommandStatus::Superseded.to_string(),
+    ];
+    if statuses.iter().any(|s| unsuccessful.contains(s)) {
+        finish_schedule(conn, schedule, "failed", "Some commands failed")
+    } else {
+        finish_schedule(conn, schedule, "completed", "All slots executed")
+    }
+}
+
+fn finish_schedule(
+    conn: &mut PgConnection,
+    schedule: &Schedule,
+    status: &str,
+    reason: &str,
+) -> Result<(), diesel::result::Error> {
+    conn.transaction(|conn| {
+        diesel::update(schedules::table.find(schedule.id))
+            .set((
+                schedules::status.eq(status),
+                schedules::updated_at.eq(Utc::now()),
+            ))
+            .execute(conn)?;
+
+        diesel::insert_into(automation_logs::table)
+            .values(&NewAutomationLog {
+                id: Uuid::new_v4(),
+                user_id: schedule.user_id,
+                device_id: Some(schedule.device_id),
+                rule_id: Some(schedule.rule_id),
+                action: format!("schedule_{}", status),
+                details_json: Some(json!({
+                    "schedule_id": schedule.id,
+                    "date": schedule.date,
+                    "reason": reason,
+                })),
+            })
+            .execute(conn)?;
+
+        log::info!("Schedule {} marked as {}: {}", schedule.id, status, reason);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MADRID: Tz = chrono_tz::Europe::Madrid;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    #[test]
+    fn spring_forward_day_lasts_23_hours() {
+        let day = date(2026, 3, 29);
+        let start = slot_instant(day, "00:00", MADRID).unwrap();
+        let end = slot_instant(day, "24:00", MADRID).unwrap();
+
+        assert_eq!(start, utc(2026, 3, 28, 23, 0));
+        assert_eq!(end, utc(2026, 3, 29, 22, 0));
+        assert_eq!(end - start, Duration::hours(23));
+    }
+
+    #[test]
+    fn skipped_02_00_moves_forward_to_03_00() {
+        let day = date(2026, 3, 29);
+
+        assert_eq!(slot_instant(day, "01:00", MADRID), Some(utc(2026, 3, 29, 0, 0)));
+        // Les 02:00 i les 02:30 no existeixen: són les 03:00 i les 03:30 d'estiu
+        assert_eq!(slot_instant(day, "02:00", MADRID), Some(utc(2026, 3, 29, 1, 0)));
+        assert_eq!(slot_instant(day, "02:30", MADRID), Some(utc(2026, 3, 29, 1, 30)));
+        assert_eq!(slot_instant(day, "03:00", MADRID), Some(utc(2026, 3, 29, 1, 0)));
+    }
+
+    #[test]
+    fn fall_back_day_lasts_25_hours() {
+        let day = date(2026, 10, 25);
+        let start = slot_instant(day, "00:00", MADRID).unwrap();
+        let end = slot_instant(day, "24:00", MADRID).unwrap();
+
+        assert_eq!(start, utc(2026, 10, 24, 22, 0));
+        assert_eq!(end, utc(2026, 10, 25, 23, 0));
+        assert_eq!(end - start, Duration::hours(25));
+    }
+
+    #[test]
+    fn repeated_02_00_resolves_to_the_first_occurrence() {
+        let day = date(2026, 10, 25);
+
+        // Les 02:00 d'estiu (00:00 UTC); la segona vegada, a les 01:00 UTC, ja és hivern
+        assert_eq!(slot_instant(day, "02:00", MADRID), Some(utc(2026, 10, 25, 0, 0)));
+        assert_eq!(slot_instant(day, "02:30", MADRID), Some(utc(2026, 10, 25, 0, 30)));
+        assert_eq!(slot_instant(day, "03:00", MADRID), Some(utc(2026, 10, 25, 2, 0)));
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        assert_eq!(slot_instant(date(2026, 10, 19), "25:00", MADRID), None);
+        assert_eq!(slot_instant(date(2026, 10, 19), "soon", MADRID), None);
+    }
+}
@@ -0,0 +1,34 @@
+use crate::{models::user::UserSettings, schema::user_settings};
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use uuid::Uuid;
+
+// Preferències de l'usuari, o els valors per defecte si encara no n'ha desat
+pub fn load_settings(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+) -> Result<UserSettings, diesel::result::Error> {
+    Ok(user_settings::table
+        .find(user_id)
+        .first::<UserSettings>(conn)
+        .optional()?
+        .unwrap_or_else(|| UserSettings::defaults(user_id)))
+}
+
+pub fn save_settings(
+    conn: &mut PgConnection,
+    settings: &UserSettings,
+) -> Result<UserSettings, diesel::result::Error> {
+    diesel::insert_into(user_settings::table)
+        .values(settings)
+        .on_conflict(user_settings::user_id)
+        .do_update()
+        .set((
+            user_settings::timezone.eq(excluded(user_settings::timezone)),
+            user_settings::language.eq(excluded(user_settings::language)),
+            user_settings::currency_display.eq(excluded(user_settings::currency_display)),
+            user_settings::updated_at.eq(Utc::now()),
+        ))
+        .get_result::<UserSettings>(conn)
+}
@@ -0,0 +1,182 @@
+use crate::{
+    models::{
+        schedule::PricePeriod,
+        tariff::{PriceSeries, Tariff, TouPeriod, UserTariff},
+    },
+    schema::user_tariffs,
+    services::schedule_builder,
+};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+// Tarifa desada de l'usuari, o el PVPC 2.0TD si encara no n'ha triat cap
+pub fn load_tariff(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+) -> Result<UserTariff, diesel::result::Error> {
+    Ok(user_tariffs::table
+        .find(user_id)
+        .first::<UserTariff>(conn)
+        .optional()?
+        .unwrap_or_else(|| UserTariff::defaults(user_id)))
+}
+
+pub fn save_tariff(
+    conn: &mut PgConnection,
+    tariff: &UserTariff,
+) -> Result<UserTariff, diesel::result::Error> {
+    diesel::insert_into(user_tariffs::table)
+        .values(tariff)
+        .on_conflict(user_tariffs::user_id)
+        .do_update()
+        .set((
+            user_tariffs::kind.eq(excluded(user_tariffs::kind)),
+            user_tariffs::params_json.eq(excluded(user_tariffs::params_json)),
+            user_tariffs::updated_at.eq(Utc::now()),
+        ))
+        .get_result::<UserTariff>(conn)
+}
+
+// Tarifa tipada de l'usuari. Si la desada no és vàlida s'aplica el PVPC.
+pub fn user_tariff(conn: &mut PgConnection, user_id: Uuid) -> Result<Tariff, diesel::result::Error> {
+    let stored = load_tariff(conn, user_id)?;
+    Ok(stored.tariff().unwrap_or_else(|e| {
+        log::warn!("Falling back to PVPC for user {}: {}", user_id, e);
+        Tariff::Pvpc
+    }))
+}
+
+// Preus de la tarifa per a un dia natural en la zona horària `tz`
+pub fn load_tariff_prices(
+    conn: &mut PgConnection,
+    tariff: &Tariff,
+    date: NaiveDate,
+    tz: Tz,
+) -> Result<Option<Vec<PricePeriod>>, diesel::result::Error> {
+    tariff_prices(tariff, date, tz, |series, date| {
+        schedule_builder::load_day_price(conn, series, date)
+            .map(|dp| dp.and_then(|dp| dp.get_prices().ok()))
+    })
+}
+
+// Igual que `load_tariff_prices`, amb les corbes publicades obtingudes de `load`.
+// Les tarifes de preus fixos no depenen de cap publicació i sempre tenen preus.
+pub fn tariff_prices<E>(
+    tariff: &Tariff,
+    date: NaiveDate,
+    tz: Tz,
+    mut load: impl FnMut(PriceSeries, NaiveDate) -> Result<Option<Vec<PricePeriod>>, E>,
+) -> Result<Option<Vec<PricePeriod>>, E> {
+    match tariff {
+        Tariff::Pvpc => schedule_builder::local_prices(date, tz, |date| load(PriceSeries::Pvpc, date)),
+        Tariff::OmieSpot(params) => {
+            let spot = schedule_builder::local_prices(date, tz, |date| load(PriceSeries::Spot, date))?;
+            Ok(spot.map(|prices| {
+                prices
+                    .into_iter()
+                    .map(|p| PricePeriod {
+                        price: params
+                            .price(p.price, p.start.with_timezone(&tz).naive_local())
+                            .round_dp(6),
+                        ..p
+                    })
+                    .collect()
+            }))
+        }
+        Tariff::ThreePeriod(params) => Ok(fixed_prices(date, tz, 60, |local| {
+            params.price(TouPeriod::at(local))
+        })),
+        Tariff::Custom(params) => Ok(fixed_prices(date, tz, params.resolution_minutes(), |local| {
+            params.price(local)
+        })),
+    }
+}
+
+// Corba d'un dia local a partir d'un preu per hora local. Els períodes es generen
+// per instants UTC, així que els dies de canvi horari tenen 23 o 25 hores.
+fn fixed_prices(
+    date: NaiveDate,
+    tz: Tz,
+    minutes: u32,
+    price_at: impl Fn(NaiveDateTime) -> Decimal,
+) -> Option<Vec<PricePeriod>> {
+    let day_start = |date: NaiveDate| -> Option<DateTime<Utc>> {
+        tz.from_local_datetime(&date.and_time(NaiveTime::MIN))
+            .earliest()
+            .map(|dt| dt.with_timezone(&Utc))
+    };
+    let start = day_start(date)?;
+    let end = day_start(date + Duration::days(1))?;
+
+    let step = Duration::minutes(minutes as i64);
+    let mut prices = Vec::new();
+    let mut instant = start;
+    while instant < end {
+        let local = instant.with_timezone(&tz);
+        prices.push(PricePeriod {
+            start: instant,
+            hour: local.hour() as u8,
+            minute: local.minute() as u8,
+            minutes,
+            price: price_at(local.naive_local()),
+        });
+        instant += step;
+    }
+
+    Some(prices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::Europe::Madrid;
+
+    fn hourly_prices(date: &str, minutes: u32) -> Vec<PricePeriod> {
+        let date = date.parse::<NaiveDate>().unwrap();
+        fixed_prices(date, Madrid, minutes, |local| Decimal::from(local.hour())).unwrap()
+    }
+
+    #[test]
+    fn fixed_prices_cover_a_regular_day_in_local_hours() {
+        let prices = hourly_prices("2026-10-19", 60);
+        assert_eq!(prices.len(), 24);
+        assert_eq!(prices[0].start, "2026-10-18T22:00:00Z".parse::<DateTime<Utc>>().unwrap());
+        assert!(prices.iter().enumerate().all(|(i, p)| p.hour as usize == i && p.price == Decimal::from(i)));
+    }
+
+    #[test]
+    fn fixed_prices_skip_the_missing_hour_on_the_23_hour_day() {
+        let prices = hourly_prices("2026-03-29", 60);
+        assert_eq!(prices.len(), 23);
+
+        let hours: Vec<u8> = prices.iter().map(|p| p.hour).collect();
+        assert_eq!(&hours[..4], &[0, 1, 3, 4]);
+        assert_eq!(prices[2].price, Decimal::from(3));
+    }
+
+    #[test]
+    fn fixed_prices_repeat_the_extra_hour_on_the_25_hour_day() {
+        let prices = hourly_prices("2026-10-25", 60);
+        assert_eq!(prices.len(), 25);
+
+        let hours: Vec<u8> = prices.iter().map(|p| p.hour).collect();
+        assert_eq!(&hours[..5], &[0, 1, 2, 2, 3]);
+        assert_eq!(prices[3].start - prices[2].start, Duration::hours(1));
+        assert_eq!(prices.last().unwrap().hour, 23);
+    }
+
+    #[test]
+    fn fixed_prices_use_quarter_hours_when_asked() {
+        let prices = hourly_prices("2026-10-25", 15);
+        assert_eq!(prices.len(), 25 * 4);
+        assert!(prices.iter().all(|p| p.minutes == 15));
+        assert_eq!(
+            prices[..4].iter().map(|p| p.minute).collect::<Vec<_>>(),
+            vec![0, 15, 30, 45]
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use crate::{
+    models::{
+        command::{Command, CommandStatus, NewAutomationLog, NewCommand, OnOffPayload},
+        schedule::PricePeriod,
+        task::{OneShotTask, TaskStatus},
+    },
+    schema::{automation_logs, one_shot_tasks},
+    services::{
+        command_processor, optimizer,
+        push_notifier::PushNotifier,
+        schedule_builder::ScheduleError,
+        settings, tariffs,
+        ws_hub::WsHub,
+    },
+    DbPool,
+};
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+const TICK_SECONDS: u64 = 30;
+
+// Preus de la tarifa de l'usuari dels dies locals entre `from` i `to` que ja estan publicats
+fn window_prices(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<PricePeriod>, diesel::result::Error> {
+    let tz = settings::load_settings(conn, user_id)?.tz();
+    let tariff = tariffs::user_tariff(conn, user_id)?;
+
+    let mut prices = Vec::new();
+    let mut date = from.with_timezone(&tz).date_naive();
+    while date <= to.with_timezone(&tz).date_naive() {
+        prices.extend(tariffs::load_tariff_prices(conn, &tariff, date, tz)?.unwrap_or_default());
+        date += Duration::days(1);
+    }
+
+    Ok(prices)
+}
+
+// Tria el bloc més barat amb els preus disponibles ara. Sense preus per a cap bloc
+// la tasca queda en espera. Les tasques que ja han començat no es toquen.
+pub fn plan_task(
+    conn: &mut PgConnection,
+    task: &OneShotTask,
+    now: DateTime<Utc>,
+) -> Result<OneShotTask, diesel::result::Error> {
+    let earliest = task.not_before.max(now);
+    let prices = window_prices(conn, task.user_id, earliest, task.deadline)?;
+    let block = optimizer::cheapest_block(&prices, task.duration_minutes as u32, earliest, task.deadline);
+
+    let status = match block {
+        Some(_) => TaskStatus::Scheduled,
+        None => TaskStatus::Waiting,
+    };
+    let planned = diesel::update(
+        one_shot_tasks::table
+            .find(task.id)
+            .filter(one_shot_tasks::status.eq_any(TaskStatus::OPEN.map(|s| s.as_str()))),
+    )
+    .set((
+        one_shot_tasks::status.eq(status.as_str()),
+        one_shot_tasks::planned_start.eq(block.map(|(start, _)| start)),
+        one_shot_tasks::planned_cost.eq(block.map(|(_, cost)| cost)),
+        one_shot_tasks::updated_at.eq(Utc::now()),
+    ))
+    .get_result::<OneShotTask>(conn)
+    .optional()?;
+
+    match planned {
+        Some(planned) => Ok(planned),
+        None => one_shot_tasks::table.find(task.id).first::<OneShotTask>(conn),
+    }
+}
+
+// Torna a planificar les tasques pendents (p.ex. quan es publiquen els preus de demà)
+pub async fn replan_open_tasks(pool: &DbPool) -> Result<usize, ScheduleError> {
+    let conn = pool
+        .get()
+        .await
+        .map_err(|e| ScheduleError::Database(e.to_string()))?;
+
+    conn.interact(|conn| {
+        let now = Utc::now();
+        let open = one_shot_tasks::table
+            .filter(one_shot_tasks::status.eq_any(TaskStatus::OPEN.map(|s| s.as_str())))
+            .load::<OneShotTask>(conn)?;
+
+        let mut replanned = 0;
+        for task in open.iter().filter(|t| now <= t.latest_start()) {
+            let planned = plan_task(conn, task, now)?;
+            if planned.planned_start != task.planned_start {
+                log::info!(
+                    "Task {} moved from {:?} to {:?}",
+                    task.id,
+                    task.planned_start,
+                    planned.planned_start
+                );
+                replanned += 1;
+            }
+        }
+
+        Ok::<_, diesel::result::Error>(replanned)
+    })
+    .await
+    .map_err(|e| ScheduleError::Database(e.to_string()))?
+    .map_err(|e| ScheduleError::Database(e.to_string()))
+}
+
+// Tasca en segon pla: engega les tasques quan arriba el seu bloc i tanca les acabades
+pub async fn run_task_executor(pool: DbPool, notifier: Arc<dyn PushNotifier>, hub: Arc<WsHub>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(TICK_SECONDS));
+    loop {
+        interval.tick().await;
+        match execute_due_tasks(&pool).await {
+            Ok(queued) => {
+                for command in &queued {
+                    command_processor::dispatch_command(&pool, notifier.as_ref(), hub.as_ref(), command)
+                        .await;
+                }
+            }
+            Err(e) => log::error!("Task executor failed: {}", e),
+        }
+    }
+}
+
+// Retorna les comandes d'engegada encuades en aquesta passada
+pub async fn execute_due_tasks(pool: &DbPool) -> Result<Vec<Command>, ScheduleError> {
+    let conn = pool
+        .get()
+        .await
+        .map_err(|e| ScheduleError::Database(e.to_string()))?;
+
+    conn.interact(|conn| {
+        let now = Utc::now();
+        let tasks = one_shot_tasks::table
+            .filter(one_shot_tasks::status.eq_any([
+                TaskStatus::Waiting.as_str(),
+                TaskStatus::Scheduled.as_str(),
+                TaskStatus::Started.as_str(),
+            ]))
+            .load::<OneShotTask>(conn)?;
+
+        let mut queued = Vec::new();
+        for task in tasks {
+            let task_id = task.id;
+            match process_task(conn, task, now) {
+                Ok(Some(command)) => queued.push(command),
+                Ok(None) => {}
+                Err(e) => log::error!("Failed to execute task {}: {}", task_id, e),
+            }
+        }
+
+        Ok::<_, diesel::result::Error>(queued)
+    })
+    .await
+    .map_err(|e| ScheduleError::Database(e.to_string()))?
+    .map_err(|e| ScheduleError::Database(e.to_string()))
+}
+
+fn process_task(
+    conn: &mut PgConnection,
+    task: OneShotTask,
+    now: DateTime<Utc>,
+) -> Result<Option<Command>, diesel::result::Error> {
+    let planned_end = task.planned_start.map(|start| start + task.duration());
+
+    match task.status.as_str() {
+        // Sense preus a temps, o un bloc que ja ha passat sencer (servidor aturat)
+        "waiting" if now > task.latest_start() => {
+            finish_task(conn, &task, TaskStatus::Expired, "No prices available before the deadline")
+                .map(|_| None)
+        }
+        "waiting" => plan_task(conn, &task, now).map(|_| None),
+        "scheduled" if planned_end.is_some_and(|end| now >= end) => {
+            finish_task(conn, &task, TaskStatus::Expired, "The planned block was missed").map(|_| None)
+        }
+        "scheduled" if task.planned_start.is_some_and(|start| start <= now) => start_task(conn, &task),
+        // Un cop acabat el cicle la tasca caduca
+        "started" if planned_end.is_none_or(|end| now >= end) => {
+            finish_task(conn, &task, TaskStatus::Completed, "Task finished").map(|_| None)
+        }
+        _ => Ok(None),
+    }
+}
+
+fn start_task(conn: &mut PgConnection, task: &OneShotTask) -> Result<Option<Command>, diesel::result::Error> {
+    conn.transaction(|conn| {
+        // L'actualització condicional garanteix que la tasca només s'engega una vegada
+        let claimed = diesel::update(
+            one_shot_tasks::table
+                .find(task.id)
+                .filter(one_shot_tasks::status.eq(TaskStatus::Scheduled.as_str())),
+        )
+        .set((
+            one_shot_tasks::status.eq(TaskStatus::Started.as_str()),
+            one_shot_tasks::updated_at.eq(Utc::now()),
+        ))
+        .execute(conn)?;
+
+        if claimed == 0 {
+            return Ok(None);
+        }
+
+        let new_command = NewCommand {
+            id: Uuid::new_v4(),
+            user_id: task.user_id,
+            device_id: task.device_id,
+            command_type: "on_off".to_string(),
+            payload_json: serde_json::to_value(OnOffPayload { on: true }).unwrap_or_else(|_| json!({})),
+            status: CommandStatus::Queued.to_string(),
+            retry_count: 0,
+            schedule_id: None,
+        };
+        let command = command_processor::insert_command(conn, &new_command, "Fired by one-shot task")?;
+
+        diesel::update(one_shot_tasks::table.find(task.id))
+            .set(one_shot_tasks::command_id.eq(command.id))
+            .execute(conn)?;
+
+        diesel::insert_into(automation_logs::table)
+            .values(&NewAutomationLog {
+                id: Uuid::new_v4(),
+                user_id: task.user_id,
+                device_id: Some(task.device_id),
+                rule_id: None,
+                action: "task_started".to_string(),
+                details_json: Some(json!({
+                    "task_id": task.id,
+                    "planned_start": task.planned_start,
+                    "duration_minutes": task.duration_minutes,
+                    "planned_cost": task.planned_cost,
+                    "command_id": command.id,
+                })),
+            })
+            .execute(conn)?;
+
+        log::info!("Task {} started device {}", task.id, task.device_id);
+
+        Ok(Some(command))
+    })
+}
+
+fn finish_task(
+    conn: &mut PgConnection,
+    task: &OneShotTask,
+    status: TaskStatus,
+    reason: &str,
+) -> Result<(), diesel::result::Error> {
+    conn.transaction(|conn| {
+        let finished = diesel::update(
+            one_shot_tasks::table
+                .find(task.id)
+                .filter(one_shot_tasks::status.eq(&task.status)),
+        )
+        .set((
+            one_shot_tasks::status.eq(status.as_str()),
+            one_shot_tasks::updated_at.eq(Utc::now()),
+        ))
+        .execute(conn)?;
+
+        if finished == 0 {
+            return Ok(());
+        }
+
+        diesel::insert_into(automation_logs::table)
+            .values(&NewAutomationLog {
+                id: Uuid::new_v4(),
+                user_id: task.user_id,
+                device_id: Some(task.device_id),
+                rule_id: None,
+                action: format!("task_{}", status),
+                details_json: Some(json!({
+                    "task_id": task.id,
+                    "reason": reason,
+                })),
+            })
+            .execute(conn)?;
+
+        log::info!("Task {} {}: {}", task.id, status, reason);
+
+        Ok(())
+    })
+}
@@ -0,0 +1,229 @@
+use crate::{
+    models::{
+        command::{Command, CommandResult},
+        tariff::PriceSeries,
+    },
+    services::schedule_builder::RebuildResult,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::{collections::HashMap, sync::Mutex};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+// Esdeveniments que el servidor envia pel WebSocket
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsEvent {
+    Connected {
+        user_id: Uuid,
+    },
+    Pong,
+    Error {
+        message: String,
+    },
+    DeviceState {
+        device_id: Uuid,
+        state: JsonValue,
+    },
+    CommandStatus {
+        command_id: Uuid,
+        device_id: Uuid,
+        status: String,
+        retry_count: i32,
+        error_message: Option<String>,
+    },
+    SchedulesRebuilt {
+        dates: Vec<NaiveDate>,
+        schedules: usize,
+        failures: usize,
+    },
+    DayPrices {
+        date: NaiveDate,
+        tariff: String,
+    },
+    // Comença (o està a punt de començar) un tram de preus barats o cars
+    PriceAlert {
+        tier: String,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        message: String,
+    },
+    // Comanda que l'app ha d'executar (només a connexions subscrites a comandes)
+    Command {
+        command: Command,
+    },
+    CommandRejected {
+        command_id: Uuid,
+        message: String,
+    },
+}
+
+impl WsEvent {
+    pub fn command(command: &Command) -> Self {
+        WsEvent::CommandStatus {
+            command_id: command.id,
+            device_id: command.device_id,
+            status: command.status.clone(),
+            retry_count: command.retry_count,
+            error_message: command.error_message.clone(),
+        }
+    }
+
+    pub fn day_prices(date: NaiveDate, series: PriceSeries) -> Self {
+        WsEvent::DayPrices {
+            date,
+            tariff: series.as_str().to_string(),
+        }
+    }
+
+    pub fn schedules_rebuilt(result: &RebuildResult) -> Self {
+        let mut dates: Vec<NaiveDate> = result
+            .schedules
+            .iter()
+            .map(|s| s.date)
+            .chain(result.failures.iter().map(|f| f.date))
+            .collect();
+        dates.sort();
+        dates.dedup();
+
+        WsEvent::SchedulesRebuilt {
+            dates,
+            schedules: result.schedules.len(),
+            failures: result.failures.len(),
+        }
+    }
+}
+
+// Missatges que el client pot enviar
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Auth { token: String },
+    Ping,
+    // L'app mòbil vol rebre les comandes per aquesta connexió en lloc del heartbeat
+    SubscribeCommands,
+    CommandResult(CommandResult),
+}
+
+struct Connection {
+    tx: mpsc::UnboundedSender<String>,
+    commands: bool,
+}
+
+// Registre de connexions WebSocket obertes per usuari
+#[derive(Default)]
+pub struct WsHub {
+    sessions: Mutex<HashMap<Uuid, HashMap<Uuid, Connection>>>,
+}
+
+impl WsHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Registra una connexió i retorna el seu id i el canal pel qual rebrà els esdeveniments
+    pub fn register(&self, user_id: Uuid) -> (Uuid, mpsc::UnboundedReceiver<String>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let connection_id = Uuid::new_v4();
+
+        if let Ok(mut sessions) = self.sessions.lock() {
+            sessions
+                .entry(user_id)
+                .or_default()
+                .insert(connection_id, Connection { tx, commands: false });
+        }
+
+        (connection_id, rx)
+    }
+
+    pub fn unregister(&self, user_id: Uuid, connection_id: Uuid) {
+        if let Ok(mut sessions) = self.sessions.lock() {
+            if let Some(connections) = sessions.get_mut(&user_id) {
+                connections.remove(&connection_id);
+                if connections.is_empty() {
+                    sessions.remove(&user_id);
+                }
+            }
+        }
+    }
+
+    pub fn subscribe_commands(&self, user_id: Uuid, connection_id: Uuid) {
+        if let Ok(mut sessions) = self.sessions.lock() {
+            if let Some(connection) = sessions
+                .get_mut(&user_id)
+                .and_then(|connections| connections.get_mut(&connection_id))
+            {
+                connection.commands = true;
+            }
+        }
+    }
+
+    // Indica si l'usuari té alguna connexió que accepta comandes
+    pub fn has_command_transport(&self, user_id: Uuid) -> bool {
+        self.sessions
+            .lock()
+            .ok()
+            .and_then(|sessions| {
+                sessions
+                    .get(&user_id)
+                    .map(|connections| connections.values().any(|c| c.commands && !c.tx.is_closed()))
+            })
+            .unwrap_or(false)
+    }
+
+    // Envia una comanda a una sola connexió subscrita de l'usuari.
+    // Retorna false si no n'hi ha cap d'oberta.
+    pub fn send_command(&self, command: &Command) -> bool {
+        let event = WsEvent::Command {
+            command: command.clone(),
+        };
+        let Ok(text) = serde_json::to_string(&event) else {
+            return false;
+        };
+
+        let Ok(sessions) = self.sessions.lock() else {
+            return false;
+        };
+        sessions
+            .get(&command.user_id)
+            .and_then(|connections| {
+                connections
+                    .values()
+                    .find(|c| c.commands && c.tx.send(text.clone()).is_ok())
+            })
+            .is_some()
+    }
+
+    // Envia un esdeveniment a totes les connexions de l'usuari
+    pub fn send_to_user(&self, user_id: Uuid, event: &WsEvent) {
+        let Ok(text) = serde_json::to_string(event) else {
+            return;
+        };
+
+        if let Ok(mut sessions) = self.sessions.lock() {
+            if let Some(connections) = sessions.get_mut(&user_id) {
+                // Les connexions tancades es netegen aquí mateix
+                connections.retain(|_, c| c.tx.send(text.clone()).is_ok());
+                if connections.is_empty() {
+                    sessions.remove(&user_id);
+                }
+            }
+        }
+    }
+
+    // Envia un esdeveniment a tots els usuaris connectats
+    pub fn broadcast(&self, event: &WsEvent) {
+        let Ok(text) = serde_json::to_string(event) else {
+            return;
+        };
+
+        if let Ok(mut sessions) = self.sessions.lock() {
+            for connections in sessions.values_mut() {
+                connections.retain(|_, c| c.tx.send(text.clone()).is_ok());
+            }
+            sessions.retain(|_, connections| !connections.is_empty());
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use actix_web::{error::JsonPayloadError, http::StatusCode, HttpRequest, HttpResponse, ResponseError};
+use serde::Serialize;
+use serde_json::json;
+use std::fmt;
+
+// Error d'un camp concret de la petició
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+// Errors de validació acumulats. Es retornen com a 422 amb el cos
+// `{"error": "Validation failed", "fields": [{"field": ..., "message": ...}]}`.
+#[derive(Debug, Clone, Default)]
+pub struct ValidationErrors {
+    pub fields: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn single(field: impl Into<String>, message: impl Into<String>) -> Self {
+        let mut errors = Self::new();
+        errors.add(field, message);
+        errors
+    }
+
+    pub fn add(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.fields.push(FieldError {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    // Ok(value) si no hi ha cap error
+    pub fn into_result<T>(self, value: T) -> Result<T, Self> {
+        if self.is_empty() {
+            Ok(value)
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let messages: Vec<String> = self
+            .fields
+            .iter()
+            .map(|e| format!("{}: {}", e.field, e.message))
+            .collect();
+        write!(f, "{}", messages.join("; "))
+    }
+}
+
+impl ResponseError for ValidationErrors {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::UNPROCESSABLE_ENTITY
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(json!({
+            "error": "Validation failed",
+            "fields": self.fields
+        }))
+    }
+}
+
+// Gestor d'errors de `web::Json`: un cos mal format o amb camps desconeguts es
+// retorna com a error de validació. La resta d'errors (mida, content type) no canvien.
+pub fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    match err {
+        JsonPayloadError::Deserialize(e) => {
+            let message = e.to_string();
+            let field = unknown_field(&message).unwrap_or("body").to_string();
+            ValidationErrors::single(field, message).into()
+        }
+        other => other.into(),
+    }
+}
+
+// Nom del camp d'un error "unknown field `x`" de serde
+fn unknown_field(message: &str) -> Option<&str> {
+    let rest = message.strip_prefix("unknown field `")?;
+    rest.split('`').next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    #[allow(dead_code)]
+    struct Body {
+        name: String,
+    }
+
+    fn handle(body: &str) -> HttpResponse {
+        let err = serde_json::from_str::<Body>(body).unwrap_err();
+        let req = TestRequest::default().to_http_request();
+        json_error_handler(JsonPayloadError::Deserialize(err), &req).error_response()
+    }
+
+    async fn fields(response: HttpResponse) -> Vec<String> {
+        let body = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        json["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["field"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn unknown_fields_are_reported_by_name() {
+        let response = handle(r#"{"name": "boiler", "colour": "red"}"#);
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(fields(response).await, vec!["colour"]);
+    }
+
+    #[tokio::test]
+    async fn malformed_json_is_a_validation_error_on_the_body() {
+        for body in [r#"{"name": "#, r#"{"name": 3}"#, "{}"] {
+            let response = handle(body);
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(fields(response).await, vec!["body"]);
+        }
+    }
+
+    #[test]
+    fn other_payload_errors_keep_their_status() {
+        let req = TestRequest::default().to_http_request();
+        let response = json_error_handler(JsonPayloadError::Overflow { limit: 16 }, &req).error_response();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -2,4 +2,4 @@
 // TODO: Afegir utilitats per:
 // - Encryption/Decryption
 // - Time utilities
-// - Error handling helpers
+pub mod errors;